@@ -0,0 +1,35 @@
+// Works out the build date for the boot banner. `SOURCE_DATE_EPOCH` takes precedence
+// over the current time, so reproducible builds get a fixed date.
+// We don't print any `rerun-if` lines, so cargo reruns this whenever any file in
+// the package changes - which keeps the date up to date.
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let seconds = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0)
+        });
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    println!("cargo:rustc-env=BOREDOS_BUILD_DATE={:04}-{:02}-{:02}", year, month, day);
+}
+
+// Days since 1970-01-01 to a (year, month, day) date, using Howard Hinnant's
+// `civil_from_days` algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,225 @@
+// Just enough of ACPI to find the interrupt controllers. The firmware leaves a
+// pointer (the RSDP) somewhere in the BIOS area, which leads to a list of tables, one
+// of which is the MADT: what APICs there are and how the ISA IRQs map onto the I/O
+// APIC's inputs (its global system interrupts, or GSIs).
+//
+// Everything is read through the bootloader's mapping of physical memory, and only
+// once, by `init`. What there was is kept in `MADT`, for `madt` to hand out.
+
+use core::mem::size_of;
+use spin::Once;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+// Where the BIOS keeps the segment of the extended BIOS data area
+const EBDA_SEGMENT: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe0000;
+const BIOS_AREA_END: u64 = 0x100000;
+// The RSDP is always on a 16 byte boundary
+const RSDP_ALIGN: u64 = 16;
+// Only the first KiB of the EBDA is searched
+const EBDA_SEARCH_LEN: u64 = 1024;
+
+// MADT entry types
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+
+// More than any PC has, and it means we don't need a heap
+const MAX_IO_APICS: usize = 8;
+const MAX_OVERRIDES: usize = 16;
+
+static MADT: Once<Madt> = Once::new();
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // From here on only in ACPI 2.0 and later (revision 2)
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+// The start of every table
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: u32,
+    // The first GSI this I/O APIC takes
+    pub gsi_base: u32,
+}
+
+// An ISA IRQ that isn't on the GSI of the same number, or isn't edge triggered and
+// active high like ISA IRQs usually are. The timer is nearly always on GSI 2.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    // Polarity in bits 0-1 and trigger mode in bits 2-3, 0 meaning the bus's default
+    pub flags: u16,
+}
+
+pub struct Madt {
+    pub local_apic_address: u32,
+    io_apics: [Option<IoApicEntry>; MAX_IO_APICS],
+    overrides: [Option<InterruptOverride>; MAX_OVERRIDES],
+}
+
+impl Madt {
+    pub fn io_apics(&self) -> impl Iterator<Item = &IoApicEntry> {
+        self.io_apics.iter().flatten()
+    }
+
+    pub fn interrupt_override(&self, irq: u8) -> Option<&InterruptOverride> {
+        self.overrides.iter().flatten().find(|entry| entry.irq == irq)
+    }
+}
+
+// Looks for the MADT, and says whether there was one
+pub fn init(physical_memory_offset: u64) -> bool {
+    let madt = unsafe { find_madt(physical_memory_offset) };
+    match madt {
+        Some(madt) => {
+            MADT.call_once(|| madt);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn madt() -> Option<&'static Madt> {
+    MADT.r#try()
+}
+
+// Unsafe since it reads whatever the firmware left around, through the mapping of
+// physical memory at `offset`
+unsafe fn find_madt(offset: u64) -> Option<Madt> {
+    let rsdp = find_rsdp(offset)?;
+    let rsdp = &*((offset + rsdp) as *const Rsdp);
+    // The XSDT has 64-bit pointers to tables, the RSDT 32-bit ones
+    let (table, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (rsdp.rsdt_address as u64, 4)
+    };
+    let header = table_at(offset, table)?;
+    let entries = (header.length as usize - size_of::<SdtHeader>()) / entry_size;
+    let first = offset + table + size_of::<SdtHeader>() as u64;
+    for index in 0..entries {
+        let pointer = first + (index * entry_size) as u64;
+        let address = if entry_size == 8 {
+            (pointer as *const u64).read_unaligned()
+        } else {
+            (pointer as *const u32).read_unaligned() as u64
+        };
+        match table_at(offset, address) {
+            Some(table) if &table.signature == MADT_SIGNATURE => {
+                return Some(parse_madt(offset + address, table.length as usize))
+            }
+            _ => continue,
+        }
+    }
+    None
+}
+
+// The physical address of the RSDP: in the first KiB of the EBDA, or somewhere in
+// the BIOS area just below 1 MiB
+unsafe fn find_rsdp(offset: u64) -> Option<u64> {
+    let ebda = ((offset + EBDA_SEGMENT) as *const u16).read_unaligned() as u64 * 16;
+    let areas = [(ebda, ebda + EBDA_SEARCH_LEN), (BIOS_AREA_START, BIOS_AREA_END)];
+    for (start, end) in areas {
+        if start == 0 {
+            continue;
+        }
+        let mut address = start;
+        while address + size_of::<Rsdp>() as u64 <= end {
+            let candidate = (offset + address) as *const [u8; 8];
+            // The first 20 bytes are ACPI 1.0's RSDP, which is all the checksum covers
+            if &*candidate == RSDP_SIGNATURE && checksum(offset + address, 20) {
+                return Some(address);
+            }
+            address += RSDP_ALIGN;
+        }
+    }
+    None
+}
+
+// The table at `address`, if its checksum says it's really one
+unsafe fn table_at(offset: u64, address: u64) -> Option<&'static SdtHeader> {
+    if address == 0 {
+        return None;
+    }
+    let header = &*((offset + address) as *const SdtHeader);
+    if (header.length as usize) < size_of::<SdtHeader>() || !checksum(offset + address, header.length as usize) {
+        return None;
+    }
+    Some(header)
+}
+
+// ACPI's checksums make all the bytes add up to 0
+unsafe fn checksum(address: u64, len: usize) -> bool {
+    let bytes = core::slice::from_raw_parts(address as *const u8, len);
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+// After the header: the local APIC address (a u32), flags (a u32), and then entries
+// that each start with their type and length (a u8 each)
+unsafe fn parse_madt(address: u64, len: usize) -> Madt {
+    let bytes = core::slice::from_raw_parts(address as *const u8, len);
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+
+    let header_len = size_of::<SdtHeader>();
+    let mut madt = Madt {
+        local_apic_address: u32_at(header_len),
+        io_apics: [None; MAX_IO_APICS],
+        overrides: [None; MAX_OVERRIDES],
+    };
+    let mut io_apics = 0;
+    let mut overrides = 0;
+    let mut at = header_len + 8;
+    while at + 2 <= len {
+        let (kind, entry_len) = (bytes[at], bytes[at + 1] as usize);
+        if entry_len < 2 || at + entry_len > len {
+            break;
+        }
+        match kind {
+            ENTRY_IO_APIC if entry_len >= 12 && io_apics < MAX_IO_APICS => {
+                madt.io_apics[io_apics] = Some(IoApicEntry {
+                    id: bytes[at + 2],
+                    address: u32_at(at + 4),
+                    gsi_base: u32_at(at + 8),
+                });
+                io_apics += 1;
+            }
+            ENTRY_INTERRUPT_OVERRIDE if entry_len >= 10 && overrides < MAX_OVERRIDES => {
+                madt.overrides[overrides] = Some(InterruptOverride {
+                    irq: bytes[at + 3],
+                    gsi: u32_at(at + 4),
+                    flags: u16_at(at + 8),
+                });
+                overrides += 1;
+            }
+            _ => {}
+        }
+        at += entry_len;
+    }
+    madt
+}
@@ -0,0 +1,124 @@
+// The kernel heap, which is what `Box`, `Vec`, `String` and the rest of `alloc` get
+// their memory from. It's `config::HEAP_SIZE` bytes at `config::HEAP_START`, mapped
+// to frames from `memory::frames` by `init_heap`, and handed out by the global
+// allocator below: a `FixedSizeBlockAllocator` (see `fixed_size_block`), or with the
+// `bump-heap` feature a `BumpAllocator` (see `bump`).
+//
+// Objects that come and go all the time can skip the heap altogether, and get a
+// `SlabCache` of their own (see `slab`).
+//
+// The allocator is behind an `IrqSafeMutex` like everything else, since interrupt
+// handlers might allocate too. They shouldn't, though: anything too big for a block
+// has the lock held for a whole search of the free list.
+
+use core::alloc::{GlobalAlloc, Layout};
+use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+use crate::config;
+use crate::memory::{self, frames::KernelFrames, MappingError};
+use crate::sync::{IrqSafeMutex, IrqSafeMutexGuard};
+
+pub mod bump;
+pub mod fixed_size_block;
+pub mod linked_list;
+pub mod slab;
+
+use bump::BumpAllocator;
+use fixed_size_block::FixedSizeBlockAllocator;
+use linked_list::LinkedListAllocator;
+
+#[cfg(not(feature = "bump-heap"))]
+#[global_allocator]
+static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+
+#[cfg(feature = "bump-heap")]
+#[global_allocator]
+static ALLOCATOR: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
+
+// `GlobalAlloc` is a foreign trait, and its methods only get `&self`, so allocators
+// that need `&mut self` are wrapped in one of these
+pub struct Locked<A> {
+    inner: IrqSafeMutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Locked<A> {
+        Locked {
+            inner: IrqSafeMutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BumpAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+// Maps the heap and gives it to the allocator. Nothing can be allocated before this,
+// and it has to come after `memory::init`.
+pub fn init_heap() -> Result<(), MappingError> {
+    let start = Page::containing_address(VirtAddr::new(config::HEAP_START as u64));
+    let end = Page::containing_address(VirtAddr::new((config::HEAP_START + config::HEAP_SIZE - 1) as u64));
+    for page in Page::range_inclusive(start, end) {
+        let frame = KernelFrames.allocate_frame().ok_or(MappingError::OutOfFrames)?;
+        // Safe since the frame is a fresh one, and nothing else goes at `HEAP_START`
+        unsafe { memory::map_to(page, frame, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)? };
+    }
+    // Safe since all of it was just mapped, and it's the only time this happens
+    unsafe { ALLOCATOR.lock().init(config::HEAP_START, config::HEAP_SIZE) };
+    Ok(())
+}
+
+// How much of the heap isn't handed out right now
+pub fn free() -> usize {
+    ALLOCATOR.lock().free()
+}
+
+// How many allocations haven't been given back yet
+#[cfg(feature = "bump-heap")]
+pub fn allocations() -> usize {
+    ALLOCATOR.lock().allocations()
+}
+
+// Starts the bump heap over, e.g. once boot is done with everything it allocated.
+// Unsafe since nothing allocated before can be used or freed anymore.
+#[cfg(feature = "bump-heap")]
+pub unsafe fn reset() {
+    ALLOCATOR.lock().reset();
+}
+
+// Whatever asked for memory can't go on without it
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!("out of heap: {} bytes (aligned to {}) wanted, {} free", layout.size(), layout.align(), free())
+}
@@ -0,0 +1,119 @@
+// About the simplest allocator there is: the heap is used from the bottom up, every
+// allocation just moves `next` past itself, and nothing is given back until every
+// allocation is (or someone calls `reset`), when it all starts over. That's very fast,
+// and fine for memory that's only ever needed for a while, like whatever gets
+// allocated during boot - as long as something that sticks around doesn't keep the
+// whole heap from ever being reused.
+//
+// Build with the `bump-heap` feature to use it for the kernel heap.
+
+use core::alloc::Layout;
+use core::ptr;
+
+pub struct BumpAllocator {
+    start: usize,
+    end: usize,
+    next: usize,
+    // Handed out and not given back yet
+    allocations: usize,
+    // Handed out since the last reset
+    total: usize,
+}
+
+impl BumpAllocator {
+    // With nothing to hand out until `init`
+    pub const fn new() -> BumpAllocator {
+        BumpAllocator {
+            start: 0,
+            end: 0,
+            next: 0,
+            allocations: 0,
+            total: 0,
+        }
+    }
+
+    // Hands out the `size` bytes from `start`. Unsafe since they have to be mapped,
+    // and nothing else can be using them. Only call this once.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.start = start;
+        self.end = start + size;
+        self.next = start;
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    // What's left above `next`, which is all that can be handed out before a reset
+    pub fn free(&self) -> usize {
+        self.end - self.next
+    }
+
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    pub fn total_allocations(&self) -> usize {
+        self.total
+    }
+
+    // Null if there's no room left above `next`
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let start = self.next.next_multiple_of(layout.align());
+        match start.checked_add(layout.size()) {
+            Some(end) if end <= self.end => {
+                self.next = end;
+                self.allocations += 1;
+                self.total += 1;
+                start as *mut u8
+            }
+            _ => ptr::null_mut(),
+        }
+    }
+
+    // Only counts, nothing is given back until the last allocation is
+    pub fn deallocate(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.start;
+        }
+    }
+
+    // Starts handing out the whole heap again. Unsafe since nothing allocated from it
+    // before can be used anymore, or given back either - that would be counted
+    // against what's allocated after.
+    pub unsafe fn reset(&mut self) {
+        self.next = self.start;
+        self.allocations = 0;
+        self.total = 0;
+    }
+}
+
+impl Default for BumpAllocator {
+    fn default() -> BumpAllocator {
+        BumpAllocator::new()
+    }
+}
+
+#[test_case]
+fn test_bump_and_reset() {
+    static mut ARENA: [u64; 128] = [0; 128];
+
+    let mut allocator = BumpAllocator::new();
+    unsafe { allocator.init(&raw mut ARENA as usize, 1024) };
+    let layout = Layout::from_size_align(100, 64).unwrap();
+    let a = allocator.allocate(layout);
+    let b = allocator.allocate(layout);
+    assert_eq!(b as usize % 64, 0);
+    assert!(b as usize >= a as usize + 100);
+    assert_eq!(allocator.allocations(), 2);
+    // Giving back the first doesn't make room, giving back both does
+    allocator.deallocate(a, layout);
+    assert_eq!(allocator.allocate(Layout::from_size_align(1024, 8).unwrap()), ptr::null_mut());
+    allocator.deallocate(b, layout);
+    assert_eq!(allocator.free(), 1024);
+
+    allocator.allocate(layout);
+    unsafe { allocator.reset() };
+    assert_eq!((allocator.allocations(), allocator.total_allocations(), allocator.free()), (0, 0, 1024));
+}
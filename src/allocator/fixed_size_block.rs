@@ -0,0 +1,128 @@
+// The kernel heap's allocator. Most allocations are small, so instead of searching
+// the free list for every one of them, small allocations come in a few fixed sizes
+// (`BLOCK_SIZES`), each with a list of free blocks of its own: one gets popped off
+// the list when it's wanted and pushed back on when it's freed, never merged with
+// anything. A size whose list is empty gets a new block from the fallback, a
+// `LinkedListAllocator`, which also takes everything bigger than the biggest size.
+//
+// Blocks are aligned to their own size, so anything with an alignment up to its
+// (rounded up) size fits in one.
+
+use core::alloc::Layout;
+use core::ptr;
+
+use super::linked_list::LinkedListAllocator;
+
+// Sizes have to be powers of two, see above
+const BLOCK_SIZES: &[usize] = &[16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// At the start of every free block
+struct Block {
+    next: *mut Block,
+}
+
+pub struct FixedSizeBlockAllocator {
+    lists: [*mut Block; BLOCK_SIZES.len()],
+    // How many blocks are on each list
+    cached: [usize; BLOCK_SIZES.len()],
+    fallback: LinkedListAllocator,
+}
+
+// The blocks are only ever reached through the allocator
+unsafe impl Send for FixedSizeBlockAllocator {}
+
+impl FixedSizeBlockAllocator {
+    // With nothing to hand out until `init`
+    pub const fn new() -> FixedSizeBlockAllocator {
+        FixedSizeBlockAllocator {
+            lists: [ptr::null_mut(); BLOCK_SIZES.len()],
+            cached: [0; BLOCK_SIZES.len()],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    // Hands out the `size` bytes from `start`. Unsafe since they have to be mapped,
+    // and nothing else can be using them. Only call this once.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.fallback.init(start, size);
+    }
+
+    pub fn size(&self) -> usize {
+        self.fallback.size()
+    }
+
+    // Free blocks count as free, though they can only be handed out at their size
+    pub fn free(&self) -> usize {
+        let cached: usize = BLOCK_SIZES.iter().zip(self.cached).map(|(size, count)| size * count).sum();
+        self.fallback.free() + cached
+    }
+
+    // Null if there's no room left
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let index = match list_index(&layout) {
+            Some(index) => index,
+            None => return self.fallback.allocate(layout),
+        };
+        let block = self.lists[index];
+        if block.is_null() {
+            let size = BLOCK_SIZES[index];
+            // Safe since the size is a non-zero power of two, and so a fine alignment
+            let layout = unsafe { Layout::from_size_align_unchecked(size, size) };
+            return self.fallback.allocate(layout);
+        }
+        // Safe since everything on the lists is a free block we wrote
+        self.lists[index] = unsafe { (*block).next };
+        self.cached[index] -= 1;
+        block as *mut u8
+    }
+
+    // Unsafe since `ptr` has to be from `allocate` with the same `layout`, and
+    // nothing can be using it anymore
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let index = match list_index(&layout) {
+            Some(index) => index,
+            None => return self.fallback.deallocate(ptr, layout),
+        };
+        // Every block is big and aligned enough for a `Block`, the smallest is 16
+        let block = ptr as *mut Block;
+        block.write(Block {
+            next: self.lists[index],
+        });
+        self.lists[index] = block;
+        self.cached[index] += 1;
+    }
+}
+
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> FixedSizeBlockAllocator {
+        FixedSizeBlockAllocator::new()
+    }
+}
+
+// Which size `layout` goes in, or None if it's too big for any of them
+fn list_index(layout: &Layout) -> Option<usize> {
+    let wanted = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&size| size >= wanted)
+}
+
+#[test_case]
+fn test_blocks_are_reused() {
+    static mut ARENA: [u64; 1024] = [0; 1024];
+
+    let mut allocator = FixedSizeBlockAllocator::new();
+    unsafe { allocator.init(&raw mut ARENA as usize, 8192) };
+    let small = Layout::from_size_align(20, 4).unwrap();
+    let a = allocator.allocate(small);
+    assert_eq!(a as usize % 32, 0);
+    unsafe { allocator.deallocate(a, small) };
+    // Straight back off the list, for anything else of that size too
+    assert_eq!(allocator.allocate(Layout::from_size_align(32, 32).unwrap()), a);
+
+    // Too big for a block
+    let big = Layout::from_size_align(4096, 8).unwrap();
+    let b = allocator.allocate(big);
+    assert!(!b.is_null());
+    let free = allocator.free();
+    unsafe { allocator.deallocate(b, big) };
+    assert_eq!(allocator.free(), free + 4096);
+}
@@ -0,0 +1,169 @@
+// A first-fit allocator that keeps the free parts of the heap (holes) on a list,
+// written into the holes themselves, in address order. Allocating walks the list for
+// the first hole that fits and takes what it needs out of it, and freeing puts the
+// memory back where it goes in the list, merged with the holes on either side so the
+// heap doesn't end up in crumbs.
+//
+// Every allocation is rounded up to a multiple of `Hole`'s size and alignment, so
+// whatever's left of a hole is always big enough to be one again.
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr;
+
+// At the start of every hole
+struct Hole {
+    size: usize,
+    next: *mut Hole,
+}
+
+const MIN_SIZE: usize = size_of::<Hole>();
+const MIN_ALIGN: usize = align_of::<Hole>();
+
+pub struct LinkedListAllocator {
+    first: *mut Hole,
+    size: usize,
+    free: usize,
+}
+
+// The holes are only ever reached through the allocator
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    // With nothing to hand out until `init`
+    pub const fn new() -> LinkedListAllocator {
+        LinkedListAllocator {
+            first: ptr::null_mut(),
+            size: 0,
+            free: 0,
+        }
+    }
+
+    // Hands out the `size` bytes from `start`. Unsafe since they have to be mapped,
+    // and nothing else can be using them. Only call this once.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        let aligned = start.next_multiple_of(MIN_ALIGN);
+        let size = (size - (aligned - start)) / MIN_SIZE * MIN_SIZE;
+        self.size = size;
+        self.deallocate_region(aligned, size);
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    // Null if there's no hole big enough
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = adjust(layout);
+        let mut link: *mut *mut Hole = &mut self.first;
+        // Safe since every hole on the list is ours, and was written by us
+        unsafe {
+            while !(*link).is_null() {
+                let hole = *link;
+                let start = hole as usize;
+                let end = start + (*hole).size;
+                let mut allocation = start.next_multiple_of(align);
+                // Whatever's left in front has to be a hole of its own
+                if allocation != start && allocation - start < MIN_SIZE {
+                    allocation = (start + MIN_SIZE).next_multiple_of(align);
+                }
+                match allocation.checked_add(size) {
+                    // And so does whatever's left after
+                    Some(allocation_end) if allocation_end == end || allocation_end + MIN_SIZE <= end => {
+                        let mut next = (*hole).next;
+                        if allocation_end != end {
+                            next = write_hole(allocation_end, end - allocation_end, next);
+                        }
+                        if allocation != start {
+                            next = write_hole(start, allocation - start, next);
+                        }
+                        *link = next;
+                        self.free -= size;
+                        return allocation as *mut u8;
+                    }
+                    _ => link = &mut (*hole).next,
+                }
+            }
+        }
+        ptr::null_mut()
+    }
+
+    // Unsafe since `ptr` has to be from `allocate` with the same `layout`, and
+    // nothing can be using it anymore
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = adjust(layout);
+        self.deallocate_region(ptr as usize, size);
+    }
+
+    unsafe fn deallocate_region(&mut self, start: usize, size: usize) {
+        self.free += size;
+        let end = start + size;
+        // Find the holes either side of the one going back
+        let mut previous: *mut Hole = ptr::null_mut();
+        let mut link: *mut *mut Hole = &mut self.first;
+        while !(*link).is_null() && (*link as usize) < start {
+            previous = *link;
+            link = &mut (*previous).next;
+        }
+        let next = *link;
+
+        let (mut size, mut after) = (size, next);
+        if !next.is_null() && next as usize == end {
+            size += (*next).size;
+            after = (*next).next;
+        }
+        if !previous.is_null() && previous as usize + (*previous).size == start {
+            (*previous).size += size;
+            (*previous).next = after;
+        } else {
+            *link = write_hole(start, size, after);
+        }
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> LinkedListAllocator {
+        LinkedListAllocator::new()
+    }
+}
+
+// What an allocation really takes, so any leftovers can always be holes
+fn adjust(layout: Layout) -> (usize, usize) {
+    let size = layout.size().max(MIN_SIZE).next_multiple_of(MIN_SIZE);
+    (size, layout.align().max(MIN_ALIGN))
+}
+
+unsafe fn write_hole(start: usize, size: usize, next: *mut Hole) -> *mut Hole {
+    let hole = start as *mut Hole;
+    hole.write(Hole { size, next });
+    hole
+}
+
+#[test_case]
+fn test_allocate_and_merge() {
+    static mut ARENA: [u64; 512] = [0; 512];
+
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(&raw mut ARENA as usize, 4096) };
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let aligned = Layout::from_size_align(100, 256).unwrap();
+    let a = allocator.allocate(small);
+    let b = allocator.allocate(aligned);
+    let c = allocator.allocate(small);
+    assert!(!a.is_null() && !b.is_null() && !c.is_null());
+    assert_eq!(b as usize % 256, 0);
+    // Too big once anything's taken
+    assert!(allocator.allocate(Layout::from_size_align(4096, 8).unwrap()).is_null());
+    unsafe {
+        allocator.deallocate(b, aligned);
+        allocator.deallocate(a, small);
+        allocator.deallocate(c, small);
+    }
+    // All back in one piece
+    assert_eq!(allocator.free(), allocator.size());
+    assert!(!allocator.allocate(Layout::from_size_align(4096, 8).unwrap()).is_null());
+}
@@ -0,0 +1,351 @@
+// Caches for objects the kernel makes and throws away all the time. A `SlabCache<T>`
+// only ever hands out `T`s, from slabs: a frame or a few straight from
+// `memory::frames`, with a header at the start and the rest cut up into slots the
+// size of a `T`. Free slots are on a list in their slab, so taking one or putting it
+// back is a pop or a push, and since slabs are aligned to their own size, the slab
+// an object is in is just its address rounded down.
+//
+// Slabs with free slots are on the cache's `partial` list. Once a slab's last object
+// is given back its frames go back too, except for one spare kept for the next time
+// the cache runs dry, so an object going back and forth doesn't cost a slab each time.
+//
+// Objects come wrapped in a `SlabBox`, which gives them back when it's dropped:
+//     static WAIT_NODES: SlabCache<WaitNode> = SlabCache::new("wait nodes");
+//     let node = WAIT_NODES.alloc(WaitNode::new()).expect("out of memory");
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use x86_64::structures::paging::PhysFrame;
+use x86_64::PhysAddr;
+
+use crate::memory::{self, frames, regions::Size};
+use crate::sync::IrqSafeMutex;
+
+const FRAME_SIZE: usize = 4096;
+// Slabs are made big enough for at least this many objects
+const MIN_OBJECTS: usize = 8;
+
+// At the start of every slab
+struct Slab {
+    next: *mut Slab,
+    prev: *mut Slab,
+    free: *mut Slot,
+    in_use: usize,
+}
+
+// A free slot
+struct Slot {
+    next: *mut Slot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SlabStats {
+    pub slabs: usize,
+    pub in_use: usize,
+    pub free: usize,
+    pub allocations: u64,
+    pub frees: u64,
+    // Allocations that found no frames for a new slab
+    pub failures: u64,
+}
+
+pub struct SlabCache<T> {
+    name: &'static str,
+    slabs: IrqSafeMutex<Slabs>,
+    _objects: PhantomData<T>,
+}
+
+// Objects go wherever whoever allocated them sends them
+unsafe impl<T: Send> Sync for SlabCache<T> {}
+
+impl<T> SlabCache<T> {
+    pub const fn new(name: &'static str) -> SlabCache<T> {
+        let align = max(align_of::<T>(), align_of::<Slot>());
+        let object_size = max(size_of::<T>(), size_of::<Slot>()).next_multiple_of(align);
+        let first_object = size_of::<Slab>().next_multiple_of(align);
+        let frames = (first_object + MIN_OBJECTS * object_size).div_ceil(FRAME_SIZE).next_power_of_two();
+        SlabCache {
+            name,
+            slabs: IrqSafeMutex::new(Slabs {
+                object_size,
+                first_object,
+                objects: (frames * FRAME_SIZE - first_object) / object_size,
+                frames,
+                partial: ptr::null_mut(),
+                spare: ptr::null_mut(),
+                stats: SlabStats {
+                    slabs: 0,
+                    in_use: 0,
+                    free: 0,
+                    allocations: 0,
+                    frees: 0,
+                    failures: 0,
+                },
+            }),
+            _objects: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // Moves `value` into a slot of its own, or None (with `value` dropped) if there
+    // are no frames left for a new slab
+    pub fn alloc(&self, value: T) -> Option<SlabBox<'_, T>> {
+        let object = NonNull::new(self.slabs.lock().allocate())?.cast::<T>();
+        // Safe since the slot is free, and big and aligned enough for a `T`
+        unsafe { object.as_ptr().write(value) };
+        Some(SlabBox { cache: self, object })
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.slabs.lock().stats
+    }
+
+    // How much memory the cache's slabs take
+    pub fn size(&self) -> usize {
+        let slabs = self.slabs.lock();
+        slabs.stats.slabs * slabs.frames * FRAME_SIZE
+    }
+}
+
+// e.g. "wait nodes: 12 in use, 90 free in 1 slab (4 KiB), 340 allocated, 328 freed"
+impl<T> fmt::Display for SlabCache<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (stats, size) = (self.stats(), self.size());
+        write!(
+            f,
+            "{}: {} in use, {} free in {} slab{} ({}), {} allocated, {} freed",
+            self.name,
+            stats.in_use,
+            stats.free,
+            stats.slabs,
+            if stats.slabs == 1 { "" } else { "s" },
+            Size(size as u64),
+            stats.allocations,
+            stats.frees,
+        )?;
+        if stats.failures > 0 {
+            write!(f, ", {} failed", stats.failures)?;
+        }
+        Ok(())
+    }
+}
+
+// A `T` in a slot of a `SlabCache<T>`, which it goes back to when this is dropped
+pub struct SlabBox<'a, T> {
+    cache: &'a SlabCache<T>,
+    object: NonNull<T>,
+}
+
+// Just like a `Box<T>`
+unsafe impl<T: Send> Send for SlabBox<'_, T> {}
+unsafe impl<T: Sync> Sync for SlabBox<'_, T> {}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safe since the object is ours until we're dropped
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        // Safe since nothing else has the object, and it came from this cache
+        unsafe {
+            ptr::drop_in_place(self.object.as_ptr());
+            self.cache.slabs.lock().deallocate(self.object.as_ptr().cast());
+        }
+    }
+}
+
+// Everything about a cache that doesn't depend on `T`
+struct Slabs {
+    object_size: usize,
+    // Where in a slab the first slot is, after the header
+    first_object: usize,
+    // Slots per slab
+    objects: usize,
+    frames: usize,
+    // Slabs with at least one free slot
+    partial: *mut Slab,
+    // A slab with nothing in use, or null
+    spare: *mut Slab,
+    stats: SlabStats,
+}
+
+// The slabs are only ever reached through the cache
+unsafe impl Send for Slabs {}
+
+impl Slabs {
+    // Null if there's no room for a new slab
+    fn allocate(&mut self) -> *mut u8 {
+        if self.partial.is_null() {
+            let slab = match NonNull::new(self.spare) {
+                Some(spare) => spare.as_ptr(),
+                None => self.new_slab(),
+            };
+            if slab.is_null() {
+                self.stats.failures += 1;
+                return ptr::null_mut();
+            }
+            self.spare = ptr::null_mut();
+            self.push(slab);
+        }
+        // Safe since slabs on the list are ours, and have a free slot
+        unsafe {
+            let slab = self.partial;
+            let slot = (*slab).free;
+            (*slab).free = (*slot).next;
+            (*slab).in_use += 1;
+            if (*slab).free.is_null() {
+                self.remove(slab);
+            }
+            self.stats.allocations += 1;
+            self.stats.in_use += 1;
+            self.stats.free -= 1;
+            slot as *mut u8
+        }
+    }
+
+    // Unsafe since `object` has to be one `allocate` handed out, and not in use
+    unsafe fn deallocate(&mut self, object: *mut u8) {
+        let slab = (object as usize & !(self.frames * FRAME_SIZE - 1)) as *mut Slab;
+        let slot = object as *mut Slot;
+        let was_full = (*slab).free.is_null();
+        slot.write(Slot { next: (*slab).free });
+        (*slab).free = slot;
+        (*slab).in_use -= 1;
+        self.stats.frees += 1;
+        self.stats.in_use -= 1;
+        self.stats.free += 1;
+        if was_full {
+            self.push(slab);
+        }
+        if (*slab).in_use == 0 {
+            self.remove(slab);
+            if self.spare.is_null() {
+                self.spare = slab;
+            } else {
+                self.release(slab);
+            }
+        }
+    }
+
+    // A slab with every slot free, null if there are no frames for one
+    fn new_slab(&mut self) -> *mut Slab {
+        let frame = match frames::allocate_contiguous(self.frames, self.frames) {
+            Some(frame) => frame,
+            None => return ptr::null_mut(),
+        };
+        let start = memory::physical_memory_offset() as usize + frame.start_address().as_u64() as usize;
+        let slab = start as *mut Slab;
+        // Safe since the frames are fresh, and mapped with the rest of physical memory
+        unsafe {
+            let mut free = ptr::null_mut();
+            for index in (0..self.objects).rev() {
+                let slot = (start + self.first_object + index * self.object_size) as *mut Slot;
+                slot.write(Slot { next: free });
+                free = slot;
+            }
+            slab.write(Slab {
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                free,
+                in_use: 0,
+            });
+        }
+        self.stats.slabs += 1;
+        self.stats.free += self.objects;
+        slab
+    }
+
+    unsafe fn release(&mut self, slab: *mut Slab) {
+        let physical = slab as u64 - memory::physical_memory_offset();
+        frames::deallocate_contiguous(PhysFrame::containing_address(PhysAddr::new(physical)), self.frames);
+        self.stats.slabs -= 1;
+        self.stats.free -= self.objects;
+    }
+
+    // Onto the front of `partial`
+    fn push(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).prev = ptr::null_mut();
+            (*slab).next = self.partial;
+            if !self.partial.is_null() {
+                (*self.partial).prev = slab;
+            }
+        }
+        self.partial = slab;
+    }
+
+    // Off `partial`, from wherever it is
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        let (next, prev) = ((*slab).next, (*slab).prev);
+        if prev.is_null() {
+            self.partial = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+#[test_case]
+fn test_slab_cache() {
+    struct Node {
+        value: u64,
+        _links: [usize; 4],
+    }
+    static NODES: SlabCache<Node> = SlabCache::new("test nodes");
+
+    let per_slab = NODES.slabs.lock().objects;
+    let mut nodes = [const { None }; 300];
+    for (index, node) in nodes.iter_mut().enumerate() {
+        *node = NODES.alloc(Node { value: index as u64, _links: [0; 4] });
+    }
+    let stats = NODES.stats();
+    assert_eq!(stats.in_use, 300);
+    assert_eq!(stats.slabs, 300usize.div_ceil(per_slab));
+    assert!(nodes.iter().enumerate().all(|(index, node)| node.as_ref().unwrap().value == index as u64));
+
+    // Every other one back, then the rest: all that's left is the spare
+    for node in nodes.iter_mut().step_by(2) {
+        *node = None;
+    }
+    assert_eq!(NODES.stats().in_use, 150);
+    for node in nodes.iter_mut() {
+        *node = None;
+    }
+    let stats = NODES.stats();
+    assert_eq!((stats.in_use, stats.slabs, stats.free), (0, 1, per_slab));
+    assert_eq!((stats.allocations, stats.frees), (300, 300));
+}
@@ -0,0 +1,169 @@
+// The local APIC, the interrupt controller every CPU core has had since the Pentium.
+// It's what takes interrupts to its own core, with a timer of its own, and the way
+// to more than one CPU later on. The PIC (see `pic`) can only ever talk to the first
+// core.
+//
+// In the original xAPIC mode the registers are memory mapped, 4 KiB at the physical
+// address in the APIC base MSR, which we get at through the bootloader's mapping of
+// physical memory. That mapping isn't uncached the way the APIC's registers should
+// be, which QEMU doesn't mind; real hardware wants a mapping of its own.
+//
+// CPUs with an x2APIC get switched to that instead, where every register is an MSR
+// (from 0x800 up, one per 16 bytes of the old layout) and no mapping is needed at
+// all. APIC IDs are 32 bits there, rather than 8, so machines with more than 255
+// cores can tell them all apart.
+//
+// Enabling the local APIC leaves the PIC working as it was: the BIOS sets up LINT0
+// to pass the PIC's interrupts straight through (virtual wire mode), and those still
+// get their EOI from the PIC. The PIC is only masked once the I/O APIC (see `ioapic`)
+// routes device interrupts instead.
+
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::cpu;
+use crate::cpu::msr;
+
+pub mod timer;
+
+// Above the PIC's 32-47. The spurious vector has to end in 0xf on older APICs. What
+// the APIC sends on it is handled in `interrupts::spurious`.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+// In the APIC base MSR
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS: u64 = 0x000f_ffff_ffff_f000;
+
+// Register offsets from the base
+const ID: usize = 0x20;
+const VERSION: usize = 0x30;
+const TASK_PRIORITY: usize = 0x80;
+const EOI: usize = 0xb0;
+const SPURIOUS: usize = 0xf0;
+pub(crate) const LVT_TIMER: usize = 0x320;
+const LVT_ERROR: usize = 0x370;
+
+// In the spurious interrupt vector register
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+// In any local vector table entry
+pub(crate) const LVT_MASKED: u32 = 1 << 16;
+
+// Where x2APIC mode has the register at offset 0
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+    Disabled,
+    XApic,
+    X2Apic,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Disabled as u8);
+// Where the registers are mapped in xAPIC mode
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+// Whether the CPU has a local APIC at all
+pub fn is_supported() -> bool {
+    cpu::features().apic
+}
+
+// Turns on the local APIC, if there is one, and says whether there was. The timer is
+// left masked until something wants it (see `timer`).
+pub fn init(physical_memory_offset: u64) -> bool {
+    let x2apic = cpu::features().x2apic;
+    let bits = if x2apic {
+        APIC_BASE_ENABLE | APIC_BASE_X2APIC
+    } else {
+        APIC_BASE_ENABLE
+    };
+    // Setting the enable bits only makes it so, the BIOS usually has already set
+    // the first. Going from xAPIC to x2APIC mode is allowed (the other way isn't).
+    // This fails if there's no APIC.
+    let base = match unsafe { msr::APIC_BASE.set_bits(bits) } {
+        Ok(value) => value & APIC_BASE_ADDRESS,
+        Err(_) => return false,
+    };
+    BASE.store(physical_memory_offset + base, Ordering::SeqCst);
+    let mode = if x2apic { Mode::X2Apic } else { Mode::XApic };
+    MODE.store(mode as u8, Ordering::SeqCst);
+
+    unsafe {
+        // Let everything through, nothing here uses priorities
+        write(TASK_PRIORITY, 0);
+        write(LVT_TIMER, LVT_MASKED);
+        write(LVT_ERROR, LVT_MASKED);
+        write(SPURIOUS, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+    true
+}
+
+pub fn mode() -> Mode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => Mode::XApic,
+        2 => Mode::X2Apic,
+        _ => Mode::Disabled,
+    }
+}
+
+pub fn is_enabled() -> bool {
+    mode() != Mode::Disabled
+}
+
+// This core's APIC ID, all 32 bits of it in x2APIC mode and the top 8 otherwise
+pub fn id() -> u32 {
+    let id = unsafe { read(ID) };
+    if mode() == Mode::X2Apic {
+        id
+    } else {
+        id >> 24
+    }
+}
+
+// The APIC's version, and how many LVT entries it has
+pub fn version() -> (u8, u8) {
+    let version = unsafe { read(VERSION) };
+    (version as u8, (version >> 16) as u8 + 1)
+}
+
+// For the end of handlers for interrupts that came through the local APIC (not the
+// PIC's, and never the spurious vector)
+pub fn end_of_interrupt() {
+    unsafe { write(EOI, 0) };
+}
+
+// Register access, only once `init` has enabled the local APIC. Registers are
+// given as their xAPIC offset in either mode. Unsafe because writing most registers
+// changes how interrupts get delivered.
+pub(crate) unsafe fn read(register: usize) -> u32 {
+    match mode() {
+        Mode::X2Apic => msr::rdmsr(x2apic_msr(register)) as u32,
+        Mode::XApic => ((BASE.load(Ordering::Relaxed) as usize + register) as *const u32).read_volatile(),
+        Mode::Disabled => panic!("the local APIC isn't enabled"),
+    }
+}
+
+pub(crate) unsafe fn write(register: usize, value: u32) {
+    match mode() {
+        Mode::X2Apic => msr::wrmsr(x2apic_msr(register), value as u64),
+        Mode::XApic => ((BASE.load(Ordering::Relaxed) as usize + register) as *mut u32).write_volatile(value),
+        Mode::Disabled => panic!("the local APIC isn't enabled"),
+    }
+}
+
+fn x2apic_msr(register: usize) -> u32 {
+    X2APIC_MSR_BASE + (register >> 4) as u32
+}
+
+#[test_case]
+fn test_apic_enabled() {
+    if !is_enabled() {
+        return;
+    }
+    let spurious = unsafe { read(SPURIOUS) };
+    assert_eq!(spurious & 0x1ff, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+    let (_, entries) = version();
+    assert!(entries >= 4);
+    let x2apic = msr::APIC_BASE.read().unwrap() & APIC_BASE_X2APIC != 0;
+    assert_eq!(x2apic, mode() == Mode::X2Apic);
+}
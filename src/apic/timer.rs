@@ -0,0 +1,136 @@
+// The local APIC's own timer, one per CPU, unlike the PIT there's only one of. It
+// counts down from whatever it's given at the bus clock over a divider, and nothing
+// says how fast that is, so `init` times it against the PIT once at boot. After that
+// it can go off once (`one_shot`) or over and over (`periodic`), on `VECTOR`.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::config;
+use crate::pit;
+
+use super::{read, write, LVT_MASKED, LVT_TIMER};
+
+// The first vector after the ISA IRQs
+pub const VECTOR: u8 = 48;
+
+// Register offsets from the APIC's base
+const INITIAL_COUNT: usize = 0x380;
+const CURRENT_COUNT: usize = 0x390;
+const DIVIDE_CONFIGURATION: usize = 0x3e0;
+
+// Divide the bus clock by 16, which leaves plenty of range in a 32-bit count
+const DIVIDE_BY_16: u32 = 0b0011;
+// In the LVT timer entry, one-shot otherwise
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+// How many PIT ticks calibrating takes
+const CALIBRATION_TICKS: u64 = 10;
+
+// 0 until `init` has calibrated the timer
+static TICKS_PER_MILLI: AtomicU32 = AtomicU32::new(0);
+static FIRED: AtomicU64 = AtomicU64::new(0);
+
+// Works out how fast the timer counts, by letting it run for `CALIBRATION_TICKS` of
+// the PIT, and returns the answer in ticks per millisecond. Needs the local APIC
+// enabled, and the PIT running with interrupts on.
+pub fn init() -> u32 {
+    unsafe {
+        write(LVT_TIMER, LVT_MASKED | VECTOR as u32);
+        write(DIVIDE_CONFIGURATION, DIVIDE_BY_16);
+    }
+    // Start right after a PIT tick, so the whole first one counts
+    let start = wait_for_tick(pit::ticks());
+    unsafe { write(INITIAL_COUNT, u32::MAX) };
+    wait_for_tick(start + CALIBRATION_TICKS - 1);
+    let elapsed = u32::MAX - unsafe { read(CURRENT_COUNT) };
+    unsafe { write(INITIAL_COUNT, 0) };
+
+    let millis = CALIBRATION_TICKS * 1000 / config::TIMER_HZ;
+    let ticks_per_milli = (elapsed as u64 / millis).max(1) as u32;
+    TICKS_PER_MILLI.store(ticks_per_milli, Ordering::SeqCst);
+    ticks_per_milli
+}
+
+// Waits for the PIT to get past `ticks`, and says where it got to
+fn wait_for_tick(ticks: u64) -> u64 {
+    loop {
+        let now = pit::ticks();
+        if now > ticks {
+            return now;
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+pub fn ticks_per_milli() -> u32 {
+    TICKS_PER_MILLI.load(Ordering::Relaxed)
+}
+
+// Goes off once, `micros` from now
+pub fn one_shot(micros: u64) {
+    start(micros, 0);
+}
+
+// Goes off every `micros` until `stop`
+pub fn periodic(micros: u64) {
+    start(micros, LVT_TIMER_PERIODIC);
+}
+
+pub fn stop() {
+    unsafe {
+        write(LVT_TIMER, LVT_MASKED | VECTOR as u32);
+        write(INITIAL_COUNT, 0);
+    }
+}
+
+// How many times the timer has gone off on this CPU
+pub fn fired() -> u64 {
+    FIRED.load(Ordering::Relaxed)
+}
+
+fn start(micros: u64, mode: u32) {
+    let ticks_per_milli = ticks_per_milli();
+    assert!(ticks_per_milli != 0, "the APIC timer isn't calibrated");
+    // At least one tick, since a count of 0 stops the timer
+    let count = (ticks_per_milli as u64 * micros / 1000).clamp(1, u32::MAX as u64);
+    unsafe {
+        write(LVT_TIMER, mode | VECTOR as u32);
+        write(INITIAL_COUNT, count as u32);
+    }
+}
+
+pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::interrupts::record(VECTOR);
+    FIRED.fetch_add(1, Ordering::Relaxed);
+    super::end_of_interrupt();
+}
+
+#[test_case]
+fn test_one_shot() {
+    if ticks_per_milli() == 0 {
+        return;
+    }
+    let start = fired();
+    one_shot(1000);
+    while fired() == start {
+        x86_64::instructions::hlt();
+    }
+    // And only the once
+    let ticks = pit::ticks();
+    wait_for_tick(ticks + 2);
+    assert_eq!(fired(), start + 1);
+}
+
+#[test_case]
+fn test_periodic() {
+    if ticks_per_milli() == 0 {
+        return;
+    }
+    let start = fired();
+    periodic(500);
+    while fired() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+    stop();
+}
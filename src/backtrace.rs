@@ -0,0 +1,45 @@
+// Backtraces by following the chain of saved frame pointers. The target spec keeps
+// frame pointers in every function, so each frame starts like this:
+//     [rbp]     the caller's rbp
+//     [rbp + 8] where to return to in the caller
+// and walking the chain from the current rbp visits every caller in turn.
+
+use core::arch::asm;
+
+// Deep enough for anything sane, and it stops a corrupted chain from going on forever
+const MAX_FRAMES: usize = 32;
+
+// Calls `f` with the return address of each frame, innermost first, starting from
+// the frame `rbp` points to
+pub fn walk<F: FnMut(u64)>(mut rbp: u64, mut f: F) {
+    for _ in 0..MAX_FRAMES {
+        // The bootloader starts us off with a zero rbp, which ends the chain. Anything
+        // misaligned can't be a frame pointer, we're reading garbage.
+        if rbp == 0 || !rbp.is_multiple_of(8) {
+            return;
+        }
+        let frame = rbp as *const u64;
+        // Safe as long as the chain is intact, which is all we can go on here
+        let (caller_rbp, return_address) =
+            unsafe { (frame.read_volatile(), frame.add(1).read_volatile()) };
+        if return_address == 0 {
+            return;
+        }
+        f(return_address);
+        // Callers are further up the stack. If the next frame isn't, the chain is broken.
+        if caller_rbp <= rbp {
+            return;
+        }
+        rbp = caller_rbp;
+    }
+}
+
+// A backtrace from wherever this is called
+#[inline(always)]
+pub fn here<F: FnMut(u64)>(f: F) {
+    let rbp: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+    }
+    walk(rbp, f);
+}
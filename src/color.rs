@@ -0,0 +1,29 @@
+// The 16 colors of the VGA text mode palette. The serial console maps them to the
+// matching ANSI colors, so it isn't tied to the VGA writer.
+
+// We use a C-like enum to specify the number for each color
+// repr(u8) ensures that each variant is stored as a u8
+// 4 bits would be sufficient, but Rust lacks a `u4` type
+// By deriving the `Copy`, `Clone`, `Debug`, `PartialEq`, and `Eq` traits
+// we enable copy semantics for the type and make it printable, and comparable
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
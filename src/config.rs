@@ -0,0 +1,82 @@
+// Build time settings for the kernel. There is no command line to read them from
+// yet, so they're just constants - change them here and rebuild.
+
+use crate::keyboard::Layout;
+use crate::serial::{self, FifoTrigger, Parity, SerialConfig};
+
+// Where the serial console (`serial_print!` and friends) goes
+pub const SERIAL_CONSOLE_PORT: u16 = serial::COM1;
+pub const SERIAL_CONSOLE_BAUD: u32 = 38400;
+
+pub fn serial_console() -> SerialConfig {
+    SerialConfig {
+        baud_divisor: SerialConfig::divisor_for(SERIAL_CONSOLE_BAUD),
+        parity: Parity::None,
+        fifo_trigger: FifoTrigger::Fourteen,
+    }
+}
+
+// How big a payload uploaded with `xmodem` can be
+pub const PAYLOAD_SIZE: usize = 256 * 1024;
+// Wait for an XMODEM upload on the serial console once the kernel is up, and run it
+pub const XMODEM_AT_BOOT: bool = false;
+
+// Where `debug_channel` sends its binary frames. It's all bulk data, so as fast as
+// the UART goes.
+pub const DEBUG_CHANNEL_PORT: u16 = serial::COM2;
+pub const DEBUG_CHANNEL_BAUD: u32 = 115200;
+
+pub fn debug_channel() -> SerialConfig {
+    SerialConfig {
+        baud_divisor: SerialConfig::divisor_for(DEBUG_CHANNEL_BAUD),
+        ..serial_console()
+    }
+}
+
+// Where `gdbstub` waits for GDB. This is the debug channel's port too, since most
+// machines only have two: use one or the other in a session, not both.
+pub const GDB_STUB_PORT: u16 = serial::COM2;
+pub const GDB_STUB_BAUD: u32 = 115200;
+
+pub fn gdb_stub() -> SerialConfig {
+    SerialConfig {
+        baud_divisor: SerialConfig::divisor_for(GDB_STUB_BAUD),
+        ..serial_console()
+    }
+}
+
+// How often the timer interrupt goes off, which is also how fine uptime is
+pub const TIMER_HZ: u64 = 1000;
+
+// How long a single test gets before `watchdog` fails the run
+pub const TEST_TIMEOUT_MICROS: u64 = 10_000_000;
+
+// How much of the kernel log `dmesg` holds on to
+pub const DMESG_SIZE: usize = 16 * 1024;
+
+// Per-module log levels applied at boot, e.g. "vga_buffer=warn, interrupts=trace".
+// Setting BOREDOS_LOG when building overrides these.
+pub const LOG_FILTERS: &str = match option_env!("BOREDOS_LOG") {
+    Some(filters) => filters,
+    None => "",
+};
+
+// What the keyboard has printed on its keys, until `keyboard::set_layout` says
+// otherwise
+pub const KEYBOARD_LAYOUT: Layout = Layout::Us;
+
+// How long a key is held before it repeats, and how many times a second it does then.
+// These are the keyboard's own defaults.
+pub const KEY_REPEAT_DELAY_MILLIS: u32 = 500;
+pub const KEY_REPEAT_PER_SECOND: u32 = 11;
+// Repeat keys off the timer instead of leaving it to the keyboard, for when that
+// doesn't work right (see `keyboard::set_software_repeat`)
+pub const SOFTWARE_KEY_REPEAT: bool = false;
+
+// How much memory (in 4 KiB frames) is set aside at boot for runs of frames that have
+// to be contiguous, like DMA buffers. 8 MiB.
+pub const CONTIGUOUS_POOL_FRAMES: usize = 2048;
+
+// Where the kernel heap goes in virtual memory, and how big it is (see `allocator`)
+pub const HEAP_START: usize = 0x4444_4444_0000;
+pub const HEAP_SIZE: usize = 1024 * 1024;
@@ -0,0 +1,269 @@
+// `print!`, `println!` and `clear!` don't talk to the VGA writer directly, they go
+// through whatever `Console` is currently selected here. That way output can be
+// sent somewhere else (a serial port, a framebuffer later on) by swapping the console
+// out, while every caller keeps using the same macros.
+
+use core::fmt;
+use lazy_static::lazy_static;
+
+use crate::color::Color;
+use crate::sync::{IrqSafeMutex, IrqSafeMutexGuard};
+#[cfg(not(feature = "headless"))]
+use crate::vga_buffer;
+
+#[cfg(feature = "dual-console")]
+use crate::serial::{self, SerialPort};
+
+mod queue;
+
+// Anything that can show text. Implementations do their own buffering, `flush`
+// is called once a whole `print!` has been written.
+pub trait Console: Send {
+    fn write_str(&mut self, s: &str);
+    // Headless builds never clear the screen, there is none
+    fn clear(&mut self);
+    fn set_color(&mut self, foreground: Color, background: Color);
+
+    // Writes `s` in another foreground color. Consoles without colors just write it.
+    fn write_colored(&mut self, _foreground: Color, s: &str) {
+        self.write_str(s);
+    }
+
+    fn flush(&mut self) {}
+}
+
+#[cfg(not(feature = "headless"))]
+impl Console for vga_buffer::Writer {
+    fn write_str(&mut self, s: &str) {
+        self.write_string(s);
+    }
+
+    fn clear(&mut self) {
+        self.clear_screen();
+    }
+
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        vga_buffer::Writer::set_color(self, foreground, background);
+    }
+
+    fn write_colored(&mut self, foreground: Color, s: &str) {
+        vga_buffer::Writer::write_colored(self, foreground, s);
+    }
+
+    fn flush(&mut self) {
+        vga_buffer::Writer::flush(self);
+    }
+}
+
+// Output from `try_print!` that couldn't be written right away
+static QUEUE: queue::Queue = queue::Queue::new();
+
+lazy_static! {
+    static ref OUTPUT: IrqSafeMutex<&'static IrqSafeMutex<dyn Console>> = IrqSafeMutex::new(default_console());
+}
+
+// The VGA console until someone says otherwise
+#[cfg(not(feature = "headless"))]
+fn default_console() -> &'static IrqSafeMutex<dyn Console> {
+    *vga_buffer::WRITER
+}
+
+// Without a screen there's only the serial console to go to
+#[cfg(feature = "headless")]
+fn default_console() -> &'static IrqSafeMutex<dyn Console> {
+    &*crate::serial::SERIAL1
+}
+
+// Sends all further `print!` output to `console`
+pub fn set_console(console: &'static IrqSafeMutex<dyn Console>) {
+    *OUTPUT.lock() = console;
+}
+
+// The console `print!` currently writes to
+pub fn current() -> &'static IrqSafeMutex<dyn Console> {
+    // Copied out, so we don't hold on to `OUTPUT` while printing
+    *OUTPUT.lock()
+}
+
+// `write_fmt` needs a `fmt::Write`, which the trait itself can't be without
+// dragging `fmt::Result` into every implementation
+struct Adapter<'a>(&'a mut dyn Console);
+
+impl fmt::Write for Adapter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+// Here we just yeet the std implementation and replace with our own print function.
+// Output goes to whichever console is selected here, the VGA one by default.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+// end yeet
+
+#[macro_export]
+macro_rules! clear {
+    () => ($crate::console::_clear());
+}
+
+// Locks `output` and hands it to `f`, after first writing out anything that's been
+// queued up, and flushes it afterwards. Without `wait` this gives up (returning false)
+// rather than wait for the lock.
+//
+// If an interrupt handler printed while we held the console lock, it would spin on
+// the lock forever, since we can't get back to releasing it until the handler returns.
+// So consoles are behind an `IrqSafeMutex`, which keeps interrupts off for as long
+// as the lock is held, and turns them back on afterwards (if they were on to begin
+// with).
+fn with_output<F>(output: &'static IrqSafeMutex<dyn Console>, wait: bool, f: F) -> bool
+where
+    F: FnOnce(&mut dyn Console),
+{
+    let mut console = match lock(output, wait) {
+        Some(console) => console,
+        None => return false,
+    };
+    #[cfg(feature = "dual-console")]
+    if let Some(mut serial) = mirror(output, wait) {
+        write_out(&mut Mirror(&mut *console, &mut *serial), f);
+        return true;
+    }
+    write_out(&mut *console, f);
+    true
+}
+
+fn write_out<F: FnOnce(&mut dyn Console)>(console: &mut dyn Console, f: F) {
+    QUEUE.drain(console);
+    f(console);
+    console.flush();
+}
+
+fn lock<T: ?Sized>(mutex: &'static IrqSafeMutex<T>, wait: bool) -> Option<IrqSafeMutexGuard<'static, T>> {
+    if wait {
+        Some(mutex.lock())
+    } else {
+        mutex.try_lock()
+    }
+}
+
+// With the `dual-console` feature, everything printed is mirrored to the serial
+// console as well, so a headless run sees the same log as the screen. If `print!`
+// already goes there, there's nothing to mirror. When we can't wait, a busy serial
+// port is just skipped.
+#[cfg(feature = "dual-console")]
+fn mirror(output: &'static IrqSafeMutex<dyn Console>, wait: bool) -> Option<IrqSafeMutexGuard<'static, SerialPort>> {
+    let serial: &'static IrqSafeMutex<SerialPort> = &serial::SERIAL1;
+    if core::ptr::addr_eq(output, serial) {
+        return None;
+    }
+    lock(serial, wait)
+}
+
+// A console that writes everything to two others
+#[cfg(feature = "dual-console")]
+struct Mirror<'a>(&'a mut dyn Console, &'a mut dyn Console);
+
+#[cfg(feature = "dual-console")]
+impl Console for Mirror<'_> {
+    fn write_str(&mut self, s: &str) {
+        self.0.write_str(s);
+        self.1.write_str(s);
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+        self.1.clear();
+    }
+
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        self.0.set_color(foreground, background);
+        self.1.set_color(foreground, background);
+    }
+
+    fn write_colored(&mut self, foreground: Color, s: &str) {
+        self.0.write_colored(foreground, s);
+        self.1.write_colored(foreground, s);
+    }
+
+    fn flush(&mut self) {
+        self.0.flush();
+        self.1.flush();
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    with_output(current(), true, |console| Adapter(console).write_fmt(args).unwrap());
+}
+
+// `print!`, but with `prefix` and then `tag` written in front, the tag in its own
+// color, all under the one lock
+#[doc(hidden)]
+pub fn _print_tagged(prefix: fmt::Arguments, tag_color: Color, tag: &str, args: fmt::Arguments) {
+    use core::fmt::Write;
+    with_output(current(), true, |console| {
+        Adapter(console).write_fmt(prefix).unwrap();
+        console.write_colored(tag_color, tag);
+        Adapter(console).write_fmt(args).unwrap();
+    });
+}
+
+#[doc(hidden)]
+pub fn _clear() {
+    with_output(current(), true, |console| console.clear());
+}
+
+// Like `print!`, but never waits for the console: if someone else is using it, the
+// message is queued and shows up with the next `print!` (or `flush_queued`). This is
+// what exception and NMI handlers should use, since whatever they interrupted might
+// be holding the console lock.
+#[macro_export]
+macro_rules! try_print {
+    ($($arg:tt)*) => ($crate::console::_try_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! try_println {
+    () => ($crate::try_print!("\n"));
+    ($($arg:tt)*) => ($crate::try_print!("{}\n", format_args!($($arg)*)));
+}
+
+// Writes out anything `try_print!` had to queue, unless the console is busy
+pub fn flush_queued() {
+    // `OUTPUT` could be held by whoever we interrupted too
+    if let Some(output) = OUTPUT.try_lock().map(|output| *output) {
+        with_output(output, false, |_| {});
+    }
+}
+
+// Formats straight into the queue
+struct QueueWriter;
+
+impl fmt::Write for QueueWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        QUEUE.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _try_print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let written = match OUTPUT.try_lock().map(|output| *output) {
+        Some(output) => with_output(output, false, |console| Adapter(console).write_fmt(args).unwrap()),
+        None => false,
+    };
+    if !written {
+        let _ = QueueWriter.write_fmt(args);
+    }
+}
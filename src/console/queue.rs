@@ -0,0 +1,100 @@
+// Where `try_print!` output goes when the console is busy. Whoever gets hold of the
+// console next writes it out before their own output, so nothing shows up out of order.
+//
+// This has to work from anywhere, including an NMI that interrupted someone else
+// in the middle of pushing, so there are no locks: writers reserve their bytes by
+// bumping `head`, and then fill them in. Each slot says whether its byte has been
+// filled in yet, so the reader simply stops at the first one that isn't.
+
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+
+use super::Console;
+
+const QUEUE_SIZE: usize = 4096;
+
+// Marks a slot that holds a byte, an empty slot is just 0
+const FILLED: u16 = 0x100;
+
+pub struct Queue {
+    slots: [AtomicU16; QUEUE_SIZE],
+    // Both only ever count up, the slot is the count modulo `QUEUE_SIZE`
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    // Bytes we had no room for
+    dropped: AtomicUsize,
+}
+
+impl Queue {
+    pub const fn new() -> Queue {
+        Queue {
+            slots: [const { AtomicU16::new(0) }; QUEUE_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // Queues all of `bytes`, or none of it if it doesn't fit
+    pub fn push(&self, bytes: &[u8]) {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            if head + bytes.len() - tail > QUEUE_SIZE {
+                self.dropped.fetch_add(bytes.len(), Ordering::Relaxed);
+                return;
+            }
+            match self.head.compare_exchange_weak(
+                head,
+                head + bytes.len(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.slots[(head + offset) % QUEUE_SIZE].store(FILLED | byte as u16, Ordering::Release);
+        }
+    }
+
+    // Writes out everything that's been queued so far. Only ever called with the
+    // console locked, so there's never more than one reader.
+    pub fn drain(&self, console: &mut dyn Console) {
+        let mut chunk = [0; 64];
+        loop {
+            // Bytes are copied out first and only taken off the queue once they've been
+            // written, since a chunk can end in the middle of a character
+            let tail = self.tail.load(Ordering::Acquire);
+            let mut len = 0;
+            while len < chunk.len() {
+                let value = self.slots[(tail + len) % QUEUE_SIZE].load(Ordering::Acquire);
+                if value & FILLED == 0 {
+                    break;
+                }
+                chunk[len] = value as u8;
+                len += 1;
+            }
+            // Everything queued is a whole `&str`, so the only thing that can go wrong
+            // here is a character that's cut off at the end
+            let valid = match core::str::from_utf8(&chunk[..len]) {
+                Ok(text) => text.len(),
+                Err(error) => error.valid_up_to(),
+            };
+            if valid == 0 {
+                break;
+            }
+            console.write_str(core::str::from_utf8(&chunk[..valid]).unwrap_or(""));
+            for offset in 0..valid {
+                self.slots[(tail + offset) % QUEUE_SIZE].store(0, Ordering::Release);
+            }
+            self.tail.store(tail + valid, Ordering::Release);
+        }
+
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            use core::fmt::Write;
+            let _ = writeln!(super::Adapter(console), "[{} bytes of queued output dropped]", dropped);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// What the CPU we're running on can do, as CPUID tells it. Anything optional should
+// be checked for here before it's used, instead of assuming every x86_64 CPU has it.
+// CPUID itself is always there on x86_64.
+//
+// The answers don't change while we're running, so `features` asks once and keeps
+// them. `msr` uses them to check an MSR is there before touching it.
+
+use core::arch::x86_64::__cpuid;
+use core::fmt;
+use spin::Once;
+
+pub mod fpu;
+pub mod mca;
+pub mod msr;
+
+// Leaves, the extended ones above 0x8000_0000
+const LEAF_VENDOR: u32 = 0;
+const LEAF_FEATURES: u32 = 1;
+const LEAF_EXTENDED: u32 = 0x8000_0000;
+const LEAF_EXTENDED_FEATURES: u32 = 0x8000_0001;
+const LEAF_BRAND: u32 = 0x8000_0002;
+const LEAF_POWER_MANAGEMENT: u32 = 0x8000_0007;
+
+static FEATURES: Once<Features> = Once::new();
+
+#[derive(Debug, Clone)]
+pub struct Features {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+
+    pub fpu: bool,
+    pub msr: bool,
+    // Machine check exceptions, and the machine check architecture behind them
+    pub mce: bool,
+    pub mca: bool,
+    pub apic: bool,
+    pub fxsr: bool,
+    pub sse: bool,
+    pub sse2: bool,
+    pub sse3: bool,
+    pub sse4_1: bool,
+    pub sse4_2: bool,
+    pub x2apic: bool,
+    pub tsc_deadline: bool,
+    pub xsave: bool,
+    // Whether the OS has turned XSAVE on (CR4.OSXSAVE), which `fpu::init` does
+    // after `features` first runs, so this is only what it was then
+    pub osxsave: bool,
+    pub avx: bool,
+    pub rdrand: bool,
+    // No-execute pages
+    pub nx: bool,
+    pub huge_pages_1g: bool,
+    // The TSC ticks at the same rate whatever the power state
+    pub invariant_tsc: bool,
+}
+
+impl Features {
+    fn detect() -> Features {
+        let vendor_leaf = __cpuid(LEAF_VENDOR);
+        let mut vendor = [0; 12];
+        vendor[0..4].copy_from_slice(&vendor_leaf.ebx.to_le_bytes());
+        vendor[4..8].copy_from_slice(&vendor_leaf.edx.to_le_bytes());
+        vendor[8..12].copy_from_slice(&vendor_leaf.ecx.to_le_bytes());
+
+        let leaf = __cpuid(LEAF_FEATURES);
+        let (ecx, edx) = (leaf.ecx, leaf.edx);
+        // The extended family and model only count on some families
+        let base_family = (leaf.eax >> 8) & 0xf;
+        let mut family = base_family;
+        let mut model = (leaf.eax >> 4) & 0xf;
+        if base_family == 0xf {
+            family += (leaf.eax >> 20) & 0xff;
+        }
+        if base_family == 0x6 || base_family == 0xf {
+            model |= ((leaf.eax >> 16) & 0xf) << 4;
+        }
+
+        let max_extended = __cpuid(LEAF_EXTENDED).eax;
+        let extended = |leaf: u32| {
+            if max_extended >= leaf {
+                Some(__cpuid(leaf))
+            } else {
+                None
+            }
+        };
+        let extended_edx = extended(LEAF_EXTENDED_FEATURES).map_or(0, |leaf| leaf.edx);
+        let power_edx = extended(LEAF_POWER_MANAGEMENT).map_or(0, |leaf| leaf.edx);
+
+        let mut brand = [0; 48];
+        for (index, chunk) in brand.chunks_mut(16).enumerate() {
+            if let Some(leaf) = extended(LEAF_BRAND + index as u32) {
+                for (bytes, register) in chunk.chunks_mut(4).zip([leaf.eax, leaf.ebx, leaf.ecx, leaf.edx]) {
+                    bytes.copy_from_slice(&register.to_le_bytes());
+                }
+            }
+        }
+
+        let bit = |register: u32, bit: u32| register & (1 << bit) != 0;
+        Features {
+            vendor,
+            brand,
+            family,
+            model,
+            stepping: leaf.eax & 0xf,
+            fpu: bit(edx, 0),
+            msr: bit(edx, 5),
+            mce: bit(edx, 7),
+            mca: bit(edx, 14),
+            apic: bit(edx, 9),
+            fxsr: bit(edx, 24),
+            sse: bit(edx, 25),
+            sse2: bit(edx, 26),
+            sse3: bit(ecx, 0),
+            sse4_1: bit(ecx, 19),
+            sse4_2: bit(ecx, 20),
+            x2apic: bit(ecx, 21),
+            tsc_deadline: bit(ecx, 24),
+            xsave: bit(ecx, 26),
+            osxsave: bit(ecx, 27),
+            avx: bit(ecx, 28),
+            rdrand: bit(ecx, 30),
+            nx: bit(extended_edx, 20),
+            huge_pages_1g: bit(extended_edx, 26),
+            invariant_tsc: bit(power_edx, 8),
+        }
+    }
+
+    // e.g. "GenuineIntel" or "AuthenticAMD"
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    // e.g. "QEMU Virtual CPU version 2.5+", or empty on CPUs too old to say
+    pub fn brand(&self) -> &str {
+        let len = self.brand.iter().position(|&byte| byte == 0).unwrap_or(self.brand.len());
+        core::str::from_utf8(&self.brand[..len]).unwrap_or("").trim()
+    }
+
+    // The optional features we care about, by name
+    fn flags(&self) -> [(&'static str, bool); 13] {
+        [
+            ("apic", self.apic),
+            ("x2apic", self.x2apic),
+            ("tsc-deadline", self.tsc_deadline),
+            ("invariant-tsc", self.invariant_tsc),
+            ("nx", self.nx),
+            ("1g-pages", self.huge_pages_1g),
+            ("sse3", self.sse3),
+            ("sse4.1", self.sse4_1),
+            ("sse4.2", self.sse4_2),
+            ("xsave", self.xsave),
+            ("avx", self.avx),
+            ("rdrand", self.rdrand),
+            ("msr", self.msr),
+        ]
+    }
+}
+
+// One line for the boot log:
+//     GenuineIntel "QEMU Virtual CPU version 2.5+" family 6 model 6 stepping 3: apic nx sse3
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.vendor())?;
+        if !self.brand().is_empty() {
+            write!(f, " \"{}\"", self.brand())?;
+        }
+        write!(f, " family {} model {} stepping {}:", self.family, self.model, self.stepping)?;
+        for (name, _) in self.flags().iter().filter(|(_, present)| *present) {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn features() -> &'static Features {
+    FEATURES.call_once(Features::detect)
+}
+
+#[test_case]
+fn test_baseline_features() {
+    // Every x86_64 CPU has these
+    let features = features();
+    assert!(features.fpu && features.fxsr && features.sse && features.sse2);
+    assert_eq!(features.vendor().len(), 12);
+}
@@ -0,0 +1,128 @@
+// The x87 FPU and SSE/AVX registers. Out of reset, using any of them is an invalid
+// opcode (or a device-not-available fault), so `init` tells the CPU the OS knows how
+// to save and restore them: CR0 and CR4 for x87 and SSE, and XCR0 for AVX on CPUs
+// with XSAVE.
+//
+// The policy: the kernel itself never touches them. It's built soft-float (see
+// target-spec.json), so the compiler can't vectorize anything, and interrupt
+// handlers can't clobber registers the code they interrupted was using. Code that
+// does use them - payloads loaded with `xmodem`, and later on tasks - owns them, and
+// whatever switches between such code keeps each one's registers in an `FpuState`.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+use super::features;
+
+// Enough for x87, SSE and AVX with XSAVE (832 bytes), or FXSAVE's 512
+const AREA_SIZE: usize = 1024;
+// CPUID leaf to ask how big an XSAVE area has to be
+const LEAF_XSAVE: u32 = 0xd;
+
+// Whether `FpuState` uses XSAVE, or FXSAVE
+static XSAVE: AtomicBool = AtomicBool::new(false);
+
+// Makes x87, SSE and (where there is one) AVX usable, and returns what XCR0 was set
+// to (empty without XSAVE)
+pub fn init() -> XCr0Flags {
+    // Safe since every x86_64 CPU has an FPU and SSE, and nothing here uses them yet:
+    // no emulating the FPU, no lazy switching (TS) and x87 errors as exceptions
+    unsafe {
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|cr4| cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+    if !features().xsave {
+        return XCr0Flags::empty();
+    }
+
+    unsafe { Cr4::update(|cr4| cr4.insert(Cr4Flags::OSXSAVE)) };
+    let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+    if features().avx {
+        xcr0 |= XCr0Flags::AVX;
+    }
+    // Safe since CPUID said these are there
+    unsafe { XCr0::write(xcr0) };
+    // Leaf 0xd's EBX is the size for what XCR0 has on right now
+    if __cpuid_count(LEAF_XSAVE, 0).ebx as usize > AREA_SIZE {
+        xcr0.remove(XCr0Flags::AVX);
+        unsafe { XCr0::write(xcr0) };
+    }
+    XSAVE.store(true, Ordering::SeqCst);
+    xcr0
+}
+
+// Somewhere to keep one owner's FPU and SIMD registers while someone else has them
+#[repr(C, align(64))]
+pub struct FpuState {
+    area: [u8; AREA_SIZE],
+    // Restoring an area nothing was saved into would load garbage
+    saved: bool,
+}
+
+impl FpuState {
+    pub const fn new() -> FpuState {
+        FpuState {
+            area: [0; AREA_SIZE],
+            saved: false,
+        }
+    }
+
+    pub fn save(&mut self) {
+        let area = self.area.as_mut_ptr();
+        // Safe since the area is big enough and aligned for either, and `init` has
+        // turned on whichever one this is
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                // Everything XCR0 has on
+                asm!("xsave64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX, options(nostack));
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack));
+            }
+        }
+        self.saved = true;
+    }
+
+    // Puts the registers back as they were at the last `save`
+    pub fn restore(&self) {
+        assert!(self.saved, "restoring FPU state that was never saved");
+        let area = self.area.as_ptr();
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!("xrstor64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX, options(nostack));
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) area, options(nostack));
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> FpuState {
+        FpuState::new()
+    }
+}
+
+#[test_case]
+fn test_save_restore() {
+    // MXCSR, the SSE control register, goes through the area like everything else
+    fn mxcsr() -> u32 {
+        let mut value = 0u32;
+        unsafe { asm!("stmxcsr [{}]", in(reg) &mut value, options(nostack)) };
+        value
+    }
+    let mut state = FpuState::new();
+    let before = mxcsr();
+    state.save();
+    // Round towards zero instead
+    let changed = before | 0x6000;
+    unsafe { asm!("ldmxcsr [{}]", in(reg) &changed, options(nostack)) };
+    assert_eq!(mxcsr(), changed);
+    state.restore();
+    assert_eq!(mxcsr(), before);
+}
@@ -0,0 +1,196 @@
+// Machine check architecture: how the CPU owns up to hardware errors, like a bad bit
+// in RAM or a cache that can't be trusted any more. Each kind of hardware has a bank
+// of MSRs, and an error leaves its details in the bank's status register. Errors
+// that can be fixed (ECC correcting a bit, say) are just left there for whoever
+// looks; the ones that can't raise a machine check exception, which without CR4.MCE
+// shuts the CPU down - on real hardware, a reboot out of nowhere.
+//
+// `init` turns all of it on, and first logs whatever the banks still hold from
+// before the last reset, which is often the only trace of why there was one.
+// `Report` is what the #MC handler puts on the crash screen.
+
+use core::fmt;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+
+use super::features;
+use super::msr::{Msr, Requires};
+
+const MCG_CAP: Msr = Msr::new(0x179, "IA32_MCG_CAP", Requires::Mca);
+const MCG_STATUS: Msr = Msr::new(0x17a, "IA32_MCG_STATUS", Requires::Mca);
+const MCG_CTL: Msr = Msr::new(0x17b, "IA32_MCG_CTL", Requires::Mca);
+// Each bank has four, starting from here
+const BANK_BASE: u32 = 0x400;
+
+// In MCG_CAP
+const CAP_COUNT: u64 = 0xff;
+const CAP_CTL_PRESENT: u64 = 1 << 8;
+// In MCG_STATUS: whether execution could carry on at the saved RIP (RIPV), and
+// whether the saved RIP is the instruction the error happened at (EIPV)
+const STATUS_RIP_VALID: u64 = 1 << 0;
+const STATUS_EIP_VALID: u64 = 1 << 1;
+const STATUS_IN_PROGRESS: u64 = 1 << 2;
+// In a bank's status
+const BANK_VALID: u64 = 1 << 63;
+const BANK_OVERFLOW: u64 = 1 << 62;
+const BANK_UNCORRECTED: u64 = 1 << 61;
+const BANK_MISC_VALID: u64 = 1 << 59;
+const BANK_ADDR_VALID: u64 = 1 << 58;
+const BANK_CONTEXT_CORRUPT: u64 = 1 << 57;
+
+// More than any CPU has had so far
+const MAX_BANKS: usize = 32;
+
+fn bank(index: usize, register: u32, name: &'static str) -> Msr {
+    Msr::new(BANK_BASE + 4 * index as u32 + register, name, Requires::Mca)
+}
+
+fn bank_count() -> usize {
+    MCG_CAP.read().map_or(0, |cap| (cap & CAP_COUNT) as usize).min(MAX_BANKS)
+}
+
+// Turns on machine checks, and says how many banks there are (0 without MCA), or
+// None if the CPU has no machine check exception at all
+pub fn init() -> Option<usize> {
+    if !features().mce {
+        return None;
+    }
+    let banks = bank_count();
+    for index in 0..banks {
+        if let Some(error) = BankError::read(index) {
+            log::warn!("machine check left over from before boot: {}", error);
+        }
+    }
+    // Safe since CPUID says all of these are there, and the banks reporting
+    // everything is what we want
+    unsafe {
+        if MCG_CAP.read().is_ok_and(|cap| cap & CAP_CTL_PRESENT != 0) {
+            let _ = MCG_CTL.write(u64::MAX);
+        }
+        for index in 0..banks {
+            let _ = bank(index, 0, "IA32_MCi_CTL").write(u64::MAX);
+            let _ = bank(index, 1, "IA32_MCi_STATUS").write(0);
+        }
+        Cr4::update(|cr4| cr4.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+    }
+    Some(banks)
+}
+
+// What one bank has to say
+#[derive(Debug, Clone, Copy)]
+pub struct BankError {
+    pub bank: usize,
+    pub status: u64,
+    pub address: Option<u64>,
+    pub misc: Option<u64>,
+}
+
+impl BankError {
+    pub fn read(bank_index: usize) -> Option<BankError> {
+        let status = bank(bank_index, 1, "IA32_MCi_STATUS").read().ok()?;
+        if status & BANK_VALID == 0 {
+            return None;
+        }
+        let optional = |bit: u64, register: u32, name| {
+            if status & bit != 0 {
+                bank(bank_index, register, name).read().ok()
+            } else {
+                None
+            }
+        };
+        Some(BankError {
+            bank: bank_index,
+            status,
+            address: optional(BANK_ADDR_VALID, 2, "IA32_MCi_ADDR"),
+            misc: optional(BANK_MISC_VALID, 3, "IA32_MCi_MISC"),
+        })
+    }
+
+    pub fn uncorrected(&self) -> bool {
+        self.status & BANK_UNCORRECTED != 0
+    }
+}
+
+// e.g. "bank 4: uncorrected memory controller error (code 0x9f), status 0xbe00..., address 0x12345000"
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.uncorrected() { "uncorrected" } else { "corrected" };
+        let code = self.status as u16;
+        write!(f, "bank {}: {} {} (code {:#x}), status {:#x}", self.bank, kind, describe(code), code, self.status)?;
+        if let Some(address) = self.address {
+            write!(f, ", address {:#x}", address)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, ", misc {:#x}", misc)?;
+        }
+        if self.status & BANK_OVERFLOW != 0 {
+            write!(f, ", more errors were lost")?;
+        }
+        if self.status & BANK_CONTEXT_CORRUPT != 0 {
+            write!(f, ", CPU state corrupt")?;
+        }
+        Ok(())
+    }
+}
+
+// The architectural part of an MCA error code. There's a lot more detail in the
+// low bits of most of these, which is left to whoever reads the code.
+fn describe(code: u16) -> &'static str {
+    // Bit 12 only says whether the error was already reported as corrected
+    let code = code & !(1 << 12);
+    match code {
+        0x0000 => "no error",
+        0x0001 => "unclassified error",
+        0x0002 => "microcode ROM parity error",
+        0x0003 => "external error",
+        0x0004 => "FRC error",
+        0x0005 => "internal parity error",
+        0x0400 => "internal timer error",
+        _ if code & 0xf800 == 0x0800 => "bus or interconnect error",
+        _ if code & 0xff00 == 0x0100 => "cache error",
+        _ if code & 0xff80 == 0x0080 => "memory controller error",
+        _ if code & 0xfff0 == 0x0010 => "TLB error",
+        _ if code & 0xfffc == 0x000c => "cache hierarchy error",
+        _ if code & 0xfc00 == 0x0400 => "internal error",
+        _ => "model-specific error",
+    }
+}
+
+// Everything the banks say, for the crash screen after a machine check exception
+pub struct Report;
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match MCG_STATUS.read() {
+            Ok(status) => status,
+            Err(_) => return write!(f, "no machine check architecture, so no details"),
+        };
+        if status & STATUS_IN_PROGRESS == 0 {
+            write!(f, "not raised by the hardware")?;
+        } else {
+            if status & STATUS_RIP_VALID == 0 {
+                write!(f, "execution can't carry on at the saved RIP")?;
+            } else {
+                write!(f, "execution could carry on at the saved RIP")?;
+            }
+            if status & STATUS_EIP_VALID == 0 {
+                write!(f, ", which may not be where it happened")?;
+            } else {
+                write!(f, ", which is where it happened")?;
+            }
+        }
+        for index in 0..bank_count() {
+            if let Some(error) = BankError::read(index) {
+                write!(f, "\n{}", error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_describe_codes() {
+    assert_eq!(describe(0x0000), "no error");
+    assert_eq!(describe(0x009f), "memory controller error");
+    assert_eq!(describe(0x1134), "cache error");
+    assert_eq!(describe(0x0e0b), "bus or interconnect error");
+}
@@ -0,0 +1,148 @@
+// Model-specific registers, read with `rdmsr` and written with `wrmsr`. Touching one
+// the CPU doesn't have is a general protection fault, so every MSR here knows what
+// CPUID has to say for it to be there, and `read` and `write` check that first.
+//
+// Reading is safe, MSRs don't change anything by being read. Writing isn't: these
+// decide things like whether long mode is on and where system calls go.
+
+use core::arch::asm;
+use core::fmt;
+
+use super::{features, Features};
+
+pub const APIC_BASE: Msr = Msr::new(0x1b, "IA32_APIC_BASE", Requires::Apic);
+pub const TSC_DEADLINE: Msr = Msr::new(0x6e0, "IA32_TSC_DEADLINE", Requires::TscDeadline);
+pub const EFER: Msr = Msr::new(0xc000_0080, "IA32_EFER", Requires::Nothing);
+// Where `syscall` goes: the segments, the 64-bit entry point, and which RFLAGS
+// bits to clear on the way in
+pub const STAR: Msr = Msr::new(0xc000_0081, "IA32_STAR", Requires::Nothing);
+pub const LSTAR: Msr = Msr::new(0xc000_0082, "IA32_LSTAR", Requires::Nothing);
+pub const SFMASK: Msr = Msr::new(0xc000_0084, "IA32_FMASK", Requires::Nothing);
+pub const FS_BASE: Msr = Msr::new(0xc000_0100, "IA32_FS_BASE", Requires::Nothing);
+pub const GS_BASE: Msr = Msr::new(0xc000_0101, "IA32_GS_BASE", Requires::Nothing);
+// What `swapgs` swaps `GS_BASE` with
+pub const KERNEL_GS_BASE: Msr = Msr::new(0xc000_0102, "IA32_KERNEL_GS_BASE", Requires::Nothing);
+
+// In EFER
+pub const EFER_SYSCALL_ENABLE: u64 = 1 << 0;
+pub const EFER_LONG_MODE_ENABLE: u64 = 1 << 8;
+pub const EFER_LONG_MODE_ACTIVE: u64 = 1 << 10;
+pub const EFER_NO_EXECUTE_ENABLE: u64 = 1 << 11;
+
+// What CPUID has to say for an MSR to be there. The ones long mode needs are always
+// there, since we wouldn't be running otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requires {
+    Nothing,
+    Apic,
+    TscDeadline,
+    X2Apic,
+    Mca,
+}
+
+impl Requires {
+    fn met_by(self, features: &Features) -> bool {
+        match self {
+            Requires::Nothing => true,
+            Requires::Apic => features.apic,
+            Requires::TscDeadline => features.tsc_deadline,
+            Requires::X2Apic => features.x2apic,
+            Requires::Mca => features.mca,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msr {
+    index: u32,
+    name: &'static str,
+    requires: Requires,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsrError {
+    // CPUID says this CPU doesn't have it
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for MsrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MsrError::Unsupported(name) => write!(f, "this CPU has no {}", name),
+        }
+    }
+}
+
+impl Msr {
+    pub const fn new(index: u32, name: &'static str, requires: Requires) -> Msr {
+        Msr { index, name, requires }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_supported(&self) -> bool {
+        features().msr && self.requires.met_by(features())
+    }
+
+    pub fn read(&self) -> Result<u64, MsrError> {
+        self.check()?;
+        // Safe since CPUID says it's there
+        Ok(unsafe { rdmsr(self.index) })
+    }
+
+    // Unsafe because most MSRs change how the CPU works. The caller has to know what
+    // the new value does.
+    pub unsafe fn write(&self, value: u64) -> Result<(), MsrError> {
+        self.check()?;
+        wrmsr(self.index, value);
+        Ok(())
+    }
+
+    // Sets `bits` and leaves the rest as they were
+    pub unsafe fn set_bits(&self, bits: u64) -> Result<u64, MsrError> {
+        let value = self.read()? | bits;
+        self.write(value)?;
+        Ok(value)
+    }
+
+    fn check(&self) -> Result<(), MsrError> {
+        if self.is_supported() {
+            Ok(())
+        } else {
+            Err(MsrError::Unsupported(self.name))
+        }
+    }
+}
+
+// The instructions themselves, for MSRs that aren't listed above. Unsafe since the
+// MSR has to exist, and writing it has to be fine.
+pub unsafe fn rdmsr(index: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!("rdmsr", in("ecx") index, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    (high as u64) << 32 | low as u64
+}
+
+pub unsafe fn wrmsr(index: u32, value: u64) {
+    let (low, high) = (value as u32, (value >> 32) as u32);
+    asm!("wrmsr", in("ecx") index, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+}
+
+#[test_case]
+fn test_long_mode_active() {
+    let efer = EFER.read().unwrap();
+    assert_ne!(efer & EFER_LONG_MODE_ACTIVE, 0);
+}
+
+#[test_case]
+fn test_unsupported_msr() {
+    let missing = Msr::new(0xffff_ffff, "MISSING", Requires::X2Apic);
+    if !missing.is_supported() {
+        assert_eq!(missing.read(), Err(MsrError::Unsupported("MISSING")));
+    }
+}
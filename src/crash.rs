@@ -0,0 +1,347 @@
+// What the kernel does on its way down. Panics (and, once there are handlers,
+// fatal CPU exceptions) end up on a crash screen with what went wrong and where,
+// the registers, the top of the stack and a backtrace:
+//     KERNEL PANIC at src/main.rs:42:5
+//     attempt to divide by zero
+//
+//     RAX=0000000000000000  RBX=0000000000000000  RCX=0000000000000000
+//     ...
+//     RSP=0000020000000f70 TOP OF STACK
+//     0000020000000f70: 0000000000000000 0000000000203c8b 0000000000000000
+//     ...
+//     BACKTRACE
+//     0000000000203c8b crash::report_panic+0x2b
+//     ...
+// The same goes to the serial console, so there's a record of it even if nobody is
+// looking at the screen, and into `crashdump` for the next boot to find.
+//
+// Whatever crashed might have been holding the console locks, and it's never going
+// to let go of them now, so the crash screen takes them by force. Interrupts are
+// turned off for good before that, so nothing else gets to run and notice.
+//
+// Tests of the crash path itself (see tests/stack_overflow.rs) say what crash they're
+// expecting with `expect_crash`. For them, getting here with the right one is the test
+// passing, and QEMU exits before there's any crash screen.
+//
+// If the crash screen itself crashes (following a broken frame pointer chain, say),
+// all we do the second time around is put a fixed message straight into video memory
+// and out of the serial port, without locks, formatting or anything else that could
+// have been the problem.
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Once;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr2, Cr3};
+
+use crate::backtrace;
+use crate::config;
+use crate::crashdump;
+use crate::memory;
+use crate::qemu::{self, QemuExitCode};
+use crate::serial::{SerialPort, SERIAL1};
+use crate::symbols;
+use crate::sync::{IrqSafeMutex, IrqSafeMutexGuard};
+#[cfg(not(feature = "headless"))]
+use crate::vga_buffer::{self, LineMode, PANIC_BACKGROUND, PANIC_FOREGROUND};
+
+// Set once we've started on the crash screen
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+// What the crash a test is waiting for has to say, if there is one
+static EXPECTED_CRASH: Once<&'static str> = Once::new();
+
+const DOUBLE_PANIC_MESSAGE: &[u8] = b"DOUBLE PANIC: the kernel crashed while reporting a crash";
+
+// How much of the stack gets shown, in rows of 3 values
+const STACK_ROWS: usize = 4;
+const STACK_COLUMNS: usize = 3;
+// As many frames as fit on the screen below everything else
+const BACKTRACE_FRAMES: usize = 7;
+
+// Locks `mutex` whether or not someone else is holding it.
+//
+// Unsafe because the holder might be in the middle of changing what's inside, so the
+// caller has to be sure they never run again (or don't mind).
+pub unsafe fn force_lock<T: ?Sized>(mutex: &IrqSafeMutex<T>) -> IrqSafeMutexGuard<'_, T> {
+    if let Some(guard) = mutex.try_lock() {
+        return guard;
+    }
+    mutex.force_unlock();
+    mutex.lock()
+}
+
+// The registers as they were when things went wrong. `capture` has the assembly
+// below depend on this exact layout.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+}
+
+impl Registers {
+    // The registers right here. By the time we get here the compiler has had its
+    // way with most of them, but the stack and frame pointers still say where we are.
+    #[inline(always)]
+    pub fn capture() -> Registers {
+        let mut registers = Registers::default();
+        unsafe {
+            asm!(
+                "mov [{r} + 0x00], rax",
+                "mov [{r} + 0x08], rbx",
+                "mov [{r} + 0x10], rcx",
+                "mov [{r} + 0x18], rdx",
+                "mov [{r} + 0x20], rsi",
+                "mov [{r} + 0x28], rdi",
+                "mov [{r} + 0x30], rbp",
+                "mov [{r} + 0x38], r8",
+                "mov [{r} + 0x40], r9",
+                "mov [{r} + 0x48], r10",
+                "mov [{r} + 0x50], r11",
+                "mov [{r} + 0x58], r12",
+                "mov [{r} + 0x60], r13",
+                "mov [{r} + 0x68], r14",
+                "mov [{r} + 0x70], r15",
+                "lea {tmp}, [rip]",
+                "mov [{r} + 0x78], {tmp}",
+                "mov [{r} + 0x80], rsp",
+                "pushfq",
+                "pop {tmp}",
+                "mov [{r} + 0x88], {tmp}",
+                r = in(reg) &mut registers as *mut Registers,
+                tmp = out(reg) _,
+            );
+        }
+        registers
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registers = [
+            ("RAX", self.rax),
+            ("RBX", self.rbx),
+            ("RCX", self.rcx),
+            ("RDX", self.rdx),
+            ("RSI", self.rsi),
+            ("RDI", self.rdi),
+            ("RBP", self.rbp),
+            ("R8 ", self.r8),
+            ("R9 ", self.r9),
+            ("R10", self.r10),
+            ("R11", self.r11),
+            ("R12", self.r12),
+            ("R13", self.r13),
+            ("R14", self.r14),
+            ("R15", self.r15),
+            ("RIP", self.rip),
+            ("RSP", self.rsp),
+            ("RFL", self.rflags),
+        ];
+        for row in registers.chunks(3) {
+            for (index, (name, value)) in row.iter().enumerate() {
+                let separator = if index == 0 { "" } else { "  " };
+                write!(f, "{}{}={:016x}", separator, name, value)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// The panic message, and where it came from
+struct PanicReport<'a>(&'a PanicInfo<'a>);
+
+impl fmt::Display for PanicReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "KERNEL PANIC")?;
+        if let Some(location) = self.0.location() {
+            write!(f, " at {}:{}:{}", location.file(), location.line(), location.column())?;
+        }
+        write!(f, "\n{}", self.0.message())
+    }
+}
+
+pub fn report_panic(info: &PanicInfo) {
+    let registers = Registers::capture();
+    crash_screen(&PanicReport(info), &registers);
+}
+
+// Makes the next crash the end of a test: it passes if whatever crashed mentions
+// `expected` (e.g. "DOUBLE FAULT"), and fails otherwise. Only the first call counts.
+pub fn expect_crash(expected: &'static str) {
+    EXPECTED_CRASH.call_once(|| expected);
+}
+
+// Shows the crash screen for `what` went wrong. After this the kernel is only good
+// for halting.
+pub fn crash_screen(what: &dyn fmt::Display, registers: &Registers) {
+    interrupts::disable();
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        double_panic();
+        return;
+    }
+
+    let expected = EXPECTED_CRASH.r#try().copied();
+    if let Some(expected) = expected {
+        let mut serial = unsafe { force_lock(&SERIAL1) };
+        if mentions(what, expected) {
+            let _ = writeln!(serial, "[ok]");
+            qemu::exit_qemu(QemuExitCode::Success);
+        }
+        // The crash screen below says what happened instead
+        let _ = writeln!(serial, "[failed] expected a crash mentioning {:?}", expected);
+    }
+
+    // Before anything else can go wrong, so the dump is there even if showing the
+    // report crashes too
+    crashdump::save(what, registers);
+
+    #[cfg(not(feature = "headless"))]
+    {
+        // Safe since nothing but us ever runs again
+        let mut writer = unsafe { vga_buffer::force_lock_active_console() };
+        // The whole screen is ours now, status bar and all
+        writer.set_reserved_rows(0, 0);
+        writer.set_line_mode(LineMode::Wrap);
+        writer.set_color(PANIC_FOREGROUND, PANIC_BACKGROUND);
+        writer.clear_screen();
+        writer.set_position(0, 0);
+        writer.hide_cursor();
+        let _ = write_report(&mut *writer, what, registers);
+        writer.flush();
+    }
+
+    // Headless builds have this as their only console anyway
+    let mut serial = unsafe { force_lock(&SERIAL1) };
+    let _ = writeln!(serial);
+    let _ = write_report(&mut *serial, what, registers);
+    drop(serial);
+
+    if expected.is_some() {
+        qemu::exit_qemu(QemuExitCode::Failed);
+    }
+}
+
+// Whether `needle` shows up anywhere in `what`. Anything longer than `SEARCH_WINDOW`
+// never does.
+fn mentions(what: &dyn fmt::Display, needle: &str) -> bool {
+    const SEARCH_WINDOW: usize = 64;
+
+    // Keeps the last `needle.len()` bytes written, which is all a match needs
+    struct Search<'a> {
+        needle: &'a [u8],
+        window: [u8; SEARCH_WINDOW],
+        len: usize,
+        found: bool,
+    }
+
+    impl Write for Search<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for &byte in s.as_bytes() {
+                if self.len == self.needle.len() {
+                    self.window.copy_within(1..self.len, 0);
+                    self.len -= 1;
+                }
+                self.window[self.len] = byte;
+                self.len += 1;
+                self.found |= self.window[..self.len] == *self.needle;
+            }
+            Ok(())
+        }
+    }
+
+    if needle.len() > SEARCH_WINDOW {
+        return false;
+    }
+    let mut search = Search {
+        needle: needle.as_bytes(),
+        window: [0; SEARCH_WINDOW],
+        len: 0,
+        found: needle.is_empty(),
+    };
+    let _ = write!(search, "{}", what);
+    search.found
+}
+
+fn double_panic() {
+    #[cfg(not(feature = "headless"))]
+    unsafe {
+        vga_buffer::write_raw(DOUBLE_PANIC_MESSAGE);
+    }
+    // A port of our own rather than `SERIAL1`, which might be what's broken. If the
+    // port was never set up this goes nowhere, but there's nothing better to try.
+    let mut serial = unsafe { SerialPort::new(config::SERIAL_CONSOLE_PORT) };
+    for &byte in b"\r\n".iter().chain(DOUBLE_PANIC_MESSAGE).chain(b"\r\n") {
+        serial.send(byte);
+    }
+}
+
+fn write_report(out: &mut dyn Write, what: &dyn fmt::Display, registers: &Registers) -> fmt::Result {
+    writeln!(out, "{}\n", what)?;
+    write!(out, "{}", registers)?;
+    let (cr3, _) = Cr3::read();
+    writeln!(
+        out,
+        "CR2={:016x}  CR3={:016x}\n",
+        Cr2::read().as_u64(),
+        cr3.start_address().as_u64()
+    )?;
+
+    writeln!(out, "RSP={:016x} TOP OF STACK", registers.rsp)?;
+    // After a stack overflow RSP points past the end of the stack, and reading there
+    // would just crash the crash screen
+    if memory::is_mapped(registers.rsp, (STACK_ROWS * STACK_COLUMNS * 8) as u64) {
+        let stack = registers.rsp as *const u64;
+        for row in 0..STACK_ROWS {
+            let address = registers.rsp + (row * STACK_COLUMNS * 8) as u64;
+            write!(out, "{:016x}:", address)?;
+            for column in 0..STACK_COLUMNS {
+                // Safe since it's all mapped, see above
+                let value = unsafe { stack.add(row * STACK_COLUMNS + column).read_volatile() };
+                write!(out, " {:016x}", value)?;
+            }
+            writeln!(out)?;
+        }
+    } else {
+        writeln!(out, "stack not mapped")?;
+    }
+
+    // Starting from the frame the registers were taken in
+    writeln!(out, "BACKTRACE")?;
+    let mut frames = 0;
+    let mut result = Ok(());
+    backtrace::walk(registers.rbp, |address| {
+        if frames == BACKTRACE_FRAMES {
+            return;
+        }
+        frames += 1;
+        result = result.and_then(|_| write!(out, "{:016x}", address));
+        // The call is just before where it returns to, and might have been the last
+        // thing in the function
+        if let Some(symbol) = symbols::lookup(address - 1) {
+            result = result.and_then(|_| write!(out, " {}+{:#x}", symbol.name(), symbol.offset + 1));
+        }
+        result = result.and_then(|_| writeln!(out));
+    });
+    result
+}
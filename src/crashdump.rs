@@ -0,0 +1,207 @@
+// A crash dump that survives a warm reboot, for crashes that never happen when
+// anyone is watching. The crash screen also writes what went wrong, the registers,
+// a bit of the stack and the end of the kernel log into a piece of RAM that nothing
+// else touches. A warm reboot (reset button, triple fault, QEMU's `system_reset`)
+// leaves RAM alone, so on the next boot `init` finds the dump there and logs it.
+//
+// The dump goes at the end of the highest usable memory below 4 GiB, which is the
+// same place every boot as long as the memory map doesn't change. Firmware that
+// clears memory on reset, or a cold boot, just means there's no dump to find.
+
+use core::fmt::{self, Write};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use bootloader::bootinfo::MemoryRegionType;
+use bootloader::BootInfo;
+
+use crate::crash::Registers;
+use crate::dmesg;
+use crate::memory;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"BOREDUMP");
+const MESSAGE_SIZE: usize = 512;
+const LOG_SIZE: usize = 32 * 1024;
+const STACK_SIZE: usize = 32;
+// How much of the saved log gets shown at boot, the rest is just noise by then
+const SHOWN_LOG_LINES: usize = 10;
+const SHOWN_STACK_ROWS: usize = 4;
+
+// Where the dump lives, through the bootloader's mapping of physical memory. 0 until
+// `init` has found a place for it.
+static DUMP: AtomicU64 = AtomicU64::new(0);
+// And its physical address, for `memory::regions` to keep it out of everyone's way
+static DUMP_PHYSICAL: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+struct CrashDump {
+    magic: u64,
+    // Over everything after the header, so a half written dump (or RAM that just
+    // happens to start with the magic) doesn't count
+    checksum: u32,
+    message_len: u32,
+    log_len: u32,
+    // Keeps the registers aligned without any padding the compiler would leave unset
+    _reserved: u32,
+    registers: Registers,
+    stack: [u64; STACK_SIZE],
+    message: [u8; MESSAGE_SIZE],
+    log: [u8; LOG_SIZE],
+}
+
+impl CrashDump {
+    fn checksum(&self) -> u32 {
+        // FNV-1a over the message length onwards
+        let start = core::ptr::addr_of!(self.message_len) as *const u8;
+        let len = size_of::<CrashDump>() - (start as usize - self as *const _ as usize);
+        let bytes = unsafe { core::slice::from_raw_parts(start, len) };
+        bytes
+            .iter()
+            .fold(0x811c9dc5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+    }
+
+    fn message(&self) -> &str {
+        text(&self.message[..(self.message_len as usize).min(MESSAGE_SIZE)])
+    }
+
+    fn log(&self) -> &str {
+        text(&self.log[..(self.log_len as usize).min(LOG_SIZE)])
+    }
+}
+
+// Both are only ever cut at character boundaries when they're written, but this is
+// memory from a previous life, so don't count on it
+fn text(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+// Finds the dump area, and logs the dump from the previous boot if there is one
+pub fn init(boot_info: &'static BootInfo) {
+    let region = boot_info
+        .memory_map
+        .iter()
+        .filter(|region| region.region_type == MemoryRegionType::Usable)
+        .map(|region| (region.range.start_addr(), region.range.end_addr().min(1 << 32)))
+        .filter(|&(start, end)| end > start && end - start >= size_of::<CrashDump>() as u64)
+        .max_by_key(|&(_, end)| end);
+    let (_, end) = match region {
+        Some(region) => region,
+        None => {
+            log::warn!("no room for a crash dump");
+            return;
+        }
+    };
+    // Page aligned, to keep it clear of anything sharing its last page
+    let start = (end - size_of::<CrashDump>() as u64) & !0xfff;
+    let address = boot_info.physical_memory_offset + start;
+    DUMP.store(address, Ordering::SeqCst);
+    DUMP_PHYSICAL.store(start, Ordering::SeqCst);
+
+    // The bootloader maps all of physical memory, and this bit is ours
+    let dump = unsafe { &mut *(address as *mut CrashDump) };
+    if dump.magic == MAGIC && dump.checksum == dump.checksum() {
+        report(dump);
+    }
+    // Whatever was there, it's been dealt with
+    dump.magic = 0;
+}
+
+fn report(dump: &CrashDump) {
+    log::error!("the previous boot crashed:\n{}\n{}", dump.message(), dump.registers);
+    for row in dump.stack.chunks_exact(3).take(SHOWN_STACK_ROWS) {
+        log::error!("stack: {:016x} {:016x} {:016x}", row[0], row[1], row[2]);
+    }
+    let log = dump.log().trim_end_matches('\n');
+    let skip = log.lines().count().saturating_sub(SHOWN_LOG_LINES);
+    for line in log.lines().skip(skip) {
+        log::error!("log: {}", line);
+    }
+}
+
+// Where the dump lives in physical memory, start and end, once `init` has found it a
+// place
+pub fn physical_range() -> Option<(u64, u64)> {
+    match DUMP_PHYSICAL.load(Ordering::SeqCst) {
+        0 => None,
+        start => Some((start, start + size_of::<CrashDump>() as u64)),
+    }
+}
+
+// Writes the dump for `what` went wrong. Only the crash screen calls this, with
+// everything else stopped, so it can help itself to the log ring.
+pub fn save(what: &dyn fmt::Display, registers: &Registers) {
+    let address = DUMP.load(Ordering::SeqCst);
+    if address == 0 {
+        return;
+    }
+    let dump = unsafe { &mut *(address as *mut CrashDump) };
+    dump.magic = 0;
+
+    let mut message = Truncating {
+        bytes: &mut dump.message,
+        len: 0,
+        skip: 0,
+    };
+    let _ = write!(message, "{}", what);
+    dump.message_len = message.len as u32;
+
+    dump.registers = *registers;
+    // Left as zeroes if RSP is off the end of the stack, as it is after an overflow
+    dump.stack = [0; STACK_SIZE];
+    if memory::is_mapped(registers.rsp, (STACK_SIZE * 8) as u64) {
+        let stack = registers.rsp as *const u64;
+        for (index, value) in dump.stack.iter_mut().enumerate() {
+            // Safe since it's all mapped, see above
+            *value = unsafe { stack.add(index).read_volatile() };
+        }
+    }
+
+    // Only the end of the log fits, so first work out how much to leave out
+    let mut total = 0;
+    unsafe { dmesg::force_for_each(|line| total += line.len() + 1) };
+    let mut log = Truncating {
+        bytes: &mut dump.log,
+        len: 0,
+        skip: total.saturating_sub(LOG_SIZE),
+    };
+    unsafe {
+        dmesg::force_for_each(|line| {
+            let _ = writeln!(log, "{}", line);
+        })
+    };
+    dump.log_len = log.len as u32;
+
+    dump.checksum = dump.checksum();
+    dump.magic = MAGIC;
+    // Caches don't necessarily make it through a reset, RAM does
+    unsafe { core::arch::asm!("wbinvd", options(nostack)) };
+}
+
+// Fills `bytes` with whatever's written, after skipping the first `skip` bytes of it
+// and stopping once full. Either way it only ever cuts between characters.
+struct Truncating<'a> {
+    bytes: &'a mut [u8],
+    len: usize,
+    skip: usize,
+}
+
+impl fmt::Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.skip > 0 {
+                self.skip = self.skip.saturating_sub(len);
+                continue;
+            }
+            if self.len + len > self.bytes.len() {
+                return Ok(());
+            }
+            c.encode_utf8(&mut self.bytes[self.len..self.len + len]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
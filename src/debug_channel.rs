@@ -0,0 +1,112 @@
+// A binary side channel on a second serial port, for getting structured data (memory
+// dumps, trace records, ...) out of the kernel without it ending up in the middle of
+// the text log. With QEMU, add something like `-serial stdio -serial file:debug.bin`
+// to get the log on the terminal and the binary data in a file.
+//
+// Every message goes out as one frame:
+//
+//     0x7E | tag | length (u32, little endian) | data | CRC (u16, little endian) | 0x7E
+//
+// Inside the frame (everything between the 0x7E flags) any 0x7E or 0x7D byte is sent
+// as 0x7D followed by the byte XOR 0x20, so a 0x7E always marks a frame boundary and a
+// reader can resync after a corrupted frame. The CRC is CRC-16/CCITT-FALSE (polynomial
+// 0x1021, starting at 0xFFFF) over the tag, length and data, before escaping.
+//
+// So a host side reader splits the stream on 0x7E, drops empty pieces, undoes the
+// escaping, and checks the length and CRC before handing out the tag and data.
+
+use lazy_static::lazy_static;
+
+use crate::config;
+use crate::serial::SerialPort;
+use crate::sync::IrqSafeMutex;
+
+const FLAG: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+const ESCAPE_XOR: u8 = 0x20;
+
+lazy_static! {
+    static ref PORT: IrqSafeMutex<SerialPort> = {
+        // The port comes from the kernel config. `gdbstub` uses the same one by default.
+        let mut serial_port = unsafe { SerialPort::new(config::DEBUG_CHANNEL_PORT) };
+        serial_port.init(config::debug_channel());
+        IrqSafeMutex::new(serial_port)
+    };
+}
+
+// Sends `data` as a single frame. What `tag` means is up to the sender and whatever
+// is reading on the other end.
+pub fn send(tag: u8, data: &[u8]) {
+    // Interrupts stay off while the port is locked, for the same reason as in `console::_print`
+    let mut port = PORT.lock();
+    let mut frame = Frame::new(|byte| port.send(byte));
+    frame.write(&[tag]);
+    frame.write(&(data.len() as u32).to_le_bytes());
+    frame.write(data);
+    frame.finish();
+}
+
+// Escapes and checksums bytes on their way out, to `send`
+struct Frame<F: FnMut(u8)> {
+    send: F,
+    crc: u16,
+}
+
+impl<F: FnMut(u8)> Frame<F> {
+    fn new(mut send: F) -> Frame<F> {
+        send(FLAG);
+        Frame { send, crc: 0xFFFF }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.crc = crc16_update(self.crc, byte);
+            self.write_escaped(byte);
+        }
+    }
+
+    fn write_escaped(&mut self, byte: u8) {
+        if byte == FLAG || byte == ESCAPE {
+            (self.send)(ESCAPE);
+            (self.send)(byte ^ ESCAPE_XOR);
+        } else {
+            (self.send)(byte);
+        }
+    }
+
+    fn finish(mut self) {
+        for byte in self.crc.to_le_bytes() {
+            self.write_escaped(byte);
+        }
+        (self.send)(FLAG);
+    }
+}
+
+fn crc16_update(crc: u16, byte: u8) -> u16 {
+    let mut crc = crc ^ (byte as u16) << 8;
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+    }
+    crc
+}
+
+#[test_case]
+fn test_crc16_known_answer() {
+    // The standard check value for CRC-16/CCITT-FALSE
+    let crc = b"123456789".iter().fold(0xFFFF, |crc, &byte| crc16_update(crc, byte));
+    assert_eq!(crc, 0x29B1);
+}
+
+#[test_case]
+fn test_frame_escaping() {
+    let mut sent = [0u8; 16];
+    let mut len = 0;
+    let mut frame = Frame::new(|byte| {
+        sent[len] = byte;
+        len += 1;
+    });
+    frame.write(&[0x7E, 0x41, 0x7D]);
+    frame.finish();
+    // The CRC (0x9E52) has nothing to escape
+    assert_eq!(&sent[..len], &[FLAG, ESCAPE, 0x5E, 0x41, ESCAPE, 0x5D, 0x52, 0x9E, FLAG]);
+}
@@ -0,0 +1,178 @@
+// Keeps the most recent log messages in memory, like Linux's `dmesg`, whichever
+// console they went to - or even if they scrolled off screen long ago. Once the ring
+// is full, the oldest messages make room for new ones.
+//
+// Every message is stored in one piece (a 2 byte length followed by the text), so it
+// can be handed out as a plain `&str`. A message that doesn't fit before the end of
+// the ring starts over at the beginning, and whatever is left at the end is skipped.
+
+use core::fmt;
+
+use crate::config::DMESG_SIZE;
+use crate::sync::IrqSafeMutex;
+
+// Longer messages are cut off
+const MAX_MESSAGE_LEN: usize = 512;
+const HEADER_LEN: usize = 2;
+// In place of a length, marks the rest of the ring as skipped
+const PADDING: u16 = u16::MAX;
+
+static RING: IrqSafeMutex<Ring> = IrqSafeMutex::new(Ring::new());
+
+struct Ring {
+    bytes: [u8; DMESG_SIZE],
+    // Both only ever count up, the offset into `bytes` is the count modulo the size.
+    // `start` is where the oldest message is, `end` where the next one goes.
+    start: usize,
+    end: usize,
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring {
+            bytes: [0; DMESG_SIZE],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    fn push(&mut self, message: &[u8]) {
+        let len = HEADER_LEN + message.len();
+        let left = DMESG_SIZE - self.end % DMESG_SIZE;
+        if len > left {
+            // Doesn't fit before the end, so skip what's left there
+            self.make_room(left);
+            if left >= HEADER_LEN {
+                self.write_header(self.end, PADDING);
+            }
+            self.end += left;
+        }
+        self.make_room(len);
+        self.write_header(self.end, message.len() as u16);
+        let offset = (self.end + HEADER_LEN) % DMESG_SIZE;
+        self.bytes[offset..offset + message.len()].copy_from_slice(message);
+        self.end += len;
+    }
+
+    // Throws away the oldest messages until there are `len` free bytes after `end`
+    fn make_room(&mut self, len: usize) {
+        while self.end + len - self.start > DMESG_SIZE {
+            self.start = self.next(self.start);
+        }
+    }
+
+    // Where the message after the one at `position` starts
+    fn next(&self, position: usize) -> usize {
+        let left = DMESG_SIZE - position % DMESG_SIZE;
+        if left < HEADER_LEN {
+            return position + left;
+        }
+        match self.read_header(position) {
+            PADDING => position + left,
+            len => position + HEADER_LEN + len as usize,
+        }
+    }
+
+    fn for_each<F: FnMut(&str)>(&self, mut f: F) {
+        let mut position = self.start;
+        while position < self.end {
+            let left = DMESG_SIZE - position % DMESG_SIZE;
+            if left >= HEADER_LEN && self.read_header(position) != PADDING {
+                let offset = (position + HEADER_LEN) % DMESG_SIZE;
+                let len = self.read_header(position) as usize;
+                // Messages are only ever cut off at character boundaries
+                f(core::str::from_utf8(&self.bytes[offset..offset + len]).unwrap_or(""));
+            }
+            position = self.next(position);
+        }
+    }
+
+    fn read_header(&self, position: usize) -> u16 {
+        let offset = position % DMESG_SIZE;
+        u16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]])
+    }
+
+    fn write_header(&mut self, position: usize, value: u16) {
+        let offset = position % DMESG_SIZE;
+        self.bytes[offset..offset + HEADER_LEN].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+// Formats a message on the stack first, since we need to know how long it is
+// before it goes into the ring
+struct Message {
+    bytes: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl fmt::Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(MAX_MESSAGE_LEN - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+// Adds a message to the log
+pub fn record(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mut message = Message {
+        bytes: [0; MAX_MESSAGE_LEN],
+        len: 0,
+    };
+    let _ = message.write_fmt(args);
+    RING.lock().push(&message.bytes[..message.len]);
+}
+
+// Calls `f` with every message that's still around, oldest first. Nothing can be
+// logged until `f` is done with the last one, so don't log from it.
+pub fn for_each<F: FnMut(&str)>(f: F) {
+    RING.lock().for_each(f);
+}
+
+// `for_each` for the crash screen, which can't wait for whoever it interrupted to
+// let go of the log. Unsafe for the same reasons as `crash::force_lock`.
+pub unsafe fn force_for_each<F: FnMut(&str)>(f: F) {
+    crate::crash::force_lock(&RING).for_each(f);
+}
+
+// Prints the whole log again
+pub fn dump() {
+    for_each(|message| crate::println!("{}", message));
+}
+
+#[test_case]
+fn test_record_keeps_messages() {
+    record(format_args!("test_record_keeps_messages {}", 42));
+    let mut last = [0; 64];
+    let mut len = 0;
+    for_each(|message| {
+        len = message.len().min(last.len());
+        last[..len].copy_from_slice(&message.as_bytes()[..len]);
+    });
+    assert_eq!(&last[..len], b"test_record_keeps_messages 42");
+}
+
+#[test_case]
+fn test_ring_drops_oldest() {
+    // Too big for the stack, and the global ring is there for everyone to see
+    static TEST_RING: IrqSafeMutex<Ring> = IrqSafeMutex::new(Ring::new());
+    let mut ring = TEST_RING.lock();
+    let message = [b'x'; 100];
+    for _ in 0..2 * DMESG_SIZE / message.len() {
+        ring.push(&message);
+    }
+    ring.push(b"last");
+    let mut count = 0;
+    let mut last_len = 0;
+    ring.for_each(|message| {
+        count += 1;
+        last_len = message.len();
+    });
+    assert!(count > 0 && count <= DMESG_SIZE / (HEADER_LEN + message.len()) + 1);
+    assert_eq!(last_len, 4);
+}
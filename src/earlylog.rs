@@ -0,0 +1,86 @@
+// Output from before there's anywhere to show it. Until `replay` is called, the
+// kernel log and `early_print!` are collected here instead of going to the console,
+// so they don't get lost when the screen is cleared at boot. `replay` then prints
+// all of it in one go, and everything after that goes straight to the console.
+
+use core::fmt;
+
+use crate::console;
+use crate::sync::IrqSafeMutex;
+
+// Early boot doesn't have much to say, anything past this is dropped
+const EARLYLOG_SIZE: usize = 4096;
+
+static EARLYLOG: IrqSafeMutex<EarlyLog> = IrqSafeMutex::new(EarlyLog {
+    bytes: [0; EARLYLOG_SIZE],
+    len: 0,
+    truncated: false,
+    replayed: false,
+});
+
+struct EarlyLog {
+    bytes: [u8; EARLYLOG_SIZE],
+    len: usize,
+    truncated: bool,
+    replayed: bool,
+}
+
+impl fmt::Write for EarlyLog {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(EARLYLOG_SIZE - self.len);
+        // Cut off at a character boundary, so the whole thing is still a `&str`
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        self.truncated |= len < s.len();
+        Ok(())
+    }
+}
+
+// `print!` that works from the very first instruction of the kernel's entry point
+#[macro_export]
+macro_rules! early_print {
+    ($($arg:tt)*) => ($crate::earlylog::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! early_println {
+    () => ($crate::early_print!("\n"));
+    ($($arg:tt)*) => ($crate::early_print!("{}\n", format_args!($($arg)*)));
+}
+
+// Keeps `args` for later, unless it's been replayed already, in which case this
+// returns false and the caller should print it itself
+pub fn collect(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    let mut earlylog = EARLYLOG.lock();
+    if earlylog.replayed {
+        return false;
+    }
+    let _ = earlylog.write_fmt(args);
+    true
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    if !collect(args) {
+        console::_print(args);
+    }
+}
+
+// Prints everything collected so far, once the console is ready for it
+pub fn replay() {
+    let mut earlylog = EARLYLOG.lock();
+    if earlylog.replayed {
+        return;
+    }
+    // Still holding on to the lock, so nothing new gets printed in between
+    let text = core::str::from_utf8(&earlylog.bytes[..earlylog.len]).unwrap_or("");
+    console::_print(format_args!("{}", text));
+    if earlylog.truncated {
+        console::_print(format_args!("[early boot output truncated]\n"));
+    }
+    earlylog.replayed = true;
+}
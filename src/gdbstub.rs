@@ -0,0 +1,555 @@
+// A small GDB remote stub, for debugging on real hardware where there's no QEMU
+// gdbserver to lean on. Connect the machine's second serial port to the host and:
+//     (gdb) target remote /dev/ttyUSB0
+// It covers what an interactive session needs: reading and writing registers and
+// memory, software breakpoints (int3) and single stepping (the trap flag).
+//
+// GDB only ever talks to us while the kernel is stopped. Once the stub is in use, the
+// breakpoint (#BP) exception handler, and the debug (#DB) one after a single step,
+// call `handle_trap` with the registers of whatever was interrupted, and it serves
+// GDB's requests until GDB says to continue or step. Whatever GDB changed in the
+// registers is written back by the handler on the way out. Call `breakpoint` to stop
+// and wait for GDB in the first place. Until then, an int3 is left to whatever
+// handles it otherwise.
+//
+// Memory is read and written as asked, so pointing GDB at an unmapped address
+// faults the kernel. Keep that in mind when poking around.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+use crate::config;
+use crate::crash::Registers;
+use crate::serial::SerialPort;
+use crate::sync::IrqSafeMutex;
+
+// What we tell GDB it may send us, and so how big a packet can get
+const PACKET_SIZE: usize = 0x1000;
+// Memory reads are sent back in hex, which has to fit in one packet
+const MAX_MEMORY_READ: usize = PACKET_SIZE / 2 - 16;
+const MAX_BREAKPOINTS: usize = 16;
+const INT3: u8 = 0xcc;
+// The trap flag in RFLAGS, which makes the CPU stop after the next instruction
+const TRAP_FLAG: u64 = 1 << 8;
+// Every stop is reported as SIGTRAP
+const STOP_REPLY: &[u8] = b"S05";
+
+// Whether breakpoints are for GDB, since `breakpoint`, until GDB detaches
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// Whether GDB asked for a single step, so the next debug exception is for us
+static STEPPING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref STUB: IrqSafeMutex<Stub> = {
+        // The port comes from the kernel config
+        let mut port = unsafe { SerialPort::new(config::GDB_STUB_PORT) };
+        port.init(config::gdb_stub());
+        IrqSafeMutex::new(Stub {
+            port,
+            breakpoints: [None; MAX_BREAKPOINTS],
+            resumed: false,
+        })
+    };
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    // The byte the int3 went over
+    original: u8,
+}
+
+struct Stub {
+    port: SerialPort,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    // Whether GDB told us to continue or step, and so is waiting to hear we stopped
+    resumed: bool,
+}
+
+// Stops the kernel right here until GDB connects and lets it carry on
+pub fn breakpoint() {
+    ACTIVE.store(true, Ordering::SeqCst);
+    x86_64::instructions::interrupts::int3();
+}
+
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+pub fn is_stepping() -> bool {
+    STEPPING.load(Ordering::SeqCst)
+}
+
+// Hands control to GDB. `registers` are those of the code that trapped, and
+// whatever GDB changes in them should be restored on return. Returns false, having
+// done nothing, for a trap inside the stub itself (GDB put a breakpoint in here),
+// which can't be served or returned from and so is fatal.
+pub fn handle_trap(registers: &mut Registers) -> bool {
+    STEPPING.store(false, Ordering::SeqCst);
+    let mut stub = match STUB.try_lock() {
+        Some(stub) => stub,
+        None => return false,
+    };
+    registers.rflags &= !TRAP_FLAG;
+    // The CPU is already past an int3 we put in, GDB expects to be on it
+    if stub.breakpoint_at(registers.rip.wrapping_sub(1)).is_some() {
+        registers.rip -= 1;
+    }
+    if stub.resumed {
+        stub.resumed = false;
+        stub.send_packet(STOP_REPLY);
+    }
+    stub.serve(registers);
+    true
+}
+
+impl Stub {
+    // Answers GDB until it wants the kernel running again
+    fn serve(&mut self, registers: &mut Registers) {
+        let mut packet = [0; PACKET_SIZE];
+        let mut reply = Reply::new();
+        loop {
+            let len = self.receive_packet(&mut packet);
+            let packet = &packet[..len];
+            reply.clear();
+            let (command, arguments) = match packet.split_first() {
+                Some((&command, arguments)) => (command, arguments),
+                None => continue,
+            };
+            match command {
+                b'?' => reply.push(STOP_REPLY),
+                b'g' => write_registers(&mut reply, registers),
+                b'G' => match read_registers(arguments, registers) {
+                    Some(()) => reply.push(b"OK"),
+                    None => reply.push(b"E01"),
+                },
+                b'm' => {
+                    if read_memory(&mut reply, arguments).is_none() {
+                        reply.clear();
+                        reply.push(b"E01");
+                    }
+                }
+                b'M' => match write_memory(arguments) {
+                    Some(()) => reply.push(b"OK"),
+                    None => reply.push(b"E01"),
+                },
+                b'Z' | b'z' => match self.update_breakpoint(command == b'Z', arguments) {
+                    Some(true) => reply.push(b"OK"),
+                    Some(false) => reply.push(b"E01"),
+                    // Not a kind of breakpoint we do, so GDB falls back to something else
+                    None => {}
+                },
+                b'c' | b's' => {
+                    if let Some(address) = parse_hex(arguments) {
+                        registers.rip = address;
+                    }
+                    if command == b's' {
+                        registers.rflags |= TRAP_FLAG;
+                        STEPPING.store(true, Ordering::SeqCst);
+                    }
+                    self.resumed = true;
+                    return;
+                }
+                b'D' => {
+                    // GDB is going away, so take our breakpoints out first
+                    self.remove_all_breakpoints();
+                    ACTIVE.store(false, Ordering::SeqCst);
+                    self.send_packet(b"OK");
+                    return;
+                }
+                // There's nothing to kill, so just carry on
+                b'k' => return,
+                b'H' => reply.push(b"OK"),
+                b'q' if arguments.starts_with(b"Supported") => {
+                    reply.push(b"PacketSize=");
+                    reply.push_hex(&(PACKET_SIZE as u16).to_be_bytes());
+                }
+                b'q' if arguments == b"Attached" => reply.push(b"1"),
+                // Anything else we don't support, which an empty reply tells GDB
+                _ => {}
+            }
+            self.send_packet(reply.as_bytes());
+        }
+    }
+
+    // Waits for a packet with a good checksum and returns its length. Everything
+    // outside of packets (acks, ^C) is ignored, we're stopped already.
+    fn receive_packet(&mut self, packet: &mut [u8; PACKET_SIZE]) -> usize {
+        loop {
+            while self.read_byte() != b'$' {}
+            let mut len = 0;
+            let mut overlong = false;
+            loop {
+                let byte = self.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+                // GDB knows our packet size, so this shouldn't happen. If it does,
+                // the packet is refused like a corrupted one.
+                if len == packet.len() {
+                    overlong = true;
+                } else {
+                    packet[len] = byte;
+                    len += 1;
+                }
+            }
+            let expected = [self.read_byte(), self.read_byte()];
+            if !overlong && parse_hex(&expected) == Some(checksum(&packet[..len]) as u64) {
+                self.port.send(b'+');
+                return len;
+            }
+            self.port.send(b'-');
+        }
+    }
+
+    // Sends `data` as a packet, again and again until GDB acknowledges it
+    fn send_packet(&mut self, data: &[u8]) {
+        let checksum = checksum(data);
+        loop {
+            self.port.send(b'$');
+            for &byte in data {
+                self.port.send(byte);
+            }
+            self.port.send(b'#');
+            self.port.send(HEX_DIGITS[(checksum >> 4) as usize]);
+            self.port.send(HEX_DIGITS[(checksum & 0xf) as usize]);
+            match self.read_byte() {
+                b'-' => continue,
+                _ => return,
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.port.receive() {
+                return byte;
+            }
+        }
+    }
+
+    fn breakpoint_at(&self, address: u64) -> Option<usize> {
+        self.breakpoints.iter().position(|breakpoint| {
+            matches!(breakpoint, Some(breakpoint) if breakpoint.address == address)
+        })
+    }
+
+    // Handles `Z0,address,kind` and `z0,address,kind`. Returns None for anything
+    // that isn't a software breakpoint, otherwise whether it worked.
+    fn update_breakpoint(&mut self, insert: bool, arguments: &[u8]) -> Option<bool> {
+        let mut fields = arguments.split(|&byte| byte == b',');
+        if fields.next()? != b"0" {
+            return None;
+        }
+        let address = match fields.next().and_then(parse_hex) {
+            Some(address) => address,
+            None => return Some(false),
+        };
+        let existing = self.breakpoint_at(address);
+        if insert {
+            if existing.is_some() {
+                return Some(true);
+            }
+            let slot = match self.breakpoints.iter().position(Option::is_none) {
+                Some(slot) => slot,
+                None => return Some(false),
+            };
+            let original = unsafe { (address as *const u8).read_volatile() };
+            unsafe { write_text(address, INT3) };
+            self.breakpoints[slot] = Some(Breakpoint { address, original });
+        } else if let Some(slot) = existing {
+            self.remove_breakpoint(slot);
+        }
+        Some(true)
+    }
+
+    fn remove_breakpoint(&mut self, slot: usize) {
+        if let Some(breakpoint) = self.breakpoints[slot].take() {
+            unsafe { write_text(breakpoint.address, breakpoint.original) };
+        }
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for slot in 0..MAX_BREAKPOINTS {
+            self.remove_breakpoint(slot);
+        }
+    }
+}
+
+// Writes a byte of kernel code (or anything else mapped read only). Write protection
+// is off just for that one write, with interrupts already off in the trap handler.
+unsafe fn write_text(address: u64, byte: u8) {
+    let flags = Cr0::read();
+    Cr0::write(flags - Cr0Flags::WRITE_PROTECT);
+    (address as *mut u8).write_volatile(byte);
+    Cr0::write(flags);
+}
+
+// The registers in the order GDB's x86-64 `g` packet has them: the 16 general purpose
+// ones and RIP as 8 bytes each, then EFLAGS and the segment registers as 4 each.
+// We don't keep the segment registers, so they're sent as unavailable ("x").
+fn write_registers(reply: &mut Reply, registers: &Registers) {
+    for value in general_registers(registers) {
+        reply.push_hex(&value.to_le_bytes());
+    }
+    reply.push_hex(&(registers.rflags as u32).to_le_bytes());
+    for _ in 0..6 {
+        reply.push(b"xxxxxxxx");
+    }
+}
+
+fn read_registers(arguments: &[u8], registers: &mut Registers) -> Option<()> {
+    let mut values = [0; 17];
+    for (index, value) in values.iter_mut().enumerate() {
+        *value = parse_le(arguments.get(index * 16..index * 16 + 16)?)?;
+    }
+    let eflags = parse_le(arguments.get(17 * 16..17 * 16 + 8)?)?;
+    let [rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8, r9, r10, r11, r12, r13, r14, r15, rip] = values;
+    *registers = Registers {
+        rax,
+        rbx,
+        rcx,
+        rdx,
+        rsi,
+        rdi,
+        rbp,
+        r8,
+        r9,
+        r10,
+        r11,
+        r12,
+        r13,
+        r14,
+        r15,
+        rip,
+        rsp,
+        rflags: eflags,
+    };
+    Some(())
+}
+
+fn general_registers(registers: &Registers) -> [u64; 17] {
+    let r = registers;
+    [
+        r.rax, r.rbx, r.rcx, r.rdx, r.rsi, r.rdi, r.rbp, r.rsp, r.r8, r.r9, r.r10, r.r11, r.r12,
+        r.r13, r.r14, r.r15, r.rip,
+    ]
+}
+
+// `m address,length`
+fn read_memory(reply: &mut Reply, arguments: &[u8]) -> Option<()> {
+    let (address, len) = split_address_length(arguments)?;
+    for offset in 0..len.min(MAX_MEMORY_READ as u64) {
+        let byte = unsafe { ((address + offset) as *const u8).read_volatile() };
+        reply.push_hex(&[byte]);
+    }
+    Some(())
+}
+
+// `M address,length:data`, where GDB also uses this to put its breakpoints in when
+// it doesn't trust `Z0`, so it has to be able to write kernel code
+fn write_memory(arguments: &[u8]) -> Option<()> {
+    let colon = arguments.iter().position(|&byte| byte == b':')?;
+    let (address, len) = split_address_length(&arguments[..colon])?;
+    let data = &arguments[colon + 1..];
+    if data.len() as u64 != len * 2 {
+        return None;
+    }
+    for (offset, pair) in data.chunks(2).enumerate() {
+        let byte = parse_hex(pair)? as u8;
+        unsafe { write_text(address + offset as u64, byte) };
+    }
+    Some(())
+}
+
+fn split_address_length(arguments: &[u8]) -> Option<(u64, u64)> {
+    let comma = arguments.iter().position(|&byte| byte == b',')?;
+    Some((parse_hex(&arguments[..comma])?, parse_hex(&arguments[comma + 1..])?))
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+// What follows the `#` of a packet, in hex: all of its data bytes added up
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0, |value, &digit| {
+        Some(value << 4 | (digit as char).to_digit(16)? as u64)
+    })
+}
+
+// A little endian value sent as hex, two digits per byte
+fn parse_le(digits: &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (index, pair) in digits.chunks(2).enumerate() {
+        value |= parse_hex(pair)? << (index * 8);
+    }
+    Some(value)
+}
+
+// Builds up a reply packet's data
+struct Reply {
+    bytes: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Reply {
+        Reply {
+            bytes: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(PACKET_SIZE - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(&[HEX_DIGITS[(byte >> 4) as usize], HEX_DIGITS[(byte & 0xf) as usize]]);
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+#[test_case]
+fn test_checksum() {
+    assert_eq!(checksum(b""), 0);
+    assert_eq!(checksum(b"g"), 0x67);
+    assert_eq!(checksum(b"OK"), 0x9a);
+    // Wrapping around, as it does for anything but the shortest packets
+    assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+}
+
+#[test_case]
+fn test_parse_hex() {
+    assert_eq!(parse_hex(b"0"), Some(0));
+    assert_eq!(parse_hex(b"ffffffff8000beef"), Some(0xffff_ffff_8000_beef));
+    assert_eq!(parse_hex(b"AbC"), Some(0xabc));
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_hex(b"12g4"), None);
+    assert_eq!(parse_hex(b"10000000000000000"), None);
+    assert_eq!(parse_le(b"efbeadde"), Some(0xdead_beef));
+    assert_eq!(parse_le(b"efbeadd"), Some(0x0dad_beef));
+    assert_eq!(parse_le(b"xx"), None);
+}
+
+#[test_case]
+fn test_registers() {
+    let registers = Registers {
+        rax: 0x1122_3344_5566_7788,
+        r15: 15,
+        rip: 0xffff_8000_0000_1000,
+        rsp: 0x4444_0000,
+        rflags: 0x1_0000_0202,
+        ..Registers::default()
+    };
+    let mut reply = Reply::new();
+    write_registers(&mut reply, &registers);
+    let packet = reply.as_bytes();
+    // 17 registers, EFLAGS and 6 unavailable segment registers
+    assert_eq!(packet.len(), 17 * 16 + 8 + 6 * 8);
+    assert_eq!(&packet[..16], b"8877665544332211");
+    assert_eq!(&packet[16 * 16..17 * 16], b"0010000000800fff");
+    // EFLAGS is only 32 bits
+    assert_eq!(&packet[17 * 16..17 * 16 + 8], b"02020000");
+    assert_eq!(&packet[17 * 16 + 8..17 * 16 + 16], b"xxxxxxxx");
+
+    // `G` takes what `g` gave (the segment registers are ignored)
+    let mut read = Registers::default();
+    assert_eq!(read_registers(packet, &mut read), Some(()));
+    assert_eq!(general_registers(&read), general_registers(&registers));
+    assert_eq!(read.rflags, 0x202);
+    // Too short, or not hex
+    assert_eq!(read_registers(&packet[..17 * 16], &mut read), None);
+    let mut bad = [0; 17 * 16 + 8];
+    bad.copy_from_slice(&packet[..17 * 16 + 8]);
+    bad[3] = b'z';
+    assert_eq!(read_registers(&bad, &mut read), None);
+}
+
+#[test_case]
+fn test_memory() {
+    use core::fmt::Write;
+
+    let mut memory = [0x12u8, 0x34, 0xab, 0xcd];
+    let address = memory.as_mut_ptr() as u64;
+    let mut arguments = Reply::new();
+    write!(Text(&mut arguments), "{:x},3", address).unwrap();
+    let mut reply = Reply::new();
+    assert_eq!(read_memory(&mut reply, arguments.as_bytes()), Some(()));
+    assert_eq!(reply.as_bytes(), b"1234ab");
+    assert_eq!(read_memory(&mut reply, b"1000"), None);
+    assert_eq!(read_memory(&mut reply, b"1000,"), None);
+
+    arguments.clear();
+    write!(Text(&mut arguments), "{:x},2:beef", address + 1).unwrap();
+    assert_eq!(write_memory(arguments.as_bytes()), Some(()));
+    assert_eq!(unsafe { core::ptr::read_volatile(&memory) }, [0x12, 0xbe, 0xef, 0xcd]);
+    // The length has to match the data
+    arguments.clear();
+    write!(Text(&mut arguments), "{:x},2:be", address).unwrap();
+    assert_eq!(write_memory(arguments.as_bytes()), None);
+    assert_eq!(write_memory(b"1000,1"), None);
+}
+
+#[test_case]
+fn test_update_breakpoint() {
+    use core::fmt::Write;
+
+    // Never run, so it doesn't matter what the int3 goes over
+    let mut code = [0x90u8; 2];
+    let address = code.as_mut_ptr() as u64 + 1;
+    // Nothing here uses the port
+    let mut stub = Stub {
+        port: unsafe { SerialPort::new(config::GDB_STUB_PORT) },
+        breakpoints: [None; MAX_BREAKPOINTS],
+        resumed: false,
+    };
+    let mut arguments = Reply::new();
+    write!(Text(&mut arguments), "0,{:x},1", address).unwrap();
+
+    assert_eq!(stub.update_breakpoint(true, arguments.as_bytes()), Some(true));
+    assert_eq!(stub.breakpoint_at(address), Some(0));
+    assert_eq!(unsafe { core::ptr::read_volatile(&code) }, [0x90, INT3]);
+    // Inserting it twice is fine, and it still only takes one slot
+    assert_eq!(stub.update_breakpoint(true, arguments.as_bytes()), Some(true));
+    assert_eq!(stub.breakpoints.iter().filter(|slot| slot.is_some()).count(), 1);
+    assert_eq!(stub.update_breakpoint(false, arguments.as_bytes()), Some(true));
+    assert_eq!(stub.breakpoint_at(address), None);
+    assert_eq!(unsafe { core::ptr::read_volatile(&code) }, [0x90, 0x90]);
+
+    // Hardware breakpoints and watchpoints aren't ours, and a bad address is an error
+    assert_eq!(stub.update_breakpoint(true, b"1,1000,1"), None);
+    assert_eq!(stub.update_breakpoint(true, b"2,1000,8"), None);
+    assert_eq!(stub.update_breakpoint(true, b"0,xyz,1"), Some(false));
+    assert_eq!(stub.update_breakpoint(true, b"0"), Some(false));
+}
+
+// Lets tests format packet arguments into a `Reply`
+#[cfg(test)]
+struct Text<'a>(&'a mut Reply);
+
+#[cfg(test)]
+impl core::fmt::Write for Text<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.push(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+// Our own Global Descriptor Table. Long mode barely looks at segments, but the CPU
+// still wants a code segment to say it's running 64-bit kernel code, and the TSS
+// (for interrupt stacks) and user segments (for ring 3) can only go in a GDT. The
+// bootloader's GDT is somewhere in memory we don't own, so we don't add to that.
+//
+// The TSS is where the interrupt stack table is: stacks the CPU switches to before
+// calling an exception handler, whatever state the stack it was on is in. A double
+// fault from a kernel stack overflow can't be handled on the stack that overflowed,
+// and without a stack to go to it turns into a triple fault, which is a reboot.
+
+use lazy_static::lazy_static;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+// Which IST entry the double fault handler runs on
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const DOUBLE_FAULT_STACK_SIZE: usize = 5 * 4096;
+
+// The double fault stack. There's no guard page below it yet, so a double fault
+// handler that overflows this one writes over whatever's next to it. The CPU aligns
+// the stack pointer itself when it switches stacks.
+static mut DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        let mut tss = TaskStateSegment::new();
+        // Stacks grow down, so the CPU wants where it ends
+        let stack_start = VirtAddr::from_ptr(core::ptr::addr_of!(DOUBLE_FAULT_STACK));
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            stack_start + DOUBLE_FAULT_STACK_SIZE;
+        tss
+    };
+}
+
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        (gdt, Selectors { code, data, tss })
+    };
+}
+
+pub struct Selectors {
+    pub code: SegmentSelector,
+    pub data: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+// Loads the GDT and points every segment register at it, so nothing is left
+// referring to the bootloader's, and loads the TSS
+pub fn init() {
+    GDT.0.load();
+    let selectors = &GDT.1;
+    // Safe since the selectors are for the GDT that was just loaded, and say the same
+    // as the bootloader's did: 64-bit ring 0 code, and data anyone can use
+    unsafe {
+        CS::set_reg(selectors.code);
+        DS::set_reg(selectors.data);
+        ES::set_reg(selectors.data);
+        SS::set_reg(selectors.data);
+        load_tss(selectors.tss);
+    }
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+#[test_case]
+fn test_double_fault_stack_in_tss() {
+    let top = TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize].as_u64();
+    let stack = core::ptr::addr_of!(DOUBLE_FAULT_STACK) as u64;
+    assert_eq!(top, stack + DOUBLE_FAULT_STACK_SIZE as u64);
+}
+
+#[test_case]
+fn test_segments_reloaded() {
+    assert_eq!(CS::get_reg(), selectors().code);
+    assert_eq!(SS::get_reg(), selectors().data);
+}
@@ -0,0 +1,128 @@
+// The Interrupt Descriptor Table: which handler the CPU calls for every exception and
+// hardware interrupt. Handlers are `extern "x86-interrupt"` functions, which the
+// compiler gives the prologue and `iretq` the CPU expects, and they all get added
+// to `IDT` below.
+//
+// A vector with no handler is still an entry, just one marked not present. The CPU
+// treats calling one of those as a general protection fault.
+//
+// The CPU's own exceptions are the first 32 vectors, and their handlers are in
+// `exceptions`. Hardware interrupts come after, with their handlers in the drivers
+// they're for. They come through the I/O APIC (see `ioapic`) when there is one, and
+// the PIC (see `pic`) otherwise, on the same vectors either way. Drivers go through
+// `enable_irq` and `end_of_interrupt` here, so they don't need to know which.
+//
+// Every handler counts itself with `record`, see `stats`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::InterruptDescriptorTable;
+
+use crate::acpi;
+use crate::apic;
+use crate::ioapic;
+use crate::keyboard;
+use crate::mouse;
+use crate::pic;
+use crate::pit;
+use crate::serial;
+
+mod exceptions;
+pub mod nmi;
+pub mod spurious;
+mod stats;
+
+pub use stats::{record, stats, Stats};
+
+// Whether the ISA IRQs go through the I/O APIC, with the PIC masked
+static IO_APIC_ROUTING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        exceptions::install(&mut idt);
+        nmi::install(&mut idt);
+        idt[pic::vector(pit::IRQ) as usize].set_handler_fn(pit::timer_interrupt_handler);
+        idt[pic::vector(keyboard::IRQ) as usize].set_handler_fn(keyboard::keyboard_interrupt_handler);
+        idt[pic::vector(mouse::IRQ) as usize].set_handler_fn(mouse::mouse_interrupt_handler);
+        idt[pic::vector(serial::IRQ) as usize].set_handler_fn(serial::serial_interrupt_handler);
+        idt[apic::timer::VECTOR as usize].set_handler_fn(apic::timer::timer_interrupt_handler);
+        spurious::install(&mut idt);
+        idt
+    };
+}
+
+// Has to come after `gdt::init`, the handlers run with the code segment it sets up
+pub fn init_idt() {
+    IDT.load();
+}
+
+// Sets up the interrupt controllers and turns interrupts on. The PIC is always
+// remapped, since it can send spurious interrupts even when masked, and is only used
+// when the I/O APIC can't be.
+pub fn init_controllers(physical_memory_offset: u64) {
+    pic::init();
+    if !apic::init(physical_memory_offset) {
+        log::warn!("no local APIC, interrupts only go through the PIC");
+    } else if !acpi::init(physical_memory_offset) || !ioapic::init(physical_memory_offset) {
+        log::warn!("local APIC {} enabled, but no I/O APIC, IRQs go through the PIC", apic::id());
+    } else {
+        pic::disable();
+        IO_APIC_ROUTING.store(true, Ordering::SeqCst);
+        log::info!("local APIC {} enabled ({:?}), IRQs go through the I/O APIC", apic::id(), apic::mode());
+    }
+    x86_64::instructions::interrupts::enable();
+}
+
+pub fn routed_by_io_apic() -> bool {
+    IO_APIC_ROUTING.load(Ordering::Relaxed)
+}
+
+// Lets ISA IRQ `irq` through to this CPU, on `pic::vector(irq)`
+pub fn enable_irq(irq: u8) {
+    if !routed_by_io_apic() {
+        pic::unmask(irq);
+        return;
+    }
+    let (gsi, trigger, polarity) = ioapic::isa_irq(irq);
+    if let Err(err) = ioapic::route(gsi, pic::vector(irq), apic::id(), trigger, polarity) {
+        log::error!("can't route IRQ {}: {}", irq, err);
+    }
+}
+
+// For the end of ISA IRQ handlers, where interrupts are off already
+pub fn end_of_interrupt(irq: u8) {
+    if routed_by_io_apic() {
+        apic::end_of_interrupt();
+    } else {
+        pic::end_of_interrupt(pic::vector(irq));
+    }
+}
+
+// Stops for a moment to show where we are, then carries on. Like any other print,
+// don't use it somewhere that holds the console lock.
+#[macro_export]
+macro_rules! breakpoint {
+    () => {
+        $crate::interrupts::_breakpoint()
+    };
+}
+
+#[doc(hidden)]
+#[inline(always)]
+pub fn _breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+#[test_case]
+fn test_breakpoint_exception() {
+    // Getting past this at all is the test
+    breakpoint!();
+}
+
+#[test_case]
+fn test_idt_loaded() {
+    let pointer = x86_64::instructions::tables::sidt();
+    assert_eq!(pointer.base.as_u64(), &*IDT as *const _ as u64);
+    assert_eq!(pointer.limit as usize, core::mem::size_of::<InterruptDescriptorTable>() - 1);
+}
@@ -0,0 +1,456 @@
+// Handlers for the 32 vectors the CPU keeps for its own exceptions. Apart from
+// breakpoints, single steps for `gdbstub` and NMIs (which have `nmi` to themselves),
+// there's no recovering from any of them yet, so they all end on the crash screen
+// the same way:
+//     EXCEPTION: PAGE FAULT (vector 14, error code 0x2)
+//     writing 0xdeadbeef000: page not present, in kernel mode
+// The first line is the same for every exception, the rest is whatever more the
+// handler knows about it. Then the CPU stays halted.
+//
+// Breakpoints and debug exceptions come in through `trap_entry` instead of the
+// x86-interrupt ABI, so GDB gets every register of what it stopped.
+//
+// The reserved vectors (and coprocessor segment overrun, which nothing since the 386
+// raises) can't be given a handler. The CPU never raises those, and an `int` to one
+// turns into a general protection fault like any other vector without a handler.
+
+use core::arch::global_asm;
+use core::fmt;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+use crate::cpu::mca;
+use crate::crash::{self, Registers};
+use crate::gdbstub;
+use crate::gdt;
+use crate::println;
+
+const NAMES: [&str; 32] = [
+    "DIVIDE ERROR",
+    "DEBUG",
+    "NON-MASKABLE INTERRUPT",
+    "BREAKPOINT",
+    "OVERFLOW",
+    "BOUND RANGE EXCEEDED",
+    "INVALID OPCODE",
+    "DEVICE NOT AVAILABLE",
+    "DOUBLE FAULT",
+    "COPROCESSOR SEGMENT OVERRUN",
+    "INVALID TSS",
+    "SEGMENT NOT PRESENT",
+    "STACK-SEGMENT FAULT",
+    "GENERAL PROTECTION FAULT",
+    "PAGE FAULT",
+    "RESERVED",
+    "X87 FLOATING-POINT EXCEPTION",
+    "ALIGNMENT CHECK",
+    "MACHINE CHECK",
+    "SIMD FLOATING-POINT EXCEPTION",
+    "VIRTUALIZATION EXCEPTION",
+    "CONTROL PROTECTION EXCEPTION",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "HYPERVISOR INJECTION EXCEPTION",
+    "VMM COMMUNICATION EXCEPTION",
+    "SECURITY EXCEPTION",
+    "RESERVED",
+];
+
+const DIVIDE_ERROR: u8 = 0;
+const DEBUG: u8 = 1;
+const BREAKPOINT: u8 = 3;
+const OVERFLOW: u8 = 4;
+const BOUND_RANGE_EXCEEDED: u8 = 5;
+const INVALID_OPCODE: u8 = 6;
+const DEVICE_NOT_AVAILABLE: u8 = 7;
+const DOUBLE_FAULT: u8 = 8;
+const INVALID_TSS: u8 = 10;
+const SEGMENT_NOT_PRESENT: u8 = 11;
+const STACK_SEGMENT_FAULT: u8 = 12;
+const GENERAL_PROTECTION_FAULT: u8 = 13;
+const PAGE_FAULT: u8 = 14;
+const X87_FLOATING_POINT: u8 = 16;
+const ALIGNMENT_CHECK: u8 = 17;
+const MACHINE_CHECK: u8 = 18;
+const SIMD_FLOATING_POINT: u8 = 19;
+const VIRTUALIZATION: u8 = 20;
+const CONTROL_PROTECTION: u8 = 21;
+const HYPERVISOR_INJECTION: u8 = 28;
+const VMM_COMMUNICATION: u8 = 29;
+const SECURITY: u8 = 30;
+
+// How much of the code around RIP a general protection fault shows
+const CODE_BEFORE: u64 = 8;
+const CODE_AFTER: u64 = 16;
+
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    // Safe since the entry points below do what the CPU expects of a handler, and end
+    // in an `iretq`
+    unsafe {
+        idt.debug.set_handler_addr(entry_address(boredos_debug_entry));
+        idt.breakpoint.set_handler_addr(entry_address(boredos_breakpoint_entry));
+    }
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    // Safe since the IST entry is set up in `gdt` and nothing else uses it
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception.set_handler_fn(control_protection_handler);
+    idt.hv_injection_exception.set_handler_fn(hypervisor_injection_handler);
+    idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_handler);
+}
+
+// The entry points for #BP and #DB. Neither has an error code, so the CPU's frame is
+// just RIP, CS, RFLAGS, RSP and SS. Each pushes its vector and saves every general
+// purpose register under that, laid out as a `Registers`, copies RIP, RSP and RFLAGS
+// in from the frame and calls `trap` with it all. On the way back it's the other way
+// around, so whatever `trap` changed (GDB, usually) is what the CPU goes back to.
+global_asm!(
+    ".global boredos_breakpoint_entry",
+    "boredos_breakpoint_entry:",
+    "    push {breakpoint}",
+    "    jmp boredos_trap_entry",
+    ".global boredos_debug_entry",
+    "boredos_debug_entry:",
+    "    push {debug}",
+    "boredos_trap_entry:",
+    // RIP, RSP and RFLAGS go at the end of `Registers`, the rest in reverse
+    "    sub rsp, 24",
+    "    push r15",
+    "    push r14",
+    "    push r13",
+    "    push r12",
+    "    push r11",
+    "    push r10",
+    "    push r9",
+    "    push r8",
+    "    push rbp",
+    "    push rdi",
+    "    push rsi",
+    "    push rdx",
+    "    push rcx",
+    "    push rbx",
+    "    push rax",
+    // `Registers` is 144 bytes, then the vector, then the CPU's frame
+    "    mov rax, [rsp + 152]",
+    "    mov [rsp + 120], rax",
+    "    mov rax, [rsp + 176]",
+    "    mov [rsp + 128], rax",
+    "    mov rax, [rsp + 168]",
+    "    mov [rsp + 136], rax",
+    "    mov rdi, rsp",
+    "    mov rsi, [rsp + 144]",
+    // The calling convention wants the stack 16 byte aligned and the direction flag
+    // clear, whatever was interrupted had
+    "    mov rbx, rsp",
+    "    and rsp, -16",
+    "    cld",
+    "    call {trap}",
+    "    mov rsp, rbx",
+    "    mov rax, [rsp + 120]",
+    "    mov [rsp + 152], rax",
+    "    mov rax, [rsp + 128]",
+    "    mov [rsp + 176], rax",
+    "    mov rax, [rsp + 136]",
+    "    mov [rsp + 168], rax",
+    "    pop rax",
+    "    pop rbx",
+    "    pop rcx",
+    "    pop rdx",
+    "    pop rsi",
+    "    pop rdi",
+    "    pop rbp",
+    "    pop r8",
+    "    pop r9",
+    "    pop r10",
+    "    pop r11",
+    "    pop r12",
+    "    pop r13",
+    "    pop r14",
+    "    pop r15",
+    // `Registers`' last three, and the vector
+    "    add rsp, 32",
+    "    iretq",
+    breakpoint = const BREAKPOINT,
+    debug = const DEBUG,
+    trap = sym trap,
+);
+
+extern "C" {
+    fn boredos_breakpoint_entry();
+    fn boredos_debug_entry();
+}
+
+fn entry_address(entry: unsafe extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(entry as usize as u64)
+}
+
+// `int3` is a trap, so the CPU has already moved past it and returning just goes on
+// with the next instruction. Once `gdbstub` is in use, every one is for GDB, and so is
+// every single step it asked for. Any other debug exception is a bug (there are no
+// hardware breakpoints or anything else that would raise one).
+extern "C" fn trap(registers: &mut Registers, vector: u8) {
+    super::record(vector);
+    match vector {
+        BREAKPOINT if gdbstub::is_active() => gdb_trap(BREAKPOINT, registers),
+        BREAKPOINT => println!("EXCEPTION: BREAKPOINT\n{}", registers),
+        DEBUG if gdbstub::is_stepping() => gdb_trap(DEBUG, registers),
+        _ => fatal(DEBUG, None, None, registers),
+    }
+}
+
+// A trap the stub can't take (one inside the stub itself) can't be returned from
+// either: that would run into the int3 again, or on from the middle of it
+fn gdb_trap(vector: u8, registers: &mut Registers) {
+    if !gdbstub::handle_trap(registers) {
+        fatal(vector, None, Some(&"trapped inside the GDB stub"), registers);
+    }
+}
+
+// The registers of whatever was interrupted, as far as they can still be told. The
+// CPU saved the instruction and stack pointers and the flags, and the handler's own
+// frame starts with the interrupted frame pointer. The rest are as the handler left
+// them, which is mostly (not always) as they were.
+#[inline(always)]
+fn interrupted_registers(stack_frame: &InterruptStackFrame) -> Registers {
+    let mut registers = Registers::capture();
+    // Safe as long as the handler has a frame pointer, which everything does
+    registers.rbp = unsafe { (registers.rbp as *const u64).read() };
+    registers.rip = stack_frame.instruction_pointer.as_u64();
+    registers.rsp = stack_frame.stack_pointer.as_u64();
+    registers.rflags = stack_frame.cpu_flags;
+    registers
+}
+
+struct ExceptionReport<'a> {
+    vector: u8,
+    error_code: Option<u64>,
+    details: Option<&'a dyn fmt::Display>,
+}
+
+impl fmt::Display for ExceptionReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EXCEPTION: {} (vector {}", NAMES[self.vector as usize], self.vector)?;
+        if let Some(error_code) = self.error_code {
+            write!(f, ", error code {:#x}", error_code)?;
+        }
+        write!(f, ")")?;
+        if let Some(details) = self.details {
+            write!(f, "\n{}", details)?;
+        }
+        Ok(())
+    }
+}
+
+// Shows the crash screen for exception `vector`, then stops for good
+fn fatal(
+    vector: u8,
+    error_code: Option<u64>,
+    details: Option<&dyn fmt::Display>,
+    registers: &Registers,
+) -> ! {
+    super::record(vector);
+    let report = ExceptionReport {
+        vector,
+        error_code,
+        details,
+    };
+    crash::crash_screen(&report, registers);
+    // The crash screen left interrupts off, so nothing wakes us up again
+    crate::hlt_loop()
+}
+
+// Handlers for the exceptions all there is to say about is which one it was
+macro_rules! fatal_handler {
+    ($handler:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame) {
+            let registers = interrupted_registers(&stack_frame);
+            fatal($vector, None, None, &registers);
+        }
+    };
+    ($handler:ident, $vector:expr, error_code) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            let registers = interrupted_registers(&stack_frame);
+            fatal($vector, Some(error_code), None, &registers);
+        }
+    };
+}
+
+fatal_handler!(divide_error_handler, DIVIDE_ERROR);
+fatal_handler!(overflow_handler, OVERFLOW);
+fatal_handler!(bound_range_exceeded_handler, BOUND_RANGE_EXCEEDED);
+fatal_handler!(invalid_opcode_handler, INVALID_OPCODE);
+fatal_handler!(device_not_available_handler, DEVICE_NOT_AVAILABLE);
+fatal_handler!(x87_floating_point_handler, X87_FLOATING_POINT);
+fatal_handler!(alignment_check_handler, ALIGNMENT_CHECK, error_code);
+fatal_handler!(simd_floating_point_handler, SIMD_FLOATING_POINT);
+fatal_handler!(virtualization_handler, VIRTUALIZATION);
+fatal_handler!(control_protection_handler, CONTROL_PROTECTION, error_code);
+fatal_handler!(hypervisor_injection_handler, HYPERVISOR_INJECTION);
+fatal_handler!(vmm_communication_handler, VMM_COMMUNICATION, error_code);
+fatal_handler!(security_handler, SECURITY, error_code);
+
+// The CPU can't go on after a machine check, not even as far as returning. What
+// went wrong is in the machine check banks.
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let registers = interrupted_registers(&stack_frame);
+    fatal(MACHINE_CHECK, None, Some(&mca::Report), &registers);
+}
+
+// The error code of the exceptions about a segment, which says which selector it was
+struct SelectorErrorCode(u64);
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let table = match (self.0 >> 1) & 0b11 {
+            0 => "GDT",
+            2 => "LDT",
+            _ => "IDT",
+        };
+        write!(f, "selector {} index {}", table, (self.0 >> 3) & 0x1fff)?;
+        if self.0 & 1 != 0 {
+            write!(f, " (during an external event)")?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! selector_handler {
+    ($handler:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            let registers = interrupted_registers(&stack_frame);
+            let selector = SelectorErrorCode(error_code);
+            fatal($vector, Some(error_code), Some(&selector), &registers);
+        }
+    };
+}
+
+selector_handler!(invalid_tss_handler, INVALID_TSS);
+selector_handler!(segment_not_present_handler, SEGMENT_NOT_PRESENT);
+selector_handler!(stack_segment_fault_handler, STACK_SEGMENT_FAULT);
+
+// `address` is what was being accessed, from CR2
+struct PageFaultReport {
+    address: u64,
+    error_code: PageFaultErrorCode,
+}
+
+impl fmt::Display for PageFaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let error_code = self.error_code;
+        let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            "executing"
+        } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            "writing"
+        } else {
+            "reading"
+        };
+        let reason = if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "not allowed"
+        } else {
+            "page not present"
+        };
+        let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+            "user"
+        } else {
+            "kernel"
+        };
+        write!(f, "{} {:#x}: {}, in {} mode", access, self.address, reason, mode)?;
+        if error_code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+            write!(f, ", reserved bit set in a page table")?;
+        }
+        Ok(())
+    }
+}
+
+// For now every page fault is a bug. Once there's demand paging, this is where a
+// page that's meant to be there but isn't yet gets mapped in.
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let registers = interrupted_registers(&stack_frame);
+    let report = PageFaultReport {
+        address: Cr2::read().as_u64(),
+        error_code,
+    };
+    fatal(PAGE_FAULT, Some(error_code.bits()), Some(&report), &registers);
+}
+
+struct GeneralProtectionReport {
+    error_code: u64,
+    rip: u64,
+}
+
+impl fmt::Display for GeneralProtectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Most of these aren't about a segment at all (a bad MSR, a non-canonical
+        // address, a privileged instruction...), and then the error code is 0
+        if self.error_code != 0 {
+            writeln!(f, "{}", SelectorErrorCode(self.error_code))?;
+        }
+        write_code(f, self.rip)
+    }
+}
+
+// The bytes around `address`, with the one it points at marked:
+//     ffffffff80012340: 48 89 e5 48 83 ec 10 fa >0f 01 f8 48 8b 45 ...
+// There's no telling where instructions start before `address`, so it's up to
+// whoever reads it to make sense of the bytes.
+fn write_code(f: &mut fmt::Formatter, address: u64) -> fmt::Result {
+    // Don't go back onto the page before, which could be unmapped. If the page RIP is
+    // on isn't, this faults again, and the crash screen shows the double panic.
+    let before = (address & 0xfff).min(CODE_BEFORE);
+    let start = address - before;
+    write!(f, "{:016x}:", start)?;
+    for byte_address in start..address + CODE_AFTER {
+        let byte = unsafe { (byte_address as *const u8).read_volatile() };
+        let marker = if byte_address == address { ">" } else { " " };
+        write!(f, "{}{:02x}", marker, byte)?;
+    }
+    Ok(())
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let registers = interrupted_registers(&stack_frame);
+    let report = GeneralProtectionReport {
+        error_code,
+        rip: registers.rip,
+    };
+    fatal(GENERAL_PROTECTION_FAULT, Some(error_code), Some(&report), &registers);
+}
+
+// Runs on its own stack (see `gdt`), since the usual reason to be here is that the
+// kernel stack is gone. If RSP points into that, the crash screen faults reading the
+// stack and ends up showing the double panic message once the rest is on screen.
+extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    let registers = interrupted_registers(&stack_frame);
+    // The error code is always 0, and what the first fault was is lost
+    let details = "a fault happened while handling another one (or the stack overflowed)";
+    fatal(DOUBLE_FAULT, None, Some(&details), &registers);
+}
@@ -0,0 +1,102 @@
+// Non-maskable interrupts. The chipset sends one for a hardware error it can't
+// report any other way: a parity error on the memory bus (SERR#), or a card pulling
+// the I/O channel check line. System control port B says which, and has to be told
+// before it sends another.
+//
+// Nothing can keep an NMI out, not even turning interrupts off, so it can come in
+// while anything at all is locked. Reports only ever go through `try_println!`.
+//
+// An NMI that the chipset didn't send is someone else's - a performance counter
+// overflowing, or a watchdog. Those go to the hook set with `set_hook`, if there is
+// one.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use crate::try_println;
+
+const VECTOR: u8 = 2;
+
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+// Read from port B
+const MEMORY_PARITY_ERROR: u8 = 1 << 7;
+const CHANNEL_CHECK: u8 = 1 << 6;
+// Written to port B: setting these turns the checks off, which clears them
+const PARITY_CHECK_DISABLE: u8 = 1 << 2;
+const CHANNEL_CHECK_DISABLE: u8 = 1 << 3;
+// The low four bits are the only ones that should be written back
+const WRITABLE: u8 = 0x0f;
+
+static COUNT: AtomicU64 = AtomicU64::new(0);
+// A `fn(&InterruptStackFrame)`, or 0 for none
+static HOOK: AtomicUsize = AtomicUsize::new(0);
+
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
+}
+
+// How many NMIs there have been, whatever sent them
+pub fn count() -> u64 {
+    COUNT.load(Ordering::Relaxed)
+}
+
+// Hands NMIs the chipset didn't send to `hook`, e.g. for a watchdog that checks the
+// kernel is still making progress, or a profiler sampling where it is. It runs in
+// the NMI handler, so it can't wait for anything either.
+pub fn set_hook(hook: Option<fn(&InterruptStackFrame)>) {
+    HOOK.store(hook.map_or(0, |hook| hook as usize), Ordering::SeqCst);
+}
+
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    super::record(VECTOR);
+    COUNT.fetch_add(1, Ordering::Relaxed);
+    let mut port_b: Port<u8> = Port::new(SYSTEM_CONTROL_PORT_B);
+    // Safe since port B is on every PC, and only the check bits get changed
+    let status = unsafe { port_b.read() };
+    if status & (MEMORY_PARITY_ERROR | CHANNEL_CHECK) != 0 {
+        report(status, &stack_frame);
+        unsafe {
+            let control = status & WRITABLE;
+            port_b.write(control | PARITY_CHECK_DISABLE | CHANNEL_CHECK_DISABLE);
+            port_b.write(control & !(PARITY_CHECK_DISABLE | CHANNEL_CHECK_DISABLE));
+        }
+        return;
+    }
+    match HOOK.load(Ordering::Acquire) {
+        0 => try_println!(
+            "NMI: unknown cause at {:#x}, carrying on",
+            stack_frame.instruction_pointer.as_u64()
+        ),
+        hook => {
+            // Only ever stored from a `fn(&InterruptStackFrame)` in `set_hook`
+            let hook: fn(&InterruptStackFrame) = unsafe { core::mem::transmute(hook) };
+            hook(&stack_frame);
+        }
+    }
+}
+
+fn report(status: u8, stack_frame: &InterruptStackFrame) {
+    let rip = stack_frame.instruction_pointer.as_u64();
+    if status & MEMORY_PARITY_ERROR != 0 {
+        try_println!("NMI: memory parity error (SERR#) at {:#x}, memory may be corrupt", rip);
+    }
+    if status & CHANNEL_CHECK != 0 {
+        try_println!("NMI: I/O channel check at {:#x}, a device reported an error", rip);
+    }
+}
+
+#[test_case]
+fn test_nmi_counted() {
+    static HOOKED: AtomicU64 = AtomicU64::new(0);
+    fn hook(_stack_frame: &InterruptStackFrame) {
+        HOOKED.fetch_add(1, Ordering::Relaxed);
+    }
+    set_hook(Some(hook));
+    let before = count();
+    // `int 2` goes through the same handler, without the chipset having said anything
+    unsafe { core::arch::asm!("int 2") };
+    set_hook(None);
+    assert_eq!(count(), before + 1);
+    assert_eq!(HOOKED.load(Ordering::Relaxed), 1);
+}
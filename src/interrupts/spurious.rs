@@ -0,0 +1,94 @@
+// Interrupts that turn out not to be. The PIC sends one as IRQ 7 (or 15, from the
+// secondary) when a line goes quiet again between it raising the interrupt and the
+// CPU taking it - electrical noise, or a device that was masked at just the wrong
+// time. The only way to tell is the in-service register: a real IRQ 7 is in service,
+// a spurious one isn't. A spurious one mustn't get an EOI, since that would end
+// whatever real IRQ is in service instead. The local APIC has a vector of its own
+// for the same thing (`apic::SPURIOUS_VECTOR`), which never gets an EOI either.
+//
+// Every one is counted, since a lot of them means flaky hardware or a mask that's
+// being set at the wrong moment.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use crate::pic;
+
+const PRIMARY_IRQ: u8 = 7;
+const SECONDARY_IRQ: u8 = 15;
+
+static PRIMARY: AtomicU64 = AtomicU64::new(0);
+static SECONDARY: AtomicU64 = AtomicU64::new(0);
+static APIC: AtomicU64 = AtomicU64::new(0);
+
+// How many spurious interrupts came in on `vector`, which is only ever one of the
+// three above
+pub fn count(vector: u8) -> u64 {
+    let counter = match vector {
+        v if v == pic::vector(PRIMARY_IRQ) => &PRIMARY,
+        v if v == pic::vector(SECONDARY_IRQ) => &SECONDARY,
+        crate::apic::SPURIOUS_VECTOR => &APIC,
+        _ => return 0,
+    };
+    counter.load(Ordering::Relaxed)
+}
+
+pub fn total() -> u64 {
+    PRIMARY.load(Ordering::Relaxed) + SECONDARY.load(Ordering::Relaxed) + APIC.load(Ordering::Relaxed)
+}
+
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    idt[pic::vector(PRIMARY_IRQ) as usize].set_handler_fn(primary_handler);
+    idt[pic::vector(SECONDARY_IRQ) as usize].set_handler_fn(secondary_handler);
+    idt[crate::apic::SPURIOUS_VECTOR as usize].set_handler_fn(apic_handler);
+}
+
+// Nothing drives IRQ 7 or 15, so a real one is only ever acknowledged
+extern "x86-interrupt" fn primary_handler(_stack_frame: InterruptStackFrame) {
+    super::record(pic::vector(PRIMARY_IRQ));
+    let mut pics = pic::PICS.lock();
+    if pics.in_service() & (1 << PRIMARY_IRQ) == 0 {
+        PRIMARY.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    pics.end_of_interrupt(pic::vector(PRIMARY_IRQ));
+}
+
+extern "x86-interrupt" fn secondary_handler(_stack_frame: InterruptStackFrame) {
+    super::record(pic::vector(SECONDARY_IRQ));
+    let mut pics = pic::PICS.lock();
+    if pics.in_service() & (1 << SECONDARY_IRQ) == 0 {
+        SECONDARY.fetch_add(1, Ordering::Relaxed);
+        // The primary did see the cascade line go, and is waiting to hear it's done
+        pics.end_of_interrupt(pic::vector(pic::CASCADE_IRQ));
+        return;
+    }
+    pics.end_of_interrupt(pic::vector(SECONDARY_IRQ));
+}
+
+extern "x86-interrupt" fn apic_handler(_stack_frame: InterruptStackFrame) {
+    super::record(crate::apic::SPURIOUS_VECTOR);
+    APIC.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_count_only_spurious_vectors() {
+    assert_eq!(count(pic::vector(1)), 0);
+}
+
+#[test_case]
+fn test_spurious_interrupts_counted() {
+    // A software `int` never puts anything in service, so to the handlers it looks
+    // just like a spurious interrupt. With interrupts off no real one can come in
+    // between and throw the counts off.
+    crate::sync::without_interrupts(|| {
+        let primary = count(pic::vector(PRIMARY_IRQ));
+        let apic = count(crate::apic::SPURIOUS_VECTOR);
+        let before = total();
+        unsafe { core::arch::asm!("int {}", const pic::vector(PRIMARY_IRQ)) };
+        assert_eq!(count(pic::vector(PRIMARY_IRQ)), primary + 1);
+        unsafe { core::arch::asm!("int {}", const crate::apic::SPURIOUS_VECTOR) };
+        assert_eq!(count(crate::apic::SPURIOUS_VECTOR), apic + 1);
+        assert_eq!(total(), before + 2);
+    });
+}
@@ -0,0 +1,66 @@
+// How many times every vector has come in since boot, exceptions and IRQs alike.
+// Handlers call `record` first thing, and `stats` takes a snapshot of the lot, so
+// two snapshots apart in time give the rate - a device stuck raising its IRQ shows
+// up as a count that runs away from the rest.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+// Counts one more interrupt on `vector`
+#[inline]
+pub fn record(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn stats() -> Stats {
+    let mut counts = [0; 256];
+    for (count, counter) in counts.iter_mut().zip(COUNTS.iter()) {
+        *count = counter.load(Ordering::Relaxed);
+    }
+    Stats { counts }
+}
+
+// The counts as they were when `stats` was called. Each one was read on its own, so
+// they can be a few interrupts apart from each other.
+#[derive(Clone)]
+pub struct Stats {
+    counts: [u64; 256],
+}
+
+impl Stats {
+    pub fn count(&self, vector: u8) -> u64 {
+        self.counts[vector as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // Every vector that has come in at least once, with its count
+    pub fn iter(&self) -> impl Iterator<Item = (u8, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count != 0)
+            .map(|(vector, &count)| (vector as u8, count))
+    }
+
+    // How many came in on each vector since `earlier`
+    pub fn since(&self, earlier: &Stats) -> Stats {
+        let mut counts = [0; 256];
+        for (vector, count) in counts.iter_mut().enumerate() {
+            *count = self.counts[vector].saturating_sub(earlier.counts[vector]);
+        }
+        Stats { counts }
+    }
+}
+
+#[test_case]
+fn test_breakpoint_counted() {
+    let before = stats();
+    x86_64::instructions::interrupts::int3();
+    let after = stats().since(&before);
+    assert_eq!(after.count(3), 1);
+    assert!(after.iter().any(|(vector, _)| vector == 3));
+}
@@ -0,0 +1,197 @@
+// The I/O APIC, which takes device interrupts to the local APICs (see `apic`) in
+// place of the PIC. Each of its inputs is a global system interrupt (GSI), and every
+// GSI has an entry in the redirection table saying which vector it comes in on, which
+// CPU gets it, and how the line signals. ACPI's MADT (see `acpi`) says where the I/O
+// APICs are and which GSIs the ISA IRQs are wired to.
+//
+// Like the local APIC, the registers are reached through the bootloader's mapping of
+// physical memory. There are only two of them, though: one to pick a register and a
+// window to read or write it through.
+
+use crate::acpi;
+use crate::sync::IrqSafeMutex;
+
+const REGISTER_SELECT: usize = 0x00;
+const REGISTER_WINDOW: usize = 0x10;
+
+// Registers, through the window
+const VERSION: u32 = 0x01;
+// Two registers per GSI, the low half first
+const REDIRECTION_TABLE: u32 = 0x10;
+
+// In a redirection table entry
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL_TRIGGERED: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+const ENTRY_DESTINATION_SHIFT: u64 = 56;
+
+// In the flags of an ACPI interrupt override
+const OVERRIDE_POLARITY: u16 = 0b11;
+const OVERRIDE_ACTIVE_LOW: u16 = 0b11;
+const OVERRIDE_TRIGGER: u16 = 0b11 << 2;
+const OVERRIDE_LEVEL_TRIGGERED: u16 = 0b11 << 2;
+
+const MAX_IO_APICS: usize = 8;
+
+static IO_APICS: IrqSafeMutex<[Option<IoApic>; MAX_IO_APICS]> = IrqSafeMutex::new([None; MAX_IO_APICS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    // None of the I/O APICs has an input with that number
+    NoSuchGsi(u32),
+    // The destination field is 8 bits, x2APIC IDs past that need interrupt remapping
+    CpuOutOfRange(u32),
+}
+
+impl core::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            RouteError::NoSuchGsi(gsi) => write!(f, "no I/O APIC has GSI {}", gsi),
+            RouteError::CpuOutOfRange(cpu) => write!(f, "APIC ID {} is too big for the I/O APIC", cpu),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IoApic {
+    // Where the registers are mapped
+    base: u64,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    unsafe fn read(&self, register: u32) -> u32 {
+        ((self.base as usize + REGISTER_SELECT) as *mut u32).write_volatile(register);
+        ((self.base as usize + REGISTER_WINDOW) as *const u32).read_volatile()
+    }
+
+    unsafe fn write(&self, register: u32, value: u32) {
+        ((self.base as usize + REGISTER_SELECT) as *mut u32).write_volatile(register);
+        ((self.base as usize + REGISTER_WINDOW) as *mut u32).write_volatile(value);
+    }
+
+    unsafe fn entry(&self, gsi: u32) -> u64 {
+        let register = REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        self.read(register) as u64 | (self.read(register + 1) as u64) << 32
+    }
+
+    // The high half first, so the entry never points at a CPU it wasn't meant for
+    // while unmasked
+    unsafe fn set_entry(&self, gsi: u32, entry: u64) {
+        let register = REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        self.write(register, ENTRY_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+// Finds the I/O APICs in the MADT, with every GSI masked, and says whether there
+// were any. `acpi::init` has to have found the MADT first.
+pub fn init(physical_memory_offset: u64) -> bool {
+    let madt = match acpi::madt() {
+        Some(madt) => madt,
+        None => return false,
+    };
+    let mut io_apics = IO_APICS.lock();
+    for (slot, entry) in io_apics.iter_mut().zip(madt.io_apics()) {
+        let mut io_apic = IoApic {
+            base: physical_memory_offset + entry.address as u64,
+            gsi_base: entry.gsi_base,
+            entries: 0,
+        };
+        // Safe since the MADT says that's where an I/O APIC is, and masking
+        // everything is how nothing comes in unasked
+        unsafe {
+            io_apic.entries = ((io_apic.read(VERSION) >> 16) & 0xff) + 1;
+            for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.entries {
+                io_apic.set_entry(gsi, ENTRY_MASKED);
+            }
+        }
+        *slot = Some(io_apic);
+    }
+    io_apics.iter().any(Option::is_some)
+}
+
+// Sends `gsi` to the local APIC with ID `cpu`, as `vector`. The handler for it has
+// to end with `apic::end_of_interrupt`.
+pub fn route(gsi: u32, vector: u8, cpu: u32, trigger: TriggerMode, polarity: Polarity) -> Result<(), RouteError> {
+    if cpu > u8::MAX as u32 {
+        return Err(RouteError::CpuOutOfRange(cpu));
+    }
+    let mut entry = vector as u64 | (cpu as u64) << ENTRY_DESTINATION_SHIFT;
+    if trigger == TriggerMode::Level {
+        entry |= ENTRY_LEVEL_TRIGGERED;
+    }
+    if polarity == Polarity::ActiveLow {
+        entry |= ENTRY_ACTIVE_LOW;
+    }
+    with_io_apic(gsi, |io_apic| unsafe { io_apic.set_entry(gsi, entry) })
+}
+
+pub fn mask(gsi: u32) -> Result<(), RouteError> {
+    with_io_apic(gsi, |io_apic| unsafe { io_apic.set_entry(gsi, io_apic.entry(gsi) | ENTRY_MASKED) })
+}
+
+pub fn unmask(gsi: u32) -> Result<(), RouteError> {
+    with_io_apic(gsi, |io_apic| unsafe { io_apic.set_entry(gsi, io_apic.entry(gsi) & !ENTRY_MASKED) })
+}
+
+// Which GSI ISA IRQ `irq` is on, and how it signals. That's the GSI of the same
+// number, edge triggered and active high, unless the MADT says otherwise.
+pub fn isa_irq(irq: u8) -> (u32, TriggerMode, Polarity) {
+    let entry = match acpi::madt().and_then(|madt| madt.interrupt_override(irq)) {
+        Some(entry) => entry,
+        None => return (irq as u32, TriggerMode::Edge, Polarity::ActiveHigh),
+    };
+    let trigger = if entry.flags & OVERRIDE_TRIGGER == OVERRIDE_LEVEL_TRIGGERED {
+        TriggerMode::Level
+    } else {
+        TriggerMode::Edge
+    };
+    let polarity = if entry.flags & OVERRIDE_POLARITY == OVERRIDE_ACTIVE_LOW {
+        Polarity::ActiveLow
+    } else {
+        Polarity::ActiveHigh
+    };
+    (entry.gsi, trigger, polarity)
+}
+
+fn with_io_apic(gsi: u32, f: impl FnOnce(&IoApic)) -> Result<(), RouteError> {
+    let io_apics = IO_APICS.lock();
+    let io_apic = io_apics
+        .iter()
+        .flatten()
+        .find(|io_apic| io_apic.handles(gsi))
+        .ok_or(RouteError::NoSuchGsi(gsi))?;
+    f(io_apic);
+    Ok(())
+}
+
+#[test_case]
+fn test_no_such_gsi() {
+    assert_eq!(mask(u32::MAX), Err(RouteError::NoSuchGsi(u32::MAX)));
+}
+
+#[test_case]
+fn test_isa_irq_without_override() {
+    // Nothing overrides the keyboard's IRQ on any PC we know of
+    let (gsi, trigger, polarity) = isa_irq(1);
+    assert_eq!((gsi, trigger, polarity), (1, TriggerMode::Edge, Polarity::ActiveHigh));
+}
@@ -0,0 +1,208 @@
+// The PS/2 keyboard. Every byte the keyboard sends raises IRQ 1, and the handler
+// turns it into a `KeyEvent` as soon as it finishes one: which key went down or up,
+// what it typed in the current layout and which modifiers were held (see `scancode`,
+// `layout` and `event`). Events wait in `EVENTS` until somebody takes them off with
+// `next_event`, `try_next_event` or `read_char`. Any number of readers can do that
+// at once, each event goes to just one of them.
+//
+// The handler also keeps the keyboard's LEDs in line with the lock keys, and sets
+// how held keys repeat (see `command`). Repeating can be done here instead, off the
+// timer interrupt, with `set_software_repeat` (see `repeat`).
+//
+// For an async task that would rather decode scancodes itself, there's
+// `ScancodeStream` (see `stream`).
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::config;
+use crate::interrupts;
+use crate::pic;
+use crate::ps2::{self, Channel, Device};
+use crate::sync::IrqSafeMutex;
+use crate::time;
+
+mod command;
+pub mod event;
+pub mod layout;
+mod queue;
+mod repeat;
+pub mod scancode;
+mod stream;
+
+pub use event::{KeyEvent, Modifiers};
+pub use layout::Layout;
+pub use scancode::{KeyCode, KeyState, RawKey};
+pub use stream::ScancodeStream;
+
+pub const IRQ: u8 = 1;
+
+
+static EVENTS: queue::EventQueue = queue::EventQueue::new();
+// The interrupt handler's, `modifiers` just has a look
+static INPUT: IrqSafeMutex<Input> = IrqSafeMutex::new(Input::new());
+static LAYOUT: AtomicU8 = AtomicU8::new(config::KEYBOARD_LAYOUT as u8);
+
+// Sets up the keyboard `ps2::init` found on the first port, or returns false if it
+// didn't find one there
+pub fn init() -> bool {
+    if ps2::device(Channel::First) != Some(Device::Keyboard) || !ps2::enable_irq(Channel::First) {
+        return false;
+    }
+    interrupts::enable_irq(IRQ);
+    // The keyboard's answers wait until the lock is dropped
+    let mut input = INPUT.lock();
+    input.update_leds();
+    input.set_typematic(config::KEY_REPEAT_DELAY_MILLIS, config::KEY_REPEAT_PER_SECOND);
+    true
+}
+
+// The oldest key event nobody has taken yet, if there is one
+pub fn try_next_event() -> Option<KeyEvent> {
+    EVENTS.pop()
+}
+
+// The oldest key event nobody has taken yet, waiting for one if there isn't
+pub fn next_event() -> KeyEvent {
+    use x86_64::instructions::interrupts as cpu_interrupts;
+    assert!(cpu_interrupts::are_enabled(), "waiting for a key with interrupts off");
+    loop {
+        // With interrupts off from checking until the hlt, the key can't come in
+        // after the check but before we wait for it
+        cpu_interrupts::disable();
+        if let Some(event) = EVENTS.pop() {
+            cpu_interrupts::enable();
+            return event;
+        }
+        cpu_interrupts::enable_and_hlt();
+    }
+}
+
+// The next character typed, skipping keys that don't type one. Doesn't wait.
+pub fn read_char() -> Option<char> {
+    loop {
+        if let Some(character) = try_next_event()?.char {
+            return Some(character);
+        }
+    }
+}
+
+// Which modifiers are held and lock keys on, as of the last key that came in
+pub fn modifiers() -> Modifiers {
+    INPUT.lock().resolver.modifiers()
+}
+
+// How long a key has to be held before it repeats, and how often it does then. The
+// keyboard only does 250 to 1000ms and 2 to 30 a second, and picks the closest.
+pub fn set_repeat(delay_millis: u32, per_second: u32) {
+    let mut input = INPUT.lock();
+    input.repeater.configure(delay_millis, per_second);
+    input.set_typematic(delay_millis, per_second);
+}
+
+// Whether held keys are repeated by us rather than by the keyboard
+pub fn set_software_repeat(enabled: bool) {
+    INPUT.lock().repeater.set_enabled(enabled);
+}
+
+// Called from the timer interrupt, to repeat the key being held when it's time
+pub fn repeat_tick(now: u64) {
+    // Nothing else that takes the lock can be running under the timer interrupt, but
+    // there's no need to bet on that
+    let event = match INPUT.try_lock() {
+        Some(mut input) => input.repeat(now),
+        None => return,
+    };
+    if let Some(event) = event {
+        EVENTS.push(event);
+    }
+}
+
+pub fn layout() -> Layout {
+    Layout::ALL[LAYOUT.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+// What the interrupt handler has made of the scancodes so far
+struct Input {
+    decoder: scancode::Decoder,
+    resolver: event::Resolver,
+    commands: command::Commands,
+    repeater: repeat::Repeater,
+}
+
+impl Input {
+    const fn new() -> Input {
+        Input {
+            decoder: scancode::Decoder::new(),
+            resolver: event::Resolver::new(),
+            commands: command::Commands::new(),
+            repeater: repeat::Repeater::new(
+                config::SOFTWARE_KEY_REPEAT,
+                config::KEY_REPEAT_DELAY_MILLIS,
+                config::KEY_REPEAT_PER_SECOND,
+            ),
+        }
+    }
+
+    fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.commands.is_response(byte) {
+            if let Some(next) = self.commands.respond(byte) {
+                send(next);
+            }
+            return None;
+        }
+        if stream::push(byte) {
+            return None;
+        }
+        let key = self.decoder.add_byte(byte)?;
+        if !self.repeater.key(key, time::uptime_micros()) {
+            return None;
+        }
+        let event = self.resolver.resolve(key, layout());
+        self.update_leds();
+        Some(event)
+    }
+
+    fn repeat(&mut self, now: u64) -> Option<KeyEvent> {
+        let key = self.repeater.tick(now)?;
+        Some(self.resolver.resolve(key, layout()))
+    }
+
+    fn update_leds(&mut self) {
+        if let Some(command) = self.commands.set_leds(command::led_bits(&self.resolver.modifiers())) {
+            send(command);
+        }
+    }
+
+    fn set_typematic(&mut self, delay_millis: u32, per_second: u32) {
+        if let Some(command) = self.commands.set_typematic(command::typematic_bits(delay_millis, per_second)) {
+            send(command);
+        }
+    }
+}
+
+fn send(byte: u8) {
+    if !ps2::write_data(byte) {
+        // Usually from the interrupt handler, where the console might be locked
+        crate::try_println!("keyboard: controller never took byte {:#x}", byte);
+    }
+}
+
+// How many key events were lost because nobody took them in time
+pub fn dropped() -> usize {
+    EVENTS.dropped()
+}
+
+pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    interrupts::record(pic::vector(IRQ));
+    let scancode: u8 = unsafe { Port::new(ps2::DATA_PORT).read() };
+    if let Some(event) = INPUT.lock().add_byte(scancode) {
+        EVENTS.push(event);
+    }
+    interrupts::end_of_interrupt(IRQ);
+}
@@ -0,0 +1,173 @@
+// Commands for the keyboard itself: lighting up the lock LEDs to match the lock keys
+// (0xed), and how soon and how fast held keys repeat (0xf3). Both are a command byte
+// followed by a value byte, and the keyboard answers each byte with an ACK, or asks
+// for it again with RESEND.
+//
+// The answers come in through IRQ 1 like scancodes, and the lock keys change in the
+// interrupt handler, so the whole exchange is driven from there: `Commands` keeps
+// track of how far along it is and says what to send next, without ever waiting for
+// the keyboard. Anything asked for halfway through just goes next, once this one's
+// done.
+
+use super::event::Modifiers;
+use crate::ps2::{ACK, RESEND};
+
+const SET_LEDS: u8 = 0xed;
+const SET_TYPEMATIC: u8 = 0xf3;
+// After this many, the keyboard clearly doesn't want to
+const MAX_RESENDS: u8 = 3;
+
+const SCROLL_LOCK: u8 = 1 << 0;
+const NUM_LOCK: u8 = 1 << 1;
+const CAPS_LOCK: u8 = 1 << 2;
+// Not something the keyboard could be showing, so the first `set_leds` always sends
+const UNKNOWN: u8 = 0xff;
+
+// What the LED command's value byte is for `modifiers`
+pub fn led_bits(modifiers: &Modifiers) -> u8 {
+    let mut bits = 0;
+    if modifiers.scroll_lock {
+        bits |= SCROLL_LOCK;
+    }
+    if modifiers.num_lock {
+        bits |= NUM_LOCK;
+    }
+    if modifiers.caps_lock {
+        bits |= CAPS_LOCK;
+    }
+    bits
+}
+
+// What the typematic command's value byte is for repeating after `delay_millis`, at
+// `per_second` repeats a second - as close as the keyboard gets, which is a delay of
+// 250 to 1000ms in steps of 250, and 2 to 30 repeats a second.
+pub fn typematic_bits(delay_millis: u32, per_second: u32) -> u8 {
+    let delay = (delay_millis.clamp(250, 1000) + 125) / 250 - 1;
+    // Rate codes go from fastest to slowest, a repeat every (8 + low 3 bits)
+    // * 2^(next 2 bits) * 4.17ms
+    let tenths_per_second = |code: u32| 2398 / ((8 + (code & 7)) << (code >> 3));
+    let wanted = per_second * 10;
+    let rate = (0..32).min_by_key(|&code| tenths_per_second(code).abs_diff(wanted)).unwrap_or(0);
+    (delay << 5 | rate) as u8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    // Which command and value, with whether the value's been sent yet
+    Sent { command: u8, value: u8, sent_value: bool },
+}
+
+pub struct Commands {
+    state: State,
+    leds_shown: u8,
+    leds_wanted: u8,
+    typematic: Option<u8>,
+    resends: u8,
+}
+
+impl Commands {
+    pub const fn new() -> Commands {
+        Commands {
+            state: State::Idle,
+            leds_shown: UNKNOWN,
+            leds_wanted: 0,
+            typematic: None,
+            resends: 0,
+        }
+    }
+
+    // Asks for `leds` (see `led_bits`) to be shown, and returns the byte to send if
+    // that starts a command
+    pub fn set_leds(&mut self, leds: u8) -> Option<u8> {
+        self.leds_wanted = leds;
+        self.start()
+    }
+
+    // Asks for a new repeat delay and rate (see `typematic_bits`)
+    pub fn set_typematic(&mut self, typematic: u8) -> Option<u8> {
+        self.typematic = Some(typematic);
+        self.start()
+    }
+
+    // Whether `byte` is the keyboard answering us, rather than a scancode
+    pub fn is_response(&self, byte: u8) -> bool {
+        self.state != State::Idle && (byte == ACK || byte == RESEND)
+    }
+
+    // Takes the keyboard's answer, and returns the byte to send next if there is one
+    pub fn respond(&mut self, byte: u8) -> Option<u8> {
+        let State::Sent { command, value, sent_value } = self.state else {
+            return None;
+        };
+        let sent = if sent_value { value } else { command };
+        if byte == RESEND {
+            if self.resends < MAX_RESENDS {
+                self.resends += 1;
+                return Some(sent);
+            }
+            // From the interrupt handler, where the console might be locked
+            crate::try_println!("keyboard: won't take command byte {:#x}, giving up on it", sent);
+            if command == SET_LEDS {
+                // Don't keep trying every time a lock key is pressed
+                self.leds_shown = self.leds_wanted;
+            }
+            self.state = State::Idle;
+            self.resends = 0;
+            return self.start();
+        }
+        self.resends = 0;
+        if !sent_value {
+            // The lock keys might have changed since the command was queued
+            let value = if command == SET_LEDS { self.leds_wanted } else { value };
+            self.state = State::Sent { command, value, sent_value: true };
+            return Some(value);
+        }
+        if command == SET_LEDS {
+            self.leds_shown = value;
+        }
+        self.state = State::Idle;
+        self.start()
+    }
+
+    fn start(&mut self) -> Option<u8> {
+        if self.state != State::Idle {
+            return None;
+        }
+        let (command, value) = if self.leds_wanted != self.leds_shown {
+            (SET_LEDS, self.leds_wanted)
+        } else {
+            (SET_TYPEMATIC, self.typematic.take()?)
+        };
+        self.state = State::Sent { command, value, sent_value: false };
+        Some(command)
+    }
+}
+
+#[test_case]
+fn test_command_handshake() {
+    let mut commands = Commands::new();
+    assert_eq!(commands.set_leds(CAPS_LOCK), Some(SET_LEDS));
+    // Caps lock pressed again and the repeat rate changed, before the keyboard got
+    // to answer
+    assert_eq!(commands.set_leds(0), None);
+    assert_eq!(commands.set_typematic(0x20), None);
+    assert!(commands.is_response(RESEND));
+    assert_eq!(commands.respond(RESEND), Some(SET_LEDS));
+    assert_eq!(commands.respond(ACK), Some(0));
+    // The LEDs are done and already show what's wanted, so on to the typematic one
+    assert_eq!(commands.respond(ACK), Some(SET_TYPEMATIC));
+    assert_eq!(commands.respond(ACK), Some(0x20));
+    assert_eq!(commands.respond(ACK), None);
+    assert!(!commands.is_response(ACK));
+    assert_eq!(commands.set_leds(0), None);
+    assert_eq!(commands.set_leds(NUM_LOCK), Some(SET_LEDS));
+}
+
+#[test_case]
+fn test_typematic_bits() {
+    // The keyboard's own default, 500ms and 10.9 a second
+    assert_eq!(typematic_bits(500, 11), 0x2b);
+    assert_eq!(typematic_bits(250, 30), 0x00);
+    assert_eq!(typematic_bits(5000, 1), 0x7f);
+}
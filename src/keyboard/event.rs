@@ -0,0 +1,241 @@
+// Keys as whoever's reading the keyboard wants them: which key, what it typed, and
+// which modifiers were held down at the time. `Resolver` follows the modifier and
+// lock keys through the stream of `RawKey`s to work that out.
+//
+// The lock keys start out off, and `keyboard::init` turns the LEDs off to match.
+
+use super::layout::Layout;
+use super::scancode::{KeyCode, KeyState, RawKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_control: bool,
+    pub right_control: bool,
+    // Left alt. The right one is AltGr, which picks a layout's third character.
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    pub const fn new() -> Modifiers {
+        Modifiers {
+            left_shift: false,
+            right_shift: false,
+            left_control: false,
+            right_control: false,
+            alt: false,
+            alt_gr: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+        }
+    }
+
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn control(&self) -> bool {
+        self.left_control || self.right_control
+    }
+
+    fn flags(&self) -> [bool; 9] {
+        [
+            self.left_shift,
+            self.right_shift,
+            self.left_control,
+            self.right_control,
+            self.alt,
+            self.alt_gr,
+            self.caps_lock,
+            self.num_lock,
+            self.scroll_lock,
+        ]
+    }
+
+    // One bit each, in the order of `flags`
+    fn from_bits(bits: u16) -> Modifiers {
+        let bit = |index: u16| bits & (1 << index) != 0;
+        Modifiers {
+            left_shift: bit(0),
+            right_shift: bit(1),
+            left_control: bit(2),
+            right_control: bit(3),
+            alt: bit(4),
+            alt_gr: bit(5),
+            caps_lock: bit(6),
+            num_lock: bit(7),
+            scroll_lock: bit(8),
+        }
+    }
+
+    fn bits(&self) -> u16 {
+        self.flags().iter().enumerate().fold(0, |bits, (index, &set)| bits | (set as u16) << index)
+    }
+
+    fn update(&mut self, key: RawKey) {
+        let down = key.state == KeyState::Down;
+        match key.code {
+            KeyCode::LeftShift => self.left_shift = down,
+            KeyCode::RightShift => self.right_shift = down,
+            KeyCode::LeftControl => self.left_control = down,
+            KeyCode::RightControl => self.right_control = down,
+            KeyCode::LeftAlt => self.alt = down,
+            KeyCode::RightAlt => self.alt_gr = down,
+            // Lock keys flip when pressed, and holding them down (so they repeat)
+            // flips them again, like everywhere else
+            KeyCode::CapsLock if down => self.caps_lock = !self.caps_lock,
+            KeyCode::NumLock if down => self.num_lock = !self.num_lock,
+            KeyCode::ScrollLock if down => self.scroll_lock = !self.scroll_lock,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    // What the key typed, if it went down and types anything. With control held,
+    // letters type their control character, so Ctrl-C is '\u{3}'.
+    pub char: Option<char>,
+    // Including whatever this key itself just changed
+    pub modifiers: Modifiers,
+}
+
+// How events are packed into a u64 for the queue
+const PACKED_UP: u64 = 1 << 8;
+const PACKED_MODIFIERS_SHIFT: u32 = 16;
+const PACKED_HAS_CHAR: u64 = 1 << 31;
+const PACKED_CHAR_SHIFT: u32 = 32;
+
+impl KeyEvent {
+    pub fn is_down(&self) -> bool {
+        self.state == KeyState::Down
+    }
+
+    pub(super) fn pack(&self) -> u64 {
+        let mut packed = self.code as u64 | (self.modifiers.bits() as u64) << PACKED_MODIFIERS_SHIFT;
+        if self.state == KeyState::Up {
+            packed |= PACKED_UP;
+        }
+        if let Some(char) = self.char {
+            packed |= PACKED_HAS_CHAR | (char as u64) << PACKED_CHAR_SHIFT;
+        }
+        packed
+    }
+
+    // Only ever given what `pack` made
+    pub(super) fn unpack(packed: u64) -> KeyEvent {
+        let char = if packed & PACKED_HAS_CHAR != 0 {
+            char::from_u32((packed >> PACKED_CHAR_SHIFT) as u32)
+        } else {
+            None
+        };
+        KeyEvent {
+            code: KeyCode::from_u8(packed as u8).unwrap_or(KeyCode::Escape),
+            state: if packed & PACKED_UP != 0 { KeyState::Up } else { KeyState::Down },
+            char,
+            modifiers: Modifiers::from_bits((packed >> PACKED_MODIFIERS_SHIFT) as u16),
+        }
+    }
+}
+
+pub struct Resolver {
+    modifiers: Modifiers,
+}
+
+impl Resolver {
+    pub const fn new() -> Resolver {
+        Resolver {
+            modifiers: Modifiers::new(),
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn resolve(&mut self, key: RawKey, layout: Layout) -> KeyEvent {
+        self.modifiers.update(key);
+        let char = if key.state == KeyState::Down {
+            self.char(key.code, layout)
+        } else {
+            None
+        };
+        KeyEvent {
+            code: key.code,
+            state: key.state,
+            char,
+            modifiers: self.modifiers,
+        }
+    }
+
+    fn char(&self, code: KeyCode, layout: Layout) -> Option<char> {
+        let modifiers = &self.modifiers;
+        // Without num lock the keypad is the arrows and home/end/... printed
+        // underneath the digits
+        if is_keypad_navigation(code) && !modifiers.num_lock {
+            return None;
+        }
+        // Caps lock only works on letters, and shift undoes it
+        let mut shift = modifiers.shift();
+        if modifiers.caps_lock && layout.translate(code, false, false).is_some_and(char::is_alphabetic) {
+            shift = !shift;
+        }
+        let char = layout.translate(code, shift, modifiers.alt_gr)?;
+        if modifiers.control() && char.is_ascii_alphabetic() {
+            return Some((char.to_ascii_uppercase() as u8 - b'@') as char);
+        }
+        Some(char)
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver::new()
+    }
+}
+
+fn is_keypad_navigation(code: KeyCode) -> bool {
+    use KeyCode::*;
+    matches!(
+        code,
+        Numpad0 | Numpad1 | Numpad2 | Numpad3 | Numpad4 | Numpad5 | Numpad6 | Numpad7 | Numpad8 | Numpad9
+            | NumpadPeriod
+    )
+}
+
+#[test_case]
+fn test_modifiers_and_locks() {
+    let mut resolver = Resolver::new();
+    let mut press = |code, layout| {
+        resolver.resolve(RawKey { code, state: KeyState::Down }, layout);
+        let up = resolver.resolve(RawKey { code, state: KeyState::Up }, layout);
+        up.modifiers
+    };
+    let typed = |codes: &[KeyCode], layout| {
+        let mut resolver = Resolver::new();
+        let mut last = None;
+        for &code in codes {
+            last = resolver.resolve(RawKey { code, state: KeyState::Down }, layout).char;
+        }
+        last
+    };
+    assert_eq!(typed(&[KeyCode::LeftShift, KeyCode::A], Layout::Us), Some('A'));
+    assert_eq!(typed(&[KeyCode::CapsLock, KeyCode::RightShift, KeyCode::A], Layout::Us), Some('a'));
+    assert_eq!(typed(&[KeyCode::CapsLock, KeyCode::Key1], Layout::Us), Some('1'));
+    assert_eq!(typed(&[KeyCode::LeftControl, KeyCode::C], Layout::Us), Some('\u{3}'));
+    assert_eq!(typed(&[KeyCode::RightAlt, KeyCode::Q], Layout::German), Some('@'));
+    assert_eq!(typed(&[KeyCode::Numpad7], Layout::Us), None);
+    assert_eq!(typed(&[KeyCode::NumLock, KeyCode::Numpad7], Layout::Us), Some('7'));
+
+    // Lock keys stay on after they're let go, and pressing again turns them off
+    assert!(press(KeyCode::CapsLock, Layout::Us).caps_lock);
+    assert!(!press(KeyCode::CapsLock, Layout::Us).caps_lock);
+}
@@ -0,0 +1,265 @@
+// Which character a key types, which depends on what the keyboard has printed on it.
+// Every layout here is a handful of keys that differ from US QWERTY, with the US map
+// for everything else. The build picks one in `config::KEYBOARD_LAYOUT`, and
+// `keyboard::set_layout` changes it while running.
+//
+// Keys can have a third character for when AltGr is held (like '@' on a German
+// keyboard). Those without one type what they normally would.
+
+use super::scancode::KeyCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    Us,
+    Uk,
+    German,
+    Dvorak,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 4] = [Layout::Us, Layout::Uk, Layout::German, Layout::Dvorak];
+
+    // Short names, for picking one from a command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::German => "de",
+            Layout::Dvorak => "dvorak",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.iter().copied().find(|layout| layout.name() == name)
+    }
+
+    pub fn translate(self, code: KeyCode, shift: bool, alt_gr: bool) -> Option<char> {
+        let third = match self {
+            Layout::Uk => uk_alt_gr(code),
+            Layout::German => german_alt_gr(code),
+            Layout::Us | Layout::Dvorak => None,
+        };
+        if let (true, Some(char)) = (alt_gr, third) {
+            return Some(char);
+        }
+        let keys = match self {
+            Layout::Us => None,
+            Layout::Uk => uk(code),
+            Layout::German => german(code),
+            Layout::Dvorak => dvorak(code),
+        };
+        let (plain, shifted) = keys.or_else(|| us(code))?;
+        Some(if shift { shifted } else { plain })
+    }
+}
+
+fn us(code: KeyCode) -> Option<(char, char)> {
+    use KeyCode::*;
+    Some(match code {
+        Backtick => ('`', '~'),
+        Key1 => ('1', '!'),
+        Key2 => ('2', '@'),
+        Key3 => ('3', '#'),
+        Key4 => ('4', '$'),
+        Key5 => ('5', '%'),
+        Key6 => ('6', '^'),
+        Key7 => ('7', '&'),
+        Key8 => ('8', '*'),
+        Key9 => ('9', '('),
+        Key0 => ('0', ')'),
+        Minus => ('-', '_'),
+        Equals => ('=', '+'),
+        LeftBracket => ('[', '{'),
+        RightBracket => (']', '}'),
+        Backslash | NonUsBackslash => ('\\', '|'),
+        Semicolon => (';', ':'),
+        Quote => ('\'', '"'),
+        Comma => (',', '<'),
+        Period => ('.', '>'),
+        Slash => ('/', '?'),
+        Space => (' ', ' '),
+        Tab => ('\t', '\t'),
+        Enter | NumpadEnter => ('\n', '\n'),
+        Backspace => ('\u{8}', '\u{8}'),
+        NumpadDivide => ('/', '/'),
+        NumpadMultiply => ('*', '*'),
+        NumpadSubtract => ('-', '-'),
+        NumpadAdd => ('+', '+'),
+        NumpadPeriod => ('.', '.'),
+        Numpad0 => ('0', '0'),
+        Numpad1 => ('1', '1'),
+        Numpad2 => ('2', '2'),
+        Numpad3 => ('3', '3'),
+        Numpad4 => ('4', '4'),
+        Numpad5 => ('5', '5'),
+        Numpad6 => ('6', '6'),
+        Numpad7 => ('7', '7'),
+        Numpad8 => ('8', '8'),
+        Numpad9 => ('9', '9'),
+        A => letter('a'),
+        B => letter('b'),
+        C => letter('c'),
+        D => letter('d'),
+        E => letter('e'),
+        F => letter('f'),
+        G => letter('g'),
+        H => letter('h'),
+        I => letter('i'),
+        J => letter('j'),
+        K => letter('k'),
+        L => letter('l'),
+        M => letter('m'),
+        N => letter('n'),
+        O => letter('o'),
+        P => letter('p'),
+        Q => letter('q'),
+        R => letter('r'),
+        S => letter('s'),
+        T => letter('t'),
+        U => letter('u'),
+        V => letter('v'),
+        W => letter('w'),
+        X => letter('x'),
+        Y => letter('y'),
+        Z => letter('z'),
+        _ => return None,
+    })
+}
+
+// UK QWERTY moves a few symbols around, and has the extra ISO key
+fn uk(code: KeyCode) -> Option<(char, char)> {
+    use KeyCode::*;
+    Some(match code {
+        Backtick => ('`', '¬'),
+        Key2 => ('2', '"'),
+        Key3 => ('3', '£'),
+        Quote => ('\'', '@'),
+        // The key left of enter on ISO keyboards
+        Backslash => ('#', '~'),
+        _ => return None,
+    })
+}
+
+fn uk_alt_gr(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        Backtick => '¦',
+        Key4 => '€',
+        A => 'á',
+        E => 'é',
+        I => 'í',
+        O => 'ó',
+        U => 'ú',
+        _ => return None,
+    })
+}
+
+// German QWERTZ. The accents on the equals key are dead keys really, here they just
+// type themselves.
+fn german(code: KeyCode) -> Option<(char, char)> {
+    use KeyCode::*;
+    Some(match code {
+        Backtick => ('^', '°'),
+        Key2 => ('2', '"'),
+        Key3 => ('3', '§'),
+        Key6 => ('6', '&'),
+        Key7 => ('7', '/'),
+        Key8 => ('8', '('),
+        Key9 => ('9', ')'),
+        Key0 => ('0', '='),
+        Minus => ('ß', '?'),
+        Equals => ('´', '`'),
+        Y => letter('z'),
+        Z => letter('y'),
+        LeftBracket => ('ü', 'Ü'),
+        RightBracket => ('+', '*'),
+        Semicolon => ('ö', 'Ö'),
+        Quote => ('ä', 'Ä'),
+        Backslash => ('#', '\''),
+        NonUsBackslash => ('<', '>'),
+        Comma => (',', ';'),
+        Period => ('.', ':'),
+        Slash => ('-', '_'),
+        _ => return None,
+    })
+}
+
+fn german_alt_gr(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        Key2 => '²',
+        Key3 => '³',
+        Key7 => '{',
+        Key8 => '[',
+        Key9 => ']',
+        Key0 => '}',
+        Minus => '\\',
+        Q => '@',
+        E => '€',
+        RightBracket => '~',
+        NonUsBackslash => '|',
+        M => 'µ',
+        _ => return None,
+    })
+}
+
+// US Dvorak, which moves nearly everything except the digits
+fn dvorak(code: KeyCode) -> Option<(char, char)> {
+    use KeyCode::*;
+    Some(match code {
+        Minus => ('[', '{'),
+        Equals => (']', '}'),
+        Q => ('\'', '"'),
+        W => (',', '<'),
+        E => ('.', '>'),
+        R => letter('p'),
+        T => letter('y'),
+        Y => letter('f'),
+        U => letter('g'),
+        I => letter('c'),
+        O => letter('r'),
+        P => letter('l'),
+        LeftBracket => ('/', '?'),
+        RightBracket => ('=', '+'),
+        A => letter('a'),
+        S => letter('o'),
+        D => letter('e'),
+        F => letter('u'),
+        G => letter('i'),
+        H => letter('d'),
+        J => letter('h'),
+        K => letter('t'),
+        L => letter('n'),
+        Semicolon => letter('s'),
+        Quote => ('-', '_'),
+        Z => (';', ':'),
+        X => letter('q'),
+        C => letter('j'),
+        V => letter('k'),
+        B => letter('x'),
+        N => letter('b'),
+        M => letter('m'),
+        Comma => letter('w'),
+        Period => letter('v'),
+        Slash => letter('z'),
+        _ => return None,
+    })
+}
+
+fn letter(lower: char) -> (char, char) {
+    (lower, lower.to_ascii_uppercase())
+}
+
+#[test_case]
+fn test_layouts_differ() {
+    let typed = |layout: Layout, code| layout.translate(code, false, false);
+    assert_eq!(typed(Layout::Us, KeyCode::Y), Some('y'));
+    assert_eq!(typed(Layout::German, KeyCode::Y), Some('z'));
+    assert_eq!(typed(Layout::Dvorak, KeyCode::S), Some('o'));
+    assert_eq!(Layout::Uk.translate(KeyCode::Key3, true, false), Some('£'));
+    assert_eq!(Layout::Us.translate(KeyCode::Q, false, true), Some('q'));
+    // Anything a layout doesn't change is as on a US keyboard
+    assert_eq!(typed(Layout::Uk, KeyCode::A), Some('a'));
+    assert_eq!(Layout::from_name("dvorak"), Some(Layout::Dvorak));
+}
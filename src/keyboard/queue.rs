@@ -0,0 +1,88 @@
+// Key events from the interrupt handlers to whoever reads them. Only the keyboard and
+// timer interrupts push, which can't both be running at once, so pushing can just fill
+// in the slot at `head` and then move it on. Readers
+// can be anywhere, several at once: each copies out the event at `tail` and then
+// tries to move `tail` past it, and if someone else got there first it tries again
+// with the new one. Events are packed into a u64 (see `KeyEvent::pack`), so a slot
+// can be read while it's being written without tearing.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::KeyEvent;
+
+// Plenty, even for someone leaning on the keyboard
+const QUEUE_SIZE: usize = 128;
+
+pub struct EventQueue {
+    slots: [AtomicU64; QUEUE_SIZE],
+    // Both only ever count up, the slot is the count modulo `QUEUE_SIZE`
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    // Events that came in while the queue was full
+    dropped: AtomicUsize,
+}
+
+impl EventQueue {
+    pub const fn new() -> EventQueue {
+        EventQueue {
+            slots: [const { AtomicU64::new(0) }; QUEUE_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // Only ever called from the keyboard and timer interrupts, never from two places at
+    // once
+    pub fn push(&self, event: KeyEvent) {
+        let head = self.head.load(Ordering::Relaxed);
+        if head - self.tail.load(Ordering::Acquire) >= QUEUE_SIZE {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.slots[head % QUEUE_SIZE].store(event.pack(), Ordering::Relaxed);
+        self.head.store(head + 1, Ordering::Release);
+    }
+
+    pub fn pop(&self) -> Option<KeyEvent> {
+        let mut tail = self.tail.load(Ordering::Acquire);
+        loop {
+            if tail == self.head.load(Ordering::Acquire) {
+                return None;
+            }
+            // If the slot's been reused since `tail` was read, so has `tail`, and the
+            // exchange below fails
+            let packed = self.slots[tail % QUEUE_SIZE].load(Ordering::Relaxed);
+            match self.tail.compare_exchange_weak(tail, tail + 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Some(KeyEvent::unpack(packed)),
+                Err(current) => tail = current,
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[test_case]
+fn test_queue_order_and_overflow() {
+    use super::{KeyCode, KeyState, Modifiers};
+
+    let queue = EventQueue::new();
+    assert_eq!(queue.pop(), None);
+    let event = |n: usize| KeyEvent {
+        code: KeyCode::A,
+        state: KeyState::Down,
+        char: char::from_u32(n as u32),
+        modifiers: Modifiers::new(),
+    };
+    for n in 0..=QUEUE_SIZE {
+        queue.push(event(n));
+    }
+    assert_eq!(queue.dropped(), 1);
+    for n in 0..QUEUE_SIZE {
+        assert_eq!(queue.pop(), Some(event(n)));
+    }
+    assert_eq!(queue.pop(), None);
+}
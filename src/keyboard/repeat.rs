@@ -0,0 +1,111 @@
+// Key repeat done by us instead of the keyboard, for when hardware repeat can't be
+// trusted (some emulators and KVM switches get it badly wrong). While it's on, the
+// keyboard's own repeats of the key being held are thrown away, and the timer
+// interrupt (through `time::advance`) makes new ones: first after the delay, and then
+// at the rate.
+//
+// Only the key pressed last repeats, like on any keyboard. Modifiers and lock keys
+// never do.
+
+use super::scancode::{KeyCode, KeyState, RawKey};
+
+pub struct Repeater {
+    enabled: bool,
+    delay_micros: u64,
+    interval_micros: u64,
+    held: Option<KeyCode>,
+    // When the next repeat is due
+    next: u64,
+}
+
+impl Repeater {
+    pub const fn new(enabled: bool, delay_millis: u32, per_second: u32) -> Repeater {
+        Repeater {
+            enabled,
+            delay_micros: delay_millis as u64 * 1000,
+            interval_micros: 1_000_000 / per_second as u64,
+            held: None,
+            next: 0,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.held = None;
+    }
+
+    pub fn configure(&mut self, delay_millis: u32, per_second: u32) {
+        self.delay_micros = delay_millis as u64 * 1000;
+        self.interval_micros = 1_000_000 / per_second.max(1) as u64;
+    }
+
+    // Follows a key from the keyboard, and says whether it should be passed on
+    pub fn key(&mut self, key: RawKey, now: u64) -> bool {
+        if !self.enabled || never_repeats(key.code) {
+            return true;
+        }
+        match key.state {
+            // The keyboard's own repeat
+            KeyState::Down if self.held == Some(key.code) => false,
+            KeyState::Down => {
+                self.held = Some(key.code);
+                self.next = now + self.delay_micros;
+                true
+            }
+            KeyState::Up => {
+                if self.held == Some(key.code) {
+                    self.held = None;
+                }
+                true
+            }
+        }
+    }
+
+    // The key to repeat, if it's time for that
+    pub fn tick(&mut self, now: u64) -> Option<RawKey> {
+        let code = self.held?;
+        if now < self.next {
+            return None;
+        }
+        // From now, so a late tick doesn't make a burst of them
+        self.next = now + self.interval_micros;
+        Some(RawKey {
+            code,
+            state: KeyState::Down,
+        })
+    }
+}
+
+fn never_repeats(code: KeyCode) -> bool {
+    use KeyCode::*;
+    matches!(
+        code,
+        LeftShift
+            | RightShift
+            | LeftControl
+            | RightControl
+            | LeftAlt
+            | RightAlt
+            | LeftWindows
+            | RightWindows
+            | CapsLock
+            | NumLock
+            | ScrollLock
+            | Pause
+    )
+}
+
+#[test_case]
+fn test_software_repeat() {
+    let a = |state| RawKey { code: KeyCode::A, state };
+    let mut repeater = Repeater::new(true, 500, 10);
+    assert!(repeater.key(a(KeyState::Down), 0));
+    assert_eq!(repeater.tick(499_999), None);
+    assert_eq!(repeater.tick(500_000), Some(a(KeyState::Down)));
+    // The keyboard repeating it too doesn't count
+    assert!(!repeater.key(a(KeyState::Down), 550_000));
+    assert_eq!(repeater.tick(550_000), None);
+    assert_eq!(repeater.tick(600_000), Some(a(KeyState::Down)));
+    assert!(repeater.key(a(KeyState::Up), 610_000));
+    assert_eq!(repeater.tick(10_000_000), None);
+}
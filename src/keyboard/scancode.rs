@@ -0,0 +1,364 @@
+// Scancode set 1, which is what the keyboard controller hands over by default (it
+// translates whatever the keyboard itself speaks). Most keys are a single byte, with
+// the top bit set when the key is let go. Keys added after the original PC keyboard
+// come after an 0xe0 prefix, and Pause is a six byte sequence all of its own that
+// never has a release.
+//
+// Keys are named after what's printed on them on a US keyboard, whatever layout the
+// keyboard really has - this is only about which key it was.
+
+// Where the key is, by its US legend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KeyCode {
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Backtick,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    CapsLock,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Enter,
+    LeftShift,
+    // The extra key next to left shift on ISO keyboards
+    NonUsBackslash,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftControl,
+    LeftWindows,
+    LeftAlt,
+    Space,
+    RightAlt,
+    RightWindows,
+    Menu,
+    RightControl,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+    Insert,
+    Home,
+    PageUp,
+    Delete,
+    End,
+    PageDown,
+    ArrowUp,
+    ArrowLeft,
+    ArrowDown,
+    ArrowRight,
+    NumLock,
+    NumpadDivide,
+    NumpadMultiply,
+    NumpadSubtract,
+    NumpadAdd,
+    NumpadEnter,
+    NumpadPeriod,
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+}
+
+impl KeyCode {
+    // The key `code as u8` was, which is how `queue` keeps them
+    pub fn from_u8(code: u8) -> Option<KeyCode> {
+        if code > KeyCode::Numpad9 as u8 {
+            return None;
+        }
+        // Safe since every value up to the last one is a variant
+        Some(unsafe { core::mem::transmute::<u8, KeyCode>(code) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Down,
+    Up,
+}
+
+// A key going down or coming back up, before anything decides what it means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawKey {
+    pub code: KeyCode,
+    pub state: KeyState,
+}
+
+const EXTENDED: u8 = 0xe0;
+const PAUSE: u8 = 0xe1;
+const RELEASED: u8 = 0x80;
+// Pause is 0xe1 and then five more bytes
+const PAUSE_LEN: u8 = 6;
+// Sent around some extended keys to cancel out a shift the keyboard thinks the
+// key would otherwise go with
+const FAKE_SHIFT: u8 = 0x2a;
+const FAKE_RIGHT_SHIFT: u8 = 0x36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    Extended,
+    // How many bytes of the Pause sequence are still to come
+    Pause(u8),
+}
+
+// Turns scancodes into keys, one byte at a time
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder { state: State::Start }
+    }
+
+    // The key `byte` finishes, if it finishes one
+    pub fn add_byte(&mut self, byte: u8) -> Option<RawKey> {
+        match self.state {
+            State::Start => match byte {
+                EXTENDED => {
+                    self.state = State::Extended;
+                    None
+                }
+                PAUSE => {
+                    self.state = State::Pause(PAUSE_LEN - 1);
+                    None
+                }
+                _ => key(normal(byte & !RELEASED)?, byte),
+            },
+            State::Extended => {
+                self.state = State::Start;
+                match byte & !RELEASED {
+                    FAKE_SHIFT | FAKE_RIGHT_SHIFT => None,
+                    code => key(extended(code)?, byte),
+                }
+            }
+            State::Pause(1) => {
+                self.state = State::Start;
+                Some(RawKey {
+                    code: KeyCode::Pause,
+                    state: KeyState::Down,
+                })
+            }
+            State::Pause(left) => {
+                self.state = State::Pause(left - 1);
+                None
+            }
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+fn key(code: KeyCode, byte: u8) -> Option<RawKey> {
+    let state = if byte & RELEASED != 0 {
+        KeyState::Up
+    } else {
+        KeyState::Down
+    };
+    Some(RawKey { code, state })
+}
+
+fn normal(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0a => Key9,
+        0x0b => Key0,
+        0x0c => Minus,
+        0x0d => Equals,
+        0x0e => Backspace,
+        0x0f => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1a => LeftBracket,
+        0x1b => RightBracket,
+        0x1c => Enter,
+        0x1d => LeftControl,
+        0x1e => A,
+        0x1f => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => Semicolon,
+        0x28 => Quote,
+        0x29 => Backtick,
+        0x2a => LeftShift,
+        0x2b => Backslash,
+        0x2c => Z,
+        0x2d => X,
+        0x2e => C,
+        0x2f => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Period,
+        0x35 => Slash,
+        0x36 => RightShift,
+        0x37 => NumpadMultiply,
+        0x38 => LeftAlt,
+        0x39 => Space,
+        0x3a => CapsLock,
+        0x3b => F1,
+        0x3c => F2,
+        0x3d => F3,
+        0x3e => F4,
+        0x3f => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Numpad7,
+        0x48 => Numpad8,
+        0x49 => Numpad9,
+        0x4a => NumpadSubtract,
+        0x4b => Numpad4,
+        0x4c => Numpad5,
+        0x4d => Numpad6,
+        0x4e => NumpadAdd,
+        0x4f => Numpad1,
+        0x50 => Numpad2,
+        0x51 => Numpad3,
+        0x52 => Numpad0,
+        0x53 => NumpadPeriod,
+        0x56 => NonUsBackslash,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    })
+}
+
+fn extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x1c => NumpadEnter,
+        0x1d => RightControl,
+        0x35 => NumpadDivide,
+        0x37 => PrintScreen,
+        0x38 => RightAlt,
+        0x47 => Home,
+        0x48 => ArrowUp,
+        0x49 => PageUp,
+        0x4b => ArrowLeft,
+        0x4d => ArrowRight,
+        0x4f => End,
+        0x50 => ArrowDown,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5b => LeftWindows,
+        0x5c => RightWindows,
+        0x5d => Menu,
+        _ => return None,
+    })
+}
+
+#[test_case]
+fn test_decode_press_and_release() {
+    let mut decoder = Decoder::new();
+    let a = |state| Some(RawKey { code: KeyCode::A, state });
+    assert_eq!(decoder.add_byte(0x1e), a(KeyState::Down));
+    assert_eq!(decoder.add_byte(0x9e), a(KeyState::Up));
+}
+
+#[test_case]
+fn test_decode_extended_and_pause() {
+    let mut decoder = Decoder::new();
+    // Print screen, with the fake shift in front
+    assert_eq!(decoder.add_byte(0xe0), None);
+    assert_eq!(decoder.add_byte(0x2a), None);
+    assert_eq!(decoder.add_byte(0xe0), None);
+    let print_screen = decoder.add_byte(0x37).unwrap();
+    assert_eq!(print_screen.code, KeyCode::PrintScreen);
+
+    let pause = [0xe1, 0x1d, 0x45, 0xe1, 0x9d, 0xc5];
+    let keys = pause.iter().filter_map(|&byte| decoder.add_byte(byte));
+    assert!(keys.eq([RawKey { code: KeyCode::Pause, state: KeyState::Down }]));
+}
@@ -0,0 +1,139 @@
+// Raw scancodes for an async task, instead of having the interrupt handler decode
+// them. While a `ScancodeStream` exists, the handler just queues each byte and wakes
+// the task up, and the task can decode them with `scancode::Decoder` and
+// `event::Resolver` itself. The handler still takes the keyboard's answers to
+// commands, but otherwise leaves everything (the LEDs included) to the task.
+//
+// There's only ever one stream: with two, each would get half the scancodes.
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+
+const QUEUE_SIZE: usize = 256;
+
+static QUEUE: ScancodeQueue = ScancodeQueue::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+// Bytes from the interrupt handler to the stream. With only one of each, there's no
+// need for a lock: the handler only moves `head` and the stream only moves `tail`.
+struct ScancodeQueue {
+    bytes: [AtomicU8; QUEUE_SIZE],
+    // Both only ever count up, the slot is the count modulo `QUEUE_SIZE`
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    // Scancodes that came in while the queue was full
+    dropped: AtomicUsize,
+}
+
+impl ScancodeQueue {
+    const fn new() -> ScancodeQueue {
+        ScancodeQueue {
+            bytes: [const { AtomicU8::new(0) }; QUEUE_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, scancode: u8) {
+        let head = self.head.load(Ordering::Relaxed);
+        if head - self.tail.load(Ordering::Acquire) == QUEUE_SIZE {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.bytes[head % QUEUE_SIZE].store(scancode, Ordering::Relaxed);
+        self.head.store(head + 1, Ordering::Release);
+    }
+
+    fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let scancode = self.bytes[tail % QUEUE_SIZE].load(Ordering::Relaxed);
+        self.tail.store(tail + 1, Ordering::Release);
+        Some(scancode)
+    }
+
+    fn clear(&self) {
+        self.tail.store(self.head.load(Ordering::Acquire), Ordering::Release);
+    }
+}
+
+// From the interrupt handler: queues `scancode` if there's a stream for it, and says
+// whether there was
+pub(super) fn push(scancode: u8) -> bool {
+    if !TAKEN.load(Ordering::Acquire) {
+        return false;
+    }
+    QUEUE.push(scancode);
+    WAKER.wake();
+    true
+}
+
+pub struct ScancodeStream {
+    // Only made by `new`
+    _private: (),
+}
+
+impl ScancodeStream {
+    // Panics if there's a stream already
+    pub fn new() -> ScancodeStream {
+        assert!(!TAKEN.swap(true, Ordering::AcqRel), "there's a ScancodeStream already");
+        ScancodeStream { _private: () }
+    }
+
+    // How many scancodes were lost because the task didn't keep up
+    pub fn dropped(&self) -> usize {
+        QUEUE.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> ScancodeStream {
+        ScancodeStream::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // Saves taking the waker when there's something there already
+        if let Some(scancode) = QUEUE.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+        WAKER.register(cx.waker());
+        match QUEUE.pop() {
+            Some(scancode) => Poll::Ready(Some(scancode)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+// Back to the interrupt handler decoding everything. Whatever the stream didn't get
+// to is thrown away, it might end halfway through a key.
+impl Drop for ScancodeStream {
+    fn drop(&mut self) {
+        TAKEN.store(false, Ordering::Release);
+        QUEUE.clear();
+    }
+}
+
+#[test_case]
+fn test_stream_gets_scancodes() {
+    use core::task::Waker;
+
+    let mut stream = ScancodeStream::new();
+    let mut cx = Context::from_waker(Waker::noop());
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Pending);
+    // What the interrupt handler does with a scancode
+    crate::sync::without_interrupts(|| assert!(push(0x1e)));
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(Some(0x1e)));
+    drop(stream);
+    assert!(!push(0x1e));
+}
@@ -0,0 +1,133 @@
+// The kernel's backend for the `log` crate, so logging looks the same everywhere:
+//     info!("found {} MiB of memory", megabytes);
+//     warn!("no serial port, logging to the screen only");
+//     error!("page fault at {:#x}", address);
+// Any no_std crate that logs through `log` ends up here as well.
+//
+// Each message starts with the time since boot in seconds, to the microsecond, and
+// is tagged (`[    3.482113] [WARN] ...`) with the tag in the severity's color, and
+// goes wherever `print!` goes - so with `dual-console` it's on serial too. Anything
+// less important than the current verbosity is dropped before it's even formatted.
+// Everything that isn't dropped is kept in `dmesg` as well.
+//
+// How much gets through can be set per module, see `filter` for how. The filters in
+// `config::LOG_FILTERS` are applied at boot, and can be changed at runtime.
+
+use core::fmt;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::color::Color;
+use crate::config;
+use crate::console;
+use crate::dmesg;
+use crate::earlylog;
+use crate::sync::IrqSafeMutex;
+use crate::time;
+
+pub use filter::FilterError;
+use filter::Filters;
+
+mod filter;
+
+// Debug and trace messages are too chatty to show by default
+const DEFAULT_VERBOSITY: LevelFilter = LevelFilter::Info;
+
+static FILTERS: IrqSafeMutex<Filters> = IrqSafeMutex::new(Filters::new(DEFAULT_VERBOSITY));
+
+struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        metadata.level() <= FILTERS.lock().level(target)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = record.level();
+        // Taken once, so the screen and `dmesg` agree
+        let timestamp = Timestamp(time::uptime_micros());
+        dmesg::record(format_args!("{} {}{}", timestamp, tag(level), record.args()));
+        // Before the console is up, the message waits in the early log (without colors)
+        if earlylog::collect(format_args!("{} {}{}\n", timestamp, tag(level), record.args())) {
+            return;
+        }
+        console::_print_tagged(
+            format_args!("{} ", timestamp),
+            color(level),
+            tag(level),
+            format_args!("{}\n", record.args()),
+        );
+    }
+
+    // Every message is flushed as soon as it's written
+    fn flush(&self) {}
+}
+
+// Like Linux does it, `[    3.482113]`
+struct Timestamp(u64);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:5}.{:06}]", self.0 / 1_000_000, self.0 % 1_000_000)
+    }
+}
+
+fn tag(level: Level) -> &'static str {
+    match level {
+        Level::Error => "[ERROR] ",
+        Level::Warn => "[WARN] ",
+        Level::Info => "[INFO] ",
+        Level::Debug => "[DEBUG] ",
+        Level::Trace => "[TRACE] ",
+    }
+}
+
+fn color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::LightRed,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::LightCyan,
+        Level::Debug => Color::LightGray,
+        Level::Trace => Color::DarkGray,
+    }
+}
+
+// Installs the logger. Until this is called, `log` throws everything away, so it
+// should be the first thing the kernel does. Messages are held back in `earlylog`
+// until the console is up.
+pub fn init() {
+    // Only fails if a logger is already installed, which is fine
+    let _ = log::set_logger(&LOGGER);
+    if let Err(error) = set_filters(config::LOG_FILTERS) {
+        log::warn!("bad log filters {:?}: {:?}", config::LOG_FILTERS, error);
+    }
+}
+
+// The least important level that still gets printed, for modules without a filter
+pub fn set_verbosity(level: LevelFilter) {
+    update_filters(|filters| filters.set_default(level));
+}
+
+// The least important level that still gets printed from `target` and the modules
+// inside it
+pub fn set_module_verbosity(target: &str, level: LevelFilter) -> Result<(), FilterError> {
+    update_filters(|filters| filters.set(target, level))
+}
+
+// Applies filters like `vga_buffer=warn, interrupts=trace`
+pub fn set_filters(spec: &str) -> Result<(), FilterError> {
+    update_filters(|filters| filters.parse(spec))
+}
+
+fn update_filters<T, F: FnOnce(&mut Filters) -> T>(f: F) -> T {
+    let mut filters = FILTERS.lock();
+    let result = f(&mut filters);
+    // Let `log` drop whatever no filter wants before it gets to us
+    log::set_max_level(filters.max());
+    result
+}
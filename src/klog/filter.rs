@@ -0,0 +1,181 @@
+// Per-module verbosity, so a chatty subsystem can be turned down (or a buggy one
+// turned up) without touching everything else. Filters are written like this:
+//     vga_buffer=warn, interrupts=trace
+// A filter applies to the module it names and everything inside it, and the most
+// specific one wins. Names are relative to the kernel crate; other crates go by
+// their own name (`pc_keyboard=off`). A bare level sets the default for everything.
+
+use log::LevelFilter;
+
+// Plenty for a kernel this size, and it means we don't need a heap
+const MAX_FILTERS: usize = 16;
+const MAX_TARGET_LEN: usize = 48;
+
+// What `module_path!()` starts with for our own modules
+const KERNEL_CRATE: &str = env!("CARGO_CRATE_NAME");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterError {
+    // Not `target=level` or just `level`, or the level is made up
+    Invalid,
+    // The module name is longer than `MAX_TARGET_LEN`
+    TooLong,
+    // There are already `MAX_FILTERS` filters
+    TooMany,
+}
+
+struct Filter {
+    target: [u8; MAX_TARGET_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl Filter {
+    fn target(&self) -> &str {
+        // Only ever copied from a `&str`, in one piece
+        core::str::from_utf8(&self.target[..self.len]).unwrap_or("")
+    }
+}
+
+pub struct Filters {
+    default: LevelFilter,
+    filters: [Option<Filter>; MAX_FILTERS],
+}
+
+impl Filters {
+    pub const fn new(default: LevelFilter) -> Filters {
+        Filters {
+            default,
+            filters: [const { None }; MAX_FILTERS],
+        }
+    }
+
+    pub fn set_default(&mut self, level: LevelFilter) {
+        self.default = level;
+    }
+
+    pub fn set(&mut self, target: &str, level: LevelFilter) -> Result<(), FilterError> {
+        if target.is_empty() {
+            return Err(FilterError::Invalid);
+        }
+        if target.len() > MAX_TARGET_LEN {
+            return Err(FilterError::TooLong);
+        }
+        let existing = self
+            .filters
+            .iter()
+            .position(|filter| matches!(filter, Some(filter) if filter.target() == target));
+        let slot = match existing {
+            Some(index) => index,
+            None => self
+                .filters
+                .iter()
+                .position(Option::is_none)
+                .ok_or(FilterError::TooMany)?,
+        };
+        let mut filter = Filter {
+            target: [0; MAX_TARGET_LEN],
+            len: target.len(),
+            level,
+        };
+        filter.target[..target.len()].copy_from_slice(target.as_bytes());
+        self.filters[slot] = Some(filter);
+        Ok(())
+    }
+
+    // Applies a comma separated list of filters, stopping at the first bad one
+    pub fn parse(&mut self, spec: &str) -> Result<(), FilterError> {
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((target, level)) => self.set(target.trim(), parse_level(level.trim())?)?,
+                None => self.default = parse_level(entry)?,
+            }
+        }
+        Ok(())
+    }
+
+    // How much gets through from `target`, which is a full `module_path!()`
+    pub fn level(&self, target: &str) -> LevelFilter {
+        let relative = target
+            .strip_prefix(KERNEL_CRATE)
+            .and_then(|rest| rest.strip_prefix("::"));
+        self.filters
+            .iter()
+            .flatten()
+            .filter(|filter| {
+                covers(filter.target(), target)
+                    || relative.is_some_and(|relative| covers(filter.target(), relative))
+            })
+            .max_by_key(|filter| filter.len)
+            .map_or(self.default, |filter| filter.level)
+    }
+
+    // The most anything gets logged at, so `log` can skip the rest without asking us
+    pub fn max(&self) -> LevelFilter {
+        self.filters
+            .iter()
+            .flatten()
+            .map(|filter| filter.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+// Whether a filter for `module` applies to `target`, i.e. it's the module itself or
+// somewhere inside it
+fn covers(module: &str, target: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, FilterError> {
+    level.parse().map_err(|_| FilterError::Invalid)
+}
+
+#[test_case]
+fn test_parse_default_and_modules() {
+    let mut filters = Filters::new(LevelFilter::Info);
+    assert_eq!(filters.parse("warn, vga_buffer=trace, interrupts = off"), Ok(()));
+    assert_eq!(filters.level("pc_keyboard"), LevelFilter::Warn);
+    assert_eq!(filters.level("vga_buffer"), LevelFilter::Trace);
+    assert_eq!(filters.level("interrupts"), LevelFilter::Off);
+    assert_eq!(filters.max(), LevelFilter::Trace);
+}
+
+#[test_case]
+fn test_parse_rejects_bad_filters() {
+    let mut filters = Filters::new(LevelFilter::Info);
+    assert_eq!(filters.parse("vga_buffer=loud"), Err(FilterError::Invalid));
+    assert_eq!(filters.parse("=warn"), Err(FilterError::Invalid));
+    let long = [b'a'; MAX_TARGET_LEN + 1];
+    let long = core::str::from_utf8(&long).unwrap();
+    assert_eq!(filters.set(long, LevelFilter::Warn), Err(FilterError::TooLong));
+}
+
+#[test_case]
+fn test_most_specific_filter_wins() {
+    let mut filters = Filters::new(LevelFilter::Info);
+    filters.parse("vga_buffer=warn, vga_buffer::window=debug").unwrap();
+    let full = concat!(env!("CARGO_CRATE_NAME"), "::vga_buffer");
+    assert_eq!(filters.level(full), LevelFilter::Warn);
+    assert_eq!(filters.level("vga_buffer::window"), LevelFilter::Debug);
+    assert_eq!(filters.level("vga_buffer::window::frame"), LevelFilter::Debug);
+    // Only whole module names count
+    assert_eq!(filters.level("vga_buffer_extra"), LevelFilter::Info);
+}
+
+#[test_case]
+fn test_too_many_filters() {
+    let names = [
+        "m0", "m1", "m2", "m3", "m4", "m5", "m6", "m7", "m8", "m9", "m10", "m11", "m12", "m13",
+        "m14", "m15",
+    ];
+    let mut filters = Filters::new(LevelFilter::Info);
+    for name in names {
+        filters.set(name, LevelFilter::Warn).unwrap();
+    }
+    assert_eq!(filters.set("one_more", LevelFilter::Warn), Err(FilterError::TooMany));
+    // Changing an existing one doesn't need a new slot
+    assert_eq!(filters.set("m3", LevelFilter::Debug), Ok(()));
+}
@@ -0,0 +1,200 @@
+#![no_std] // don't link the Rust standard library
+#![cfg_attr(test, no_main)]
+// The standard test harness needs std, so `cargo test` uses our own runner instead.
+// It calls the generated `test_main` from `test_kernel_main`.
+#![feature(custom_test_frameworks)]
+// For the signature exception and interrupt handlers need
+#![feature(abi_x86_interrupt)]
+// For `alloc_error_handler`, to say what ran out
+#![feature(alloc_error_handler)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+// It's been BoredOS since long before there was a library to name
+#![allow(non_snake_case)]
+// The unsafe functions say what they need of their callers in plain comments, like
+// everything else here
+#![allow(clippy::missing_safety_doc)]
+
+// The kernel itself lives here, so the binary in main.rs and every integration test
+// under tests/ can boot the same thing. Each of those is a kernel of its own, with its
+// own entry point (see `bootloader::entry_point!`) and panic handler.
+
+extern crate alloc;
+
+pub mod acpi;
+pub mod allocator;
+pub mod apic;
+pub mod backtrace;
+pub mod bench;
+pub mod color;
+pub mod config;
+pub mod console;
+pub mod cpu;
+pub mod crash;
+pub mod crashdump;
+pub mod debug_channel;
+pub mod dmesg;
+pub mod earlylog;
+pub mod gdbstub;
+pub mod gdt;
+pub mod interrupts;
+pub mod ioapic;
+pub mod keyboard;
+pub mod klog;
+pub mod memory;
+pub mod mouse;
+pub mod pic;
+pub mod pit;
+pub mod ps2;
+pub mod qemu;
+pub mod serial;
+pub mod symbols;
+pub mod sync;
+pub mod time;
+pub mod trace;
+pub mod watchdog;
+// The `headless` feature leaves out the screen entirely, so everything goes to serial
+#[cfg(not(feature = "headless"))]
+pub mod tui;
+#[cfg(not(feature = "headless"))]
+pub mod vga_buffer;
+pub mod xmodem;
+
+#[cfg(test)]
+use bootloader::entry_point;
+use bootloader::BootInfo;
+use core::panic::PanicInfo;
+
+// Everything that has to be set up before the kernel can do anything useful. Output
+// is kept in the early log until `earlylog::replay`, so the caller can get the
+// screen ready first.
+pub fn init(boot_info: &'static BootInfo) {
+    klog::init();
+    // Kept in the early log for now, it shows up once the console is ready
+    log::info!("physical memory is mapped at {:#x}", boot_info.physical_memory_offset);
+    crashdump::init(boot_info);
+    memory::init(boot_info);
+    match allocator::init_heap() {
+        Ok(()) => log::info!("heap: {} at {:#x}", memory::regions::Size(config::HEAP_SIZE as u64), config::HEAP_START),
+        Err(error) => panic!("no kernel heap: {}", error),
+    }
+    log::info!("CPU: {}", cpu::features());
+    log::info!("FPU and SIMD enabled, XCR0 {:?}", cpu::fpu::init());
+    match cpu::mca::init() {
+        Some(banks) => log::info!("machine checks enabled, {} banks", banks),
+        None => log::warn!("no machine check exception, hardware errors reset the machine"),
+    }
+    gdt::init();
+    interrupts::init_idt();
+    interrupts::init_controllers(boot_info.physical_memory_offset);
+    pit::init();
+    serial::init();
+    // Timed against the PIT, so that has to be running first
+    if apic::is_enabled() {
+        log::info!("APIC timer runs at {} ticks/ms", apic::timer::init());
+    }
+    match ps2::init() {
+        Ok(()) => {
+            // Before the keyboard's IRQ is on, see `mouse::init`
+            match mouse::init() {
+                Ok(kind) => log::info!("PS/2 mouse found ({:?})", kind),
+                Err(error) => log::warn!("no PS/2 mouse: {}", error),
+            }
+            if !keyboard::init() {
+                log::warn!("no PS/2 keyboard on the first port, nothing can be typed");
+            }
+        }
+        Err(error) => log::warn!("{}, no keyboard or mouse", error),
+    }
+
+    // Nothing shows up on screen until the VGA buffer is found
+    #[cfg(not(feature = "headless"))]
+    vga_buffer::init(boot_info.physical_memory_offset);
+}
+
+// Sleeps until the next interrupt, over and over, for when there's nothing left to
+// do. With interrupts off (as after a crash) that's for good.
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+// Anything the test runner can run. Every `#[test_case]` function is one, and says
+// which test it is before running, so a test that hangs can be told apart. It also
+// has `config::TEST_TIMEOUT_MICROS` to finish in, or `watchdog` ends the run.
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        watchdog::arm(config::TEST_TIMEOUT_MICROS);
+        self();
+        watchdog::disarm();
+        serial_println!("[ok]");
+    }
+}
+
+// Runs every `#[test_case]` in the kernel, one after the other. A failed test panics,
+// and the panic handler takes it from there. Getting to the end means they all passed,
+// and QEMU exiting with `Success` is how `cargo test` finds out.
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    qemu::exit_qemu(qemu::QemuExitCode::Success);
+}
+
+// The panic handler for test kernels: the test that was running has failed
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    // Finishes the line `Testable::run` started
+    serial_println!("[failed]");
+    crash::report_panic(info);
+    // Exiting tells whoever ran QEMU right away, instead of them waiting for a timeout
+    qemu::exit_qemu(qemu::QemuExitCode::Failed);
+    hlt_loop()
+}
+
+// The panic handler for tests that are supposed to panic (see tests/should_panic.rs),
+// where panicking is the test passing. Such a test can only have the one test in it,
+// since there's no coming back from a panic.
+pub fn should_panic_handler(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    qemu::exit_qemu(qemu::QemuExitCode::Success);
+    hlt_loop()
+}
+
+// The same for a test that was supposed to panic but got to the end instead
+pub fn should_panic_failed() -> ! {
+    serial_println!("[test did not panic]");
+    qemu::exit_qemu(qemu::QemuExitCode::Failed);
+    hlt_loop()
+}
+
+// For tests that end in a crash (see `crash::expect_crash`) but carried on instead
+pub fn expected_crash_missing() -> ! {
+    serial_println!("[test did not crash]");
+    qemu::exit_qemu(qemu::QemuExitCode::Failed);
+    hlt_loop()
+}
+
+// Running the `#[test_case]`s in the modules above, with `cargo test --lib`
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    earlylog::replay();
+    test_main();
+    hlt_loop()
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -1,21 +1,80 @@
 #![no_std] // don't link the Rust standard library
 #![no_main] // disable all Rust-level entry points
+#![feature(custom_test_frameworks)]
+#![test_runner(BoredOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
-mod vga_buffer;
+// Everything but booting up is in lib.rs, so the integration tests get the same kernel
 
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
+use BoredOS::{config, earlylog, keyboard, print, serial_println, xmodem};
+#[cfg(not(feature = "headless"))]
+use BoredOS::{banner, clear, vga_buffer};
 
 // This function is called on panic
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    println!("{}", _info);
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::crash::report_panic(info);
+    BoredOS::hlt_loop()
 }
 
-#[no_mangle] // don't mangle the name of this function
-pub extern "C" fn _start() -> ! {
-    println!("Hello World{}", "!");
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
+
+// The bootloader jumps to `_start`, which `entry_point!` makes sure has the right
+// signature for it before calling this
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+
+    #[cfg(not(feature = "headless"))]
+    {
+        // Get rid of whatever the bootloader left on screen
+        clear!();
+        vga_buffer::WRITER.lock().show_cursor();
+        // Keep a status line at the bottom of the screen, out of the way of the log
+        vga_buffer::StatusBar::new(vga_buffer::StatusBarPosition::Bottom).keep_updated();
+        banner!();
+    }
+    #[cfg(feature = "headless")]
+    {
+        log::info!("BoredOS v{} (headless)", env!("CARGO_PKG_VERSION"));
+    }
+    earlylog::replay();
 
-    loop {}
+    #[cfg(test)]
+    test_main();
+
+    // Lets us tell the kernel got this far even without a screen, e.g. with `-serial stdio`
+    serial_println!("BoredOS is up");
+
+    if config::XMODEM_AT_BOOT {
+        run_payload();
+    }
+
+    // Echo whatever's typed, until there's a shell to hand it to
+    loop {
+        if let Some(character) = keyboard::next_event().char {
+            print!("{}", character);
+        }
+    }
 }
 
+// Waits for a payload on the serial console and jumps into it, or says why not
+fn run_payload() {
+    serial_println!("Waiting for an XMODEM upload");
+    match xmodem::receive_payload() {
+        Ok(len) => {
+            log::info!("xmodem: running a {} byte payload", len);
+            // Whatever the payload does is on whoever sent it
+            unsafe { xmodem::execute(xmodem::payload_start()) }
+        }
+        Err(error) => log::error!("xmodem: no payload: {:?}", error),
+    }
+}
@@ -0,0 +1,50 @@
+// Physical memory: what there is of it, and who's using which part. The bootloader
+// hands over a map of it and maps all of it at `physical_memory_offset`, and
+// `regions` makes sense of the map for everything that hands out memory, starting
+// with the frame allocators in `frames`. `paging` looks at the page tables that
+// decide where virtual addresses end up (`translate_addr` says where one does), and
+// changes them with `map_to` and `unmap`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use bootloader::BootInfo;
+
+use crate::crashdump;
+use regions::RegionKind;
+
+pub use paging::{is_mapped, map_to, translate_addr, unmap, MappingError};
+
+pub mod frames;
+pub mod paging;
+pub mod regions;
+
+// Where all of physical memory is mapped
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// Has to come after `crashdump::init`, since its area is kept out of the usable
+// memory
+pub fn init(boot_info: &'static BootInfo) {
+    PHYSICAL_MEMORY_OFFSET.store(boot_info.physical_memory_offset, Ordering::Relaxed);
+    let crash_dump = crashdump::physical_range().map(|(start, end)| (start, end, RegionKind::CrashDump));
+    let regions = regions::init(&boot_info.memory_map, crash_dump.into_iter());
+    regions.log_summary();
+    // Safe since the bootloader maps all of physical memory there, and this is the
+    // only allocator handing out the usable regions
+    match unsafe { frames::init(regions, boot_info.physical_memory_offset) } {
+        Some((stats, pool)) => {
+            log::info!("frames: {}", stats);
+            match pool {
+                Some(pool) => log::info!("frames: contiguous pool has {}", pool),
+                None => log::warn!("frames: no room for a contiguous pool"),
+            }
+        }
+        None => log::error!("frames: no room for the frame bitmap, there's no memory to hand out"),
+    }
+    // Safe since that mapping is there, and this is the only `&mut` to the tables
+    unsafe { paging::init() };
+}
+
+// The virtual address physical address 0 is mapped at, so physical address `p` is at
+// `physical_memory_offset() + p`. 0 before `init`.
+pub fn physical_memory_offset() -> u64 {
+    PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed)
+}
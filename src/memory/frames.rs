@@ -0,0 +1,127 @@
+// Handing out physical memory, a 4 KiB frame at a time. Every allocator here works
+// from the usable regions in `regions`, and implements the x86_64 crate's
+// `FrameAllocator`, which is what its page table code asks for frames through. The
+// ones that can also give frames back and hand out runs of them implement
+// `ContiguousFrameAllocator` on top of that.
+//
+// The kernel's own frames come from one `BitmapFrameAllocator`, set up by `init` and
+// shared through `KernelFrames`. `init` also takes `config::CONTIGUOUS_POOL_FRAMES`
+// of them for a `BuddyFrameAllocator`, which is where `allocate_contiguous` gets runs
+// from when they fit (it's much better at not chopping them up). `BumpFrameAllocator`
+// is for anything that needs frames before all that, and can't give them back.
+
+use core::fmt;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+
+use crate::config;
+use crate::memory::regions::{MemoryRegions, Size};
+use crate::sync::IrqSafeMutex;
+
+pub mod bitmap;
+pub mod buddy;
+pub mod bump;
+
+pub use bitmap::BitmapFrameAllocator;
+pub use buddy::BuddyFrameAllocator;
+pub use bump::BumpFrameAllocator;
+
+const FRAME_SIZE: u64 = 4096;
+
+static FRAMES: IrqSafeMutex<Option<BitmapFrameAllocator>> = IrqSafeMutex::new(None);
+static CONTIGUOUS: IrqSafeMutex<Option<BuddyFrameAllocator>> = IrqSafeMutex::new(None);
+
+pub trait ContiguousFrameAllocator: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB> {
+    // `count` frames in a row, starting at a multiple of `align` frames (which is
+    // what DMA and huge pages need)
+    fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrame>;
+
+    // Gives back what `allocate_contiguous` handed out. Unsafe since nothing can still
+    // be using any of it.
+    unsafe fn deallocate_contiguous(&mut self, start: PhysFrame, count: usize);
+
+    fn stats(&self) -> FrameStats;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    // Frames there are to hand out, all told
+    pub usable: usize,
+    pub free: usize,
+}
+
+impl FrameStats {
+    pub fn used(&self) -> usize {
+        self.usable - self.free
+    }
+}
+
+// e.g. "31890 of 32478 frames free (124.5 MiB)"
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let free = Size(self.free as u64 * FRAME_SIZE);
+        write!(f, "{} of {} frames free ({})", self.free, self.usable, free)
+    }
+}
+
+// Sets up the kernel's frame allocators over `regions`, and returns how much the
+// general one and the contiguous pool have. Unsafe for the same reasons as
+// `BitmapFrameAllocator::new`.
+pub unsafe fn init(regions: &MemoryRegions, physical_memory_offset: u64) -> Option<(FrameStats, Option<FrameStats>)> {
+    let mut allocator = BitmapFrameAllocator::new(regions, physical_memory_offset)?;
+    let pool = allocator
+        .allocate_contiguous(config::CONTIGUOUS_POOL_FRAMES, BuddyFrameAllocator::max_frames())
+        .map(|start| BuddyFrameAllocator::new(start, config::CONTIGUOUS_POOL_FRAMES, physical_memory_offset));
+    let stats = (allocator.stats(), pool.as_ref().map(BuddyFrameAllocator::stats));
+    *FRAMES.lock() = Some(allocator);
+    *CONTIGUOUS.lock() = pool;
+    Some(stats)
+}
+
+// How much the general allocator has left, None before `init`
+pub fn stats() -> Option<FrameStats> {
+    FRAMES.lock().as_ref().map(BitmapFrameAllocator::stats)
+}
+
+// And the contiguous pool
+pub fn contiguous_stats() -> Option<FrameStats> {
+    CONTIGUOUS.lock().as_ref().map(BuddyFrameAllocator::stats)
+}
+
+// See `ContiguousFrameAllocator::allocate_contiguous`. From the pool if it can be,
+// and otherwise wherever there's room.
+pub fn allocate_contiguous(count: usize, align: usize) -> Option<PhysFrame> {
+    if count <= BuddyFrameAllocator::max_frames() && align <= BuddyFrameAllocator::max_frames() {
+        let frames = CONTIGUOUS.lock().as_mut().and_then(|pool| pool.allocate_contiguous(count, align));
+        if frames.is_some() {
+            return frames;
+        }
+    }
+    FRAMES.lock().as_mut()?.allocate_contiguous(count, align)
+}
+
+pub unsafe fn deallocate_contiguous(start: PhysFrame, count: usize) {
+    if let Some(pool) = CONTIGUOUS.lock().as_mut().filter(|pool| pool.contains(start)) {
+        pool.deallocate_contiguous(start, count);
+        return;
+    }
+    if let Some(allocator) = FRAMES.lock().as_mut() {
+        allocator.deallocate_contiguous(start, count);
+    }
+}
+
+// The kernel's frame allocator, for anything that takes a `FrameAllocator`
+pub struct KernelFrames;
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        FRAMES.lock().as_mut()?.allocate_frame()
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for KernelFrames {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Some(allocator) = FRAMES.lock().as_mut() {
+            allocator.deallocate_frame(frame);
+        }
+    }
+}
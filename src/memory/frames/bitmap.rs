@@ -0,0 +1,194 @@
+// A frame allocator that can have frames back. One bit per frame of physical memory,
+// from address 0 up to the end of the last usable region, says whether it's taken:
+// everything that isn't usable starts out taken and stays that way. The bitmap itself
+// takes 32 KiB per GiB, and lives in the first usable region big enough for it.
+//
+// Single frames are found from where the last one came from, so allocating is
+// usually instant. Runs of frames (`allocate_contiguous`) are a search from the
+// bottom, which is fine for how rarely anything needs them.
+
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+use super::{ContiguousFrameAllocator, FrameStats};
+use crate::memory::regions::MemoryRegions;
+
+const FRAME_SIZE: u64 = 4096;
+const BITS: usize = 64;
+
+pub struct BitmapFrameAllocator {
+    // A set bit is a frame that's taken (or was never usable)
+    bitmap: &'static mut [u64],
+    frames: usize,
+    usable: usize,
+    free: usize,
+    // Where to start looking for the next single frame
+    next: usize,
+}
+
+impl BitmapFrameAllocator {
+    // Builds the bitmap in the usable memory of `regions` itself. Unsafe since all of
+    // physical memory has to be mapped at `physical_memory_offset`, and nothing else
+    // can be handing out frames from `regions`. None if there's no room for it.
+    pub unsafe fn new(regions: &MemoryRegions, physical_memory_offset: u64) -> Option<BitmapFrameAllocator> {
+        let frames = (regions.usable().map(|region| region.end).max()? / FRAME_SIZE) as usize;
+        let words = frames.div_ceil(BITS);
+        let bytes = (words * BITS / 8) as u64;
+        let home = regions
+            .usable()
+            // Not in frame 0, so it's never at a null physical address
+            .find(|region| region.start != 0 && region.len() >= bytes)?;
+        let bitmap = core::slice::from_raw_parts_mut((physical_memory_offset + home.start) as *mut u64, words);
+        let mut allocator = BitmapFrameAllocator::with_bitmap(bitmap, regions);
+        let first = (home.start / FRAME_SIZE) as usize;
+        for frame in first..first + bytes.div_ceil(FRAME_SIZE) as usize {
+            allocator.take(frame);
+        }
+        allocator.usable -= bytes.div_ceil(FRAME_SIZE) as usize;
+        Some(allocator)
+    }
+
+    // Keeps track of `regions` in `bitmap`, which covers as many frames as it has bits
+    pub fn with_bitmap(bitmap: &'static mut [u64], regions: &MemoryRegions) -> BitmapFrameAllocator {
+        bitmap.fill(u64::MAX);
+        let mut allocator = BitmapFrameAllocator {
+            frames: bitmap.len() * BITS,
+            bitmap,
+            usable: 0,
+            free: 0,
+            next: 0,
+        };
+        for region in regions.usable() {
+            let start = region.start.div_ceil(FRAME_SIZE) as usize;
+            let end = ((region.end / FRAME_SIZE) as usize).min(allocator.frames);
+            for frame in start..end {
+                allocator.give_back(frame);
+            }
+            allocator.usable += end.saturating_sub(start);
+        }
+        allocator
+    }
+
+    fn is_taken(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS] & (1 << (frame % BITS)) != 0
+    }
+
+    fn take(&mut self, frame: usize) {
+        debug_assert!(!self.is_taken(frame));
+        self.bitmap[frame / BITS] |= 1 << (frame % BITS);
+        self.free -= 1;
+    }
+
+    fn give_back(&mut self, frame: usize) {
+        self.bitmap[frame / BITS] &= !(1 << (frame % BITS));
+        self.free += 1;
+    }
+}
+
+fn frame_at(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
+
+fn index_of(frame: PhysFrame) -> usize {
+    (frame.start_address().as_u64() / FRAME_SIZE) as usize
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let words = self.bitmap.len();
+        // From the word the last frame came from, round to just before it
+        for offset in 0..words {
+            let word = (self.next / BITS + offset) % words;
+            let bits = self.bitmap[word];
+            if bits != u64::MAX {
+                let frame = word * BITS + bits.trailing_ones() as usize;
+                self.take(frame);
+                self.next = frame;
+                return Some(frame_at(frame));
+            }
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = index_of(frame);
+        assert!(
+            index < self.frames && self.is_taken(index),
+            "freeing frame {:#x}, which isn't allocated",
+            frame.start_address().as_u64()
+        );
+        self.give_back(index);
+    }
+}
+
+impl ContiguousFrameAllocator for BitmapFrameAllocator {
+    fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrame> {
+        assert!(count > 0 && align.is_power_of_two(), "bad contiguous allocation");
+        let mut start = 0;
+        while start + count <= self.frames {
+            match (start..start + count).find(|&frame| self.is_taken(frame)) {
+                Some(taken) => start = (taken + 1).next_multiple_of(align),
+                None => {
+                    for frame in start..start + count {
+                        self.take(frame);
+                    }
+                    return Some(frame_at(start));
+                }
+            }
+        }
+        None
+    }
+
+    unsafe fn deallocate_contiguous(&mut self, start: PhysFrame, count: usize) {
+        let first = index_of(start);
+        for frame in first..first + count {
+            self.deallocate_frame(frame_at(frame));
+        }
+    }
+
+    fn stats(&self) -> FrameStats {
+        FrameStats {
+            usable: self.usable,
+            free: self.free,
+        }
+    }
+}
+
+#[test_case]
+fn test_bitmap_allocate_and_free() {
+    use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+
+    // Made up, so none of these frames are really handed out
+    static mut BITMAP: [u64; 2] = [0; 2];
+    let mut map = MemoryMap::new();
+    for (start, end, region_type) in [
+        (0, 0x1000, MemoryRegionType::FrameZero),
+        (0x1000, 0x20000, MemoryRegionType::Usable),
+        (0x20000, 0x40000, MemoryRegionType::Reserved),
+        (0x40000, 0x80000, MemoryRegionType::Usable),
+    ] {
+        map.add_region(MemoryRegion {
+            range: FrameRange::new(start, end),
+            region_type,
+        });
+    }
+    let regions = MemoryRegions::from_memory_map(&map, core::iter::empty());
+    let bitmap = unsafe { &mut *core::ptr::addr_of_mut!(BITMAP) };
+    let mut allocator = BitmapFrameAllocator::with_bitmap(bitmap, &regions);
+    assert_eq!(allocator.stats(), FrameStats { usable: 31 + 64, free: 31 + 64 });
+
+    let frame = allocator.allocate_frame().unwrap();
+    assert_eq!(frame.start_address().as_u64(), 0x1000);
+    assert_eq!(allocator.stats().used(), 1);
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.stats().used(), 0);
+
+    // 32 frames on a 64 KiB boundary don't fit below the reserved hole
+    let run = allocator.allocate_contiguous(32, 16).unwrap();
+    assert_eq!(run.start_address().as_u64(), 0x40000);
+    assert_eq!(allocator.stats().free, 31 + 32);
+    unsafe { allocator.deallocate_contiguous(run, 32) };
+    assert_eq!(allocator.stats().free, 31 + 64);
+}
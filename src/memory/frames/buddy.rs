@@ -0,0 +1,268 @@
+// A buddy allocator, for runs of frames: DMA buffers, and 2 MiB pages one day. Memory
+// is kept in blocks of 2^order frames, each aligned to its own size, up to
+// `MAX_ORDER`. A request takes the smallest free block that fits, splitting bigger
+// ones in half as needed, and a block given back is merged with its other half (its
+// buddy) whenever that's free too - so big runs don't get chopped up for good.
+//
+// It looks after an arena handed to it whole (see `new`). The free lists run through
+// the free blocks themselves, through the mapping of physical memory, and a bitmap per
+// order at the start of the arena says which blocks are on them, so finding out
+// whether a buddy is free doesn't mean walking a list.
+
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+use super::{ContiguousFrameAllocator, FrameStats};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS: usize = 64;
+// 4 MiB blocks, so there are always whole 2 MiB ones to hand out
+pub const MAX_ORDER: usize = 10;
+const ORDERS: usize = MAX_ORDER + 1;
+// End of a free list
+const NONE: u64 = u64::MAX;
+
+// At the start of every free block
+#[repr(C)]
+struct FreeBlock {
+    // Physical addresses, or `NONE`
+    next: u64,
+    prev: u64,
+}
+
+pub struct BuddyFrameAllocator {
+    physical_memory_offset: u64,
+    // In frames: the arena, and where block numbering starts (aligned to the biggest
+    // block, so every block is aligned in physical memory too)
+    start: usize,
+    end: usize,
+    base: usize,
+    // One bit per block of each order, set while that block is on its free list
+    bitmap: &'static mut [u64],
+    bitmap_offsets: [usize; ORDERS],
+    free_lists: [u64; ORDERS],
+    usable: usize,
+    free: usize,
+}
+
+impl BuddyFrameAllocator {
+    // Looks after the `count` frames from `start`, keeping its bitmap in the first few.
+    // Unsafe since all of physical memory has to be mapped at `physical_memory_offset`,
+    // and the frames can't be in use by anything else.
+    pub unsafe fn new(start: PhysFrame, count: usize, physical_memory_offset: u64) -> BuddyFrameAllocator {
+        let start = index_of(start);
+        let end = start + count;
+        let base = start & !((1 << MAX_ORDER) - 1);
+        let mut bitmap_offsets = [0; ORDERS];
+        let mut words = 0;
+        for (order, offset) in bitmap_offsets.iter_mut().enumerate() {
+            *offset = words;
+            words += ((end - base) >> order).div_ceil(BITS);
+        }
+        let bitmap_frames = (words * 8).div_ceil(FRAME_SIZE as usize);
+        assert!(bitmap_frames < count, "buddy arena too small for its own bitmap");
+        let address = physical_memory_offset + (start as u64 * FRAME_SIZE);
+        let bitmap = core::slice::from_raw_parts_mut(address as *mut u64, words);
+        bitmap.fill(0);
+
+        let mut allocator = BuddyFrameAllocator {
+            physical_memory_offset,
+            start,
+            end,
+            base,
+            bitmap,
+            bitmap_offsets,
+            free_lists: [NONE; ORDERS],
+            usable: end - start - bitmap_frames,
+            free: 0,
+        };
+        allocator.free_range(start + bitmap_frames, end);
+        allocator
+    }
+
+    // The biggest run that can be asked for, in frames
+    pub const fn max_frames() -> usize {
+        1 << MAX_ORDER
+    }
+
+    pub fn contains(&self, frame: PhysFrame) -> bool {
+        (self.start..self.end).contains(&index_of(frame))
+    }
+
+    // A block of 2^order frames
+    fn allocate_order(&mut self, order: usize) -> Option<usize> {
+        let mut from = (order..ORDERS).find(|&from| self.free_lists[from] != NONE)?;
+        let block = self.pop(from);
+        // Hand the halves we don't need back, top half first
+        while from > order {
+            from -= 1;
+            self.push(block + (1 << from), from);
+        }
+        self.free -= 1 << order;
+        Some(block)
+    }
+
+    fn free_block(&mut self, mut block: usize, mut order: usize) {
+        self.free += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = block ^ (1 << order);
+            if buddy < self.start || buddy + (1 << order) > self.end || !self.is_free(buddy, order) {
+                break;
+            }
+            self.remove(buddy, order);
+            block = block.min(buddy);
+            order += 1;
+        }
+        self.push(block, order);
+    }
+
+    // Frees every frame from `start` up to `end`, in the biggest blocks that fit
+    fn free_range(&mut self, mut start: usize, end: usize) {
+        while start < end {
+            let order = (0..ORDERS)
+                .rev()
+                .find(|&order| start.is_multiple_of(1 << order) && start + (1 << order) <= end)
+                .unwrap_or(0);
+            self.free_block(start, order);
+            start += 1 << order;
+        }
+    }
+
+    fn bit(&self, block: usize, order: usize) -> (usize, u64) {
+        let index = (block - self.base) >> order;
+        (self.bitmap_offsets[order] + index / BITS, 1 << (index % BITS))
+    }
+
+    fn is_free(&self, block: usize, order: usize) -> bool {
+        let (word, mask) = self.bit(block, order);
+        self.bitmap[word] & mask != 0
+    }
+
+    fn header(&self, block: u64) -> *mut FreeBlock {
+        (self.physical_memory_offset + block) as *mut FreeBlock
+    }
+
+    fn push(&mut self, block: usize, order: usize) {
+        let (word, mask) = self.bit(block, order);
+        self.bitmap[word] |= mask;
+        let address = block as u64 * FRAME_SIZE;
+        let head = self.free_lists[order];
+        // Safe since the block is free, and ours
+        unsafe {
+            *self.header(address) = FreeBlock { next: head, prev: NONE };
+            if head != NONE {
+                (*self.header(head)).prev = address;
+            }
+        }
+        self.free_lists[order] = address;
+    }
+
+    fn pop(&mut self, order: usize) -> usize {
+        let block = (self.free_lists[order] / FRAME_SIZE) as usize;
+        self.remove(block, order);
+        block
+    }
+
+    fn remove(&mut self, block: usize, order: usize) {
+        let (word, mask) = self.bit(block, order);
+        self.bitmap[word] &= !mask;
+        let address = block as u64 * FRAME_SIZE;
+        // Safe since the block is on the list, so it has a header
+        unsafe {
+            let FreeBlock { next, prev } = *self.header(address);
+            if prev == NONE {
+                self.free_lists[order] = next;
+            } else {
+                (*self.header(prev)).next = next;
+            }
+            if next != NONE {
+                (*self.header(next)).prev = prev;
+            }
+        }
+    }
+}
+
+// The smallest order with at least `frames` frames
+fn order_for(frames: usize) -> usize {
+    frames.next_power_of_two().trailing_zeros() as usize
+}
+
+fn frame_at(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
+
+fn index_of(frame: PhysFrame) -> usize {
+    (frame.start_address().as_u64() / FRAME_SIZE) as usize
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_order(0).map(frame_at)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate_contiguous(frame, 1);
+    }
+}
+
+impl ContiguousFrameAllocator for BuddyFrameAllocator {
+    fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrame> {
+        assert!(count > 0 && align.is_power_of_two(), "bad contiguous allocation");
+        let order = order_for(count).max(order_for(align));
+        if order > MAX_ORDER {
+            return None;
+        }
+        let block = self.allocate_order(order)?;
+        // A block is all or nothing, but whatever's past `count` can go straight back
+        self.free_range(block + count, block + (1 << order));
+        Some(frame_at(block))
+    }
+
+    unsafe fn deallocate_contiguous(&mut self, start: PhysFrame, count: usize) {
+        let first = index_of(start);
+        assert!(
+            first >= self.start && first + count <= self.end,
+            "freeing frames at {:#x} that aren't from this buddy allocator",
+            start.start_address().as_u64()
+        );
+        self.free_range(first, first + count);
+    }
+
+    fn stats(&self) -> FrameStats {
+        FrameStats {
+            usable: self.usable,
+            free: self.free,
+        }
+    }
+}
+
+#[test_case]
+fn test_buddy_split_and_merge() {
+    // Real frames this time, since the free lists live in them
+    const FRAMES: usize = 1 << MAX_ORDER;
+    let offset = crate::memory::physical_memory_offset();
+    let arena = super::allocate_contiguous(FRAMES, FRAMES).expect("no memory for the buddy test");
+    let mut buddy = unsafe { BuddyFrameAllocator::new(arena, FRAMES, offset) };
+    let usable = buddy.stats().usable;
+    assert_eq!(buddy.stats().free, usable);
+
+    let one = buddy.allocate_frame().unwrap();
+    let three = buddy.allocate_contiguous(3, 4).unwrap();
+    assert_eq!(three.start_address().as_u64() % (4 * FRAME_SIZE), 0);
+    assert_eq!(buddy.stats().used(), 4);
+    // 2 MiB, aligned to match
+    let huge = buddy.allocate_contiguous(512, 512).unwrap();
+    assert_eq!(huge.start_address().as_u64() % 0x20_0000, 0);
+    unsafe {
+        buddy.deallocate_contiguous(huge, 512);
+        buddy.deallocate_contiguous(three, 3);
+        buddy.deallocate_frame(one);
+    }
+    // Everything merged back together
+    assert_eq!(buddy.stats().free, usable);
+    assert!(buddy.allocate_contiguous(512, 512).is_some());
+
+    unsafe { super::deallocate_contiguous(arena, FRAMES) };
+}
@@ -0,0 +1,81 @@
+// The simplest frame allocator there is: frames are handed out in address order, one
+// usable region after the other, and never given back. That's all it takes to build
+// the first page tables and the heap, before there's anything smarter.
+
+use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+use crate::memory::regions::MemoryRegions;
+
+const FRAME_SIZE: u64 = 4096;
+
+pub struct BumpFrameAllocator {
+    regions: &'static MemoryRegions,
+    // Which usable region the next frame comes from, and where in it
+    region: usize,
+    next: u64,
+    allocated: usize,
+}
+
+impl BumpFrameAllocator {
+    // Only one allocator should hand out frames from `regions`, or they'd hand out
+    // the same ones
+    pub fn new(regions: &'static MemoryRegions) -> BumpFrameAllocator {
+        BumpFrameAllocator {
+            regions,
+            region: 0,
+            next: regions.usable().next().map_or(0, |region| region.start),
+            allocated: 0,
+        }
+    }
+
+    // How many frames have been handed out
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BumpFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        loop {
+            let region = self.regions.usable().nth(self.region)?;
+            // Regions are whole frames, but there's no harm in making sure
+            let start = (self.next.max(region.start) + FRAME_SIZE - 1) & !(FRAME_SIZE - 1);
+            if start + FRAME_SIZE <= region.end {
+                self.next = start + FRAME_SIZE;
+                self.allocated += 1;
+                return Some(PhysFrame::containing_address(PhysAddr::new(start)));
+            }
+            self.region += 1;
+        }
+    }
+}
+
+#[test_case]
+fn test_bump_through_regions() {
+    use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+
+    // Made up, so none of these frames are really handed out
+    static REGIONS: spin::Once<MemoryRegions> = spin::Once::new();
+    let regions = REGIONS.call_once(|| {
+        let mut map = MemoryMap::new();
+        for (start, end, region_type) in [
+            (0x1000, 0x3000, MemoryRegionType::Usable),
+            (0x3000, 0x10_0000, MemoryRegionType::Reserved),
+            (0x10_0000, 0x10_1000, MemoryRegionType::Usable),
+        ] {
+            map.add_region(MemoryRegion {
+                range: FrameRange::new(start, end),
+                region_type,
+            });
+        }
+        MemoryRegions::from_memory_map(&map, core::iter::empty())
+    });
+    let mut allocator = BumpFrameAllocator::new(regions);
+    let mut next = || allocator.allocate_frame().map(|frame| frame.start_address().as_u64());
+    assert_eq!(next(), Some(0x1000));
+    assert_eq!(next(), Some(0x2000));
+    assert_eq!(next(), Some(0x10_0000));
+    assert_eq!(next(), None);
+    assert_eq!(allocator.allocated(), 3);
+}
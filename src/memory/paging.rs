@@ -0,0 +1,420 @@
+// The page tables: what each virtual address actually goes to. CR3 says where the
+// level 4 table is, and every table is reached through the mapping of physical memory,
+// so all of this only works after `memory::init`.
+//
+// `mappings` walks the active tables and hands back every page that's mapped, in
+// address order, and `dump_mappings` prints them out, runs of neighbouring pages that
+// go on in physical memory too squashed into one line. That's most of what there is
+// to do when the next page fault makes no sense.
+//
+// New mappings go in through `map_to` and come out with `unmap`, which make whatever
+// tables are missing on the way down (with frames from `frames`) and flush the TLB
+// for the page. There's just the one CPU, so that's all the flushing there is to do.
+
+use core::fmt;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{MapToError, UnmapError};
+use x86_64::structures::paging::{Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::frames::KernelFrames;
+use super::physical_memory_offset;
+use crate::sync::IrqSafeMutex;
+
+const ENTRIES: usize = 512;
+const LEVELS: usize = 4;
+// The flags every level's entry has a say in
+const INHERITED: PageTableFlags = PageTableFlags::WRITABLE
+    .union(PageTableFlags::USER_ACCESSIBLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+// The active tables, for changing them. Nothing else may make a `&mut` to them.
+static MAPPER: IrqSafeMutex<Option<OffsetPageTable<'static>>> = IrqSafeMutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingError {
+    // Before `memory::init`
+    NoPageTables,
+    // No frame to make a table out of
+    OutOfFrames,
+    AlreadyMapped(PhysFrame),
+    NotMapped,
+    // The page is inside a 2 MiB or 1 GiB one
+    InHugePage,
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MappingError::NoPageTables => write!(f, "the page tables haven't been set up"),
+            MappingError::OutOfFrames => write!(f, "no frames left for a page table"),
+            MappingError::AlreadyMapped(frame) => {
+                write!(f, "already mapped to {:#x}", frame.start_address().as_u64())
+            }
+            MappingError::NotMapped => write!(f, "not mapped"),
+            MappingError::InHugePage => write!(f, "part of a huge page"),
+        }
+    }
+}
+
+// How big a page one entry maps, which depends on which level it's in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingSize {
+    Page4KiB,
+    Page2MiB,
+    Page1GiB,
+}
+
+impl MappingSize {
+    pub fn bytes(self) -> u64 {
+        match self {
+            MappingSize::Page4KiB => 0x1000,
+            MappingSize::Page2MiB => 0x20_0000,
+            MappingSize::Page1GiB => 0x4000_0000,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            MappingSize::Page4KiB => "4K",
+            MappingSize::Page2MiB => "2M",
+            MappingSize::Page1GiB => "1G",
+        }
+    }
+}
+
+// One mapped page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub page: VirtAddr,
+    pub frame: PhysAddr,
+    pub size: MappingSize,
+    // The last level's flags, except that it's only WRITABLE or USER_ACCESSIBLE if
+    // every level above says so too, and NO_EXECUTE if any of them does - which is
+    // what the CPU goes by
+    pub flags: PageTableFlags,
+}
+
+impl Mapping {
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        address >= self.page && address.as_u64() - self.page.as_u64() < self.size.bytes()
+    }
+}
+
+// Where the level 4 table CR3 points at is
+pub fn active_level_4_frame() -> PhysFrame {
+    Cr3::read().0
+}
+
+// The active level 4 table. Unsafe since all of physical memory has to be mapped
+// already, and the table mustn't be handed out twice (nothing checks).
+pub unsafe fn active_level_4_table() -> &'static mut PageTable {
+    &mut *table_at(active_level_4_frame().start_address())
+}
+
+// Makes the active tables available to `map_to` and `unmap`. Unsafe for the same
+// reasons as `active_level_4_table`.
+pub(super) unsafe fn init() {
+    let offset = VirtAddr::new(physical_memory_offset());
+    *MAPPER.lock() = Some(OffsetPageTable::new(active_level_4_table(), offset));
+}
+
+// Maps `page` to `frame`. Tables that get made on the way are writable, and user
+// accessible if `flags` is, so `flags` alone decides what the page allows.
+//
+// Unsafe since anything could be in `frame`: mapping something in use twice, e.g.
+// a page table, can break memory safety from the other side.
+pub unsafe fn map_to(page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), MappingError> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MappingError::NoPageTables)?;
+    let table_flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | (flags & PageTableFlags::USER_ACCESSIBLE);
+    let flush = mapper
+        .map_to_with_table_flags(page, frame, flags | PageTableFlags::PRESENT, table_flags, &mut KernelFrames)
+        .map_err(|error| match error {
+            MapToError::FrameAllocationFailed => MappingError::OutOfFrames,
+            MapToError::PageAlreadyMapped(frame) => MappingError::AlreadyMapped(frame),
+            MapToError::ParentEntryHugePage => MappingError::InHugePage,
+        })?;
+    flush.flush();
+    Ok(())
+}
+
+// Takes `page` out of the tables, and gives back the frame it was mapped to, which
+// is the caller's to free (or not). The tables themselves are kept, even if this was
+// the last page in them.
+//
+// Unsafe since nothing can still be using anything in the page.
+pub unsafe fn unmap(page: Page) -> Result<PhysFrame, MappingError> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MappingError::NoPageTables)?;
+    let (frame, flush) = mapper.unmap(page).map_err(|error| match error {
+        UnmapError::ParentEntryHugePage => MappingError::InHugePage,
+        UnmapError::PageNotMapped | UnmapError::InvalidFrameAddress(_) => MappingError::NotMapped,
+    })?;
+    flush.flush();
+    Ok(frame)
+}
+
+// The physical address `address` is mapped to, if it's mapped. This walks the tables
+// itself instead of going through `MAPPER`, so it's fine to call from anywhere, a
+// page fault handler included.
+pub fn translate_addr(address: VirtAddr) -> Option<PhysAddr> {
+    let indices = [address.p4_index(), address.p3_index(), address.p2_index(), address.p1_index()];
+    let mut table = table_at(active_level_4_frame().start_address());
+    for (depth, &index) in indices.iter().enumerate() {
+        // Safe for the same reasons as in `Mappings::next`
+        let table_ref = unsafe { &*table };
+        let entry = &table_ref[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        if let Some(size) = page_size(depth, entry.flags()) {
+            return Some(entry.addr() + (address.as_u64() & (size.bytes() - 1)));
+        }
+        table = table_at(entry.addr());
+    }
+    unreachable!("level 1 entries always map a page")
+}
+
+// Whether all `len` bytes from `start` are mapped, so reading them can't fault. Like
+// `translate_addr`, this is fine to call from anywhere, a crash handler included. Before
+// `memory::init` there are no tables to look at, so nothing is.
+pub fn is_mapped(start: u64, len: u64) -> bool {
+    if physical_memory_offset() == 0 {
+        return false;
+    }
+    if len == 0 {
+        return true;
+    }
+    let Some(last) = start.checked_add(len - 1) else {
+        return false;
+    };
+    let (Ok(start), Ok(last)) = (VirtAddr::try_new(start), VirtAddr::try_new(last)) else {
+        return false;
+    };
+    let start: Page = Page::containing_address(start);
+    let last: Page = Page::containing_address(last);
+    Page::range_inclusive(start, last).all(|page| translate_addr(page.start_address()).is_some())
+}
+
+// How big a page an entry at `depth` (0 for the level 4 table) maps, or None if it
+// points at another table instead
+fn page_size(depth: usize, flags: PageTableFlags) -> Option<MappingSize> {
+    match depth {
+        3 => Some(MappingSize::Page4KiB),
+        2 if flags.contains(PageTableFlags::HUGE_PAGE) => Some(MappingSize::Page2MiB),
+        1 if flags.contains(PageTableFlags::HUGE_PAGE) => Some(MappingSize::Page1GiB),
+        _ => None,
+    }
+}
+
+fn table_at(address: PhysAddr) -> *mut PageTable {
+    (physical_memory_offset() + address.as_u64()) as *mut PageTable
+}
+
+// Every page mapped in the active tables, lowest address first
+pub fn mappings() -> Mappings {
+    let level_4 = table_at(active_level_4_frame().start_address());
+    Mappings {
+        tables: [level_4; LEVELS],
+        indices: [0; LEVELS],
+        flags: [PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE; LEVELS],
+        depth: 0,
+    }
+}
+
+// Walks the tables a level at a time, depth 0 being the level 4 table
+pub struct Mappings {
+    tables: [*const PageTable; LEVELS],
+    indices: [usize; LEVELS],
+    // What the entries leading down to each table allow, as `Mapping::flags` says (just
+    // the `INHERITED` ones)
+    flags: [PageTableFlags; LEVELS],
+    depth: usize,
+}
+
+impl Mappings {
+    // Where the entry we're at maps
+    fn address(&self) -> VirtAddr {
+        let address = (0..=self.depth)
+            .map(|depth| (self.indices[depth] as u64) << (12 + 9 * (LEVELS - 1 - depth)))
+            .sum();
+        VirtAddr::new_truncate(address)
+    }
+}
+
+impl Iterator for Mappings {
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping> {
+        loop {
+            let index = self.indices[self.depth];
+            if index == ENTRIES {
+                if self.depth == 0 {
+                    return None;
+                }
+                self.depth -= 1;
+                self.indices[self.depth] += 1;
+                continue;
+            }
+            // Safe since tables are only ever reached through entries that are
+            // present, and all of physical memory is mapped
+            let table = unsafe { &*self.tables[self.depth] };
+            let entry = &table[index];
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::PRESENT) {
+                self.indices[self.depth] += 1;
+                continue;
+            }
+            let allowed = (self.flags[self.depth] & (flags | PageTableFlags::NO_EXECUTE))
+                | (flags & PageTableFlags::NO_EXECUTE);
+
+            if let Some(size) = page_size(self.depth, flags) {
+                let page = self.address();
+                self.indices[self.depth] += 1;
+                let flags = (flags - INHERITED) | allowed;
+                return Some(Mapping { page, frame: entry.addr(), size, flags });
+            }
+            self.depth += 1;
+            self.tables[self.depth] = table_at(entry.addr());
+            self.indices[self.depth] = 0;
+            self.flags[self.depth] = allowed;
+        }
+    }
+}
+
+// Pages in a row that are in a row in physical memory as well, with the same size and
+// flags
+struct Run {
+    first: Mapping,
+    pages: u64,
+}
+
+impl Run {
+    fn extends(&self, next: &Mapping) -> bool {
+        let bytes = self.pages * self.first.size.bytes();
+        next.size == self.first.size
+            && next.flags == self.first.flags
+            && next.page.as_u64() == self.first.page.as_u64().wrapping_add(bytes)
+            && next.frame.as_u64() == self.first.frame.as_u64() + bytes
+    }
+}
+
+// e.g. "ffff800000000000-ffff800007ffffff -> 0000000000-0007ffffff rw-g 64x2M"
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.pages * self.first.size.bytes();
+        let (page, frame, flags) = (self.first.page.as_u64(), self.first.frame.as_u64(), self.first.flags);
+        let flag = |flag, c| if flags.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{:016x}-{:016x} -> {:010x}-{:010x} r{}{}{} {}x{}",
+            page,
+            page.wrapping_add(bytes - 1),
+            frame,
+            frame + bytes - 1,
+            flag(PageTableFlags::WRITABLE, 'w'),
+            if flags.contains(PageTableFlags::NO_EXECUTE) { '-' } else { 'x' },
+            flag(PageTableFlags::GLOBAL, 'g'),
+            self.pages,
+            self.first.size.name(),
+        )?;
+        if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+            write!(f, " user")?;
+        }
+        Ok(())
+    }
+}
+
+// Prints everything the active tables map
+pub fn dump_mappings() {
+    crate::println!("page tables at {:#x}:", active_level_4_frame().start_address().as_u64());
+    let mut run: Option<Run> = None;
+    for mapping in mappings() {
+        match run.as_mut() {
+            Some(run) if run.extends(&mapping) => run.pages += 1,
+            _ => {
+                if let Some(run) = run.replace(Run { first: mapping, pages: 1 }) {
+                    crate::println!("{}", run);
+                }
+            }
+        }
+    }
+    if let Some(run) = run {
+        crate::println!("{}", run);
+    }
+}
+
+#[test_case]
+fn test_mappings_find_the_kernel() {
+    // Some of our own code, which must be mapped and executable
+    let code = VirtAddr::new(mappings as fn() -> Mappings as usize as u64);
+    let mut last = None;
+    let mut found = None;
+    for mapping in mappings() {
+        assert!(last.is_none_or(|last| mapping.page > last), "mappings out of order");
+        last = Some(mapping.page);
+        if mapping.contains(code) {
+            found = Some(mapping);
+        }
+    }
+    let found = found.expect("the kernel's code isn't mapped");
+    assert!(!found.flags.contains(PageTableFlags::NO_EXECUTE));
+    // And the physical memory mapping is there, where `memory::init` was told it is
+    let offset = VirtAddr::new(physical_memory_offset());
+    assert!(mappings().any(|mapping| mapping.contains(offset)));
+}
+
+#[test_case]
+fn test_map_and_unmap() {
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+    // Nowhere anything is mapped
+    let page: Page = Page::containing_address(VirtAddr::new(0x5555_0000_0000));
+    assert!(!mappings().any(|mapping| mapping.contains(page.start_address())));
+    let frame = KernelFrames.allocate_frame().unwrap();
+    unsafe {
+        map_to(page, frame, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE).unwrap();
+        assert_eq!(map_to(page, frame, PageTableFlags::WRITABLE), Err(MappingError::AlreadyMapped(frame)));
+        // Written through the new page, read through the physical memory mapping
+        page.start_address().as_mut_ptr::<u64>().write_volatile(0x1234_5678);
+        let physical = (physical_memory_offset() + frame.start_address().as_u64()) as *const u64;
+        assert_eq!(physical.read_volatile(), 0x1234_5678);
+        let inside = page.start_address() + 0x123u64;
+        assert_eq!(translate_addr(inside), Some(frame.start_address() + 0x123u64));
+
+        assert_eq!(unmap(page), Ok(frame));
+        assert_eq!(unmap(page), Err(MappingError::NotMapped));
+        assert_eq!(translate_addr(page.start_address()), None);
+        KernelFrames.deallocate_frame(frame);
+    }
+    assert!(!mappings().any(|mapping| mapping.contains(page.start_address())));
+}
+
+#[test_case]
+fn test_translate_addr() {
+    // Something on the stack, looked at through where it's said to be
+    let value = 0xfeed_beef_u64;
+    let physical = translate_addr(VirtAddr::from_ptr(&value)).expect("the stack isn't mapped");
+    let alias = (physical_memory_offset() + physical.as_u64()) as *const u64;
+    assert_eq!(unsafe { alias.read_volatile() }, value);
+    // The physical memory mapping maps everything to itself, huge pages or not
+    let address = PhysAddr::new(0x12_3456);
+    assert_eq!(translate_addr(VirtAddr::new(physical_memory_offset() + address.as_u64())), Some(address));
+    assert_eq!(translate_addr(VirtAddr::new(0)), None);
+}
+
+#[test_case]
+fn test_is_mapped() {
+    let value = [0u64; 4];
+    assert!(is_mapped(value.as_ptr() as u64, 32));
+    assert!(!is_mapped(0, 8));
+    // Starting on a mapped page doesn't help if it runs on into an unmapped one
+    let code = is_mapped as fn(u64, u64) -> bool as usize as u64;
+    assert!(!is_mapped(code, 0x1000_0000_0000));
+    // Past the end of the address space, or into the non-canonical hole
+    assert!(!is_mapped(u64::MAX, 8));
+    assert!(!is_mapped(0x0000_7fff_ffff_fff8, 16));
+}
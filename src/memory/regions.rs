@@ -0,0 +1,261 @@
+// The bootloader's memory map, boiled down to what the kernel needs to know: which
+// ranges of physical memory are free to use, and what everything else is. The map
+// already says where the kernel, its stack, the page tables and the boot info were
+// put; on top of that, ranges the kernel keeps for itself later (like the crash dump
+// area) are carved out of the usable memory before anyone gets to hand it out.
+//
+// Neighbouring ranges of the same kind are merged, and everything's kept in address
+// order.
+
+use core::fmt;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::Once;
+
+// The bootloader's map has at most 64, and each carve-out can split one in three
+const MAX_REGIONS: usize = 128;
+const PAGE_SIZE: u64 = 4096;
+
+static REGIONS: Once<MemoryRegions> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Usable,
+    // The kernel image and its stack
+    Kernel,
+    // What the bootloader set up for us and is still in use: the page tables and the
+    // boot info
+    Boot,
+    // The bootloader's own code and data
+    Bootloader,
+    // ACPI tables, which could be given back once they've been read
+    AcpiReclaimable,
+    // Firmware's, for good
+    AcpiNvs,
+    Reserved,
+    Bad,
+    CrashDump,
+}
+
+impl RegionKind {
+    fn from_bootloader(region_type: MemoryRegionType) -> Option<RegionKind> {
+        Some(match region_type {
+            MemoryRegionType::Usable => RegionKind::Usable,
+            MemoryRegionType::Kernel | MemoryRegionType::KernelStack => RegionKind::Kernel,
+            MemoryRegionType::PageTable | MemoryRegionType::BootInfo | MemoryRegionType::Package => {
+                RegionKind::Boot
+            }
+            MemoryRegionType::Bootloader => RegionKind::Bootloader,
+            MemoryRegionType::AcpiReclaimable => RegionKind::AcpiReclaimable,
+            MemoryRegionType::AcpiNvs => RegionKind::AcpiNvs,
+            MemoryRegionType::BadMemory => RegionKind::Bad,
+            MemoryRegionType::Empty => return None,
+            // Frame zero too, so a null physical address is never handed out
+            _ => RegionKind::Reserved,
+        })
+    }
+}
+
+// Physical memory from `start` up to (but not including) `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub kind: RegionKind,
+}
+
+impl Region {
+    const EMPTY: Region = Region {
+        start: 0,
+        end: 0,
+        kind: RegionKind::Reserved,
+    };
+
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+pub struct MemoryRegions {
+    regions: [Region; MAX_REGIONS],
+    len: usize,
+}
+
+impl MemoryRegions {
+    const fn new() -> MemoryRegions {
+        MemoryRegions {
+            regions: [Region::EMPTY; MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    // `memory_map` with each of `reserved` (start, end and what it is) carved out of
+    // the usable memory, to whole pages
+    pub fn from_memory_map<I>(memory_map: &MemoryMap, reserved: I) -> MemoryRegions
+    where
+        I: Iterator<Item = (u64, u64, RegionKind)>,
+    {
+        let mut sorted = MemoryRegions::new();
+        for region in memory_map.iter() {
+            if let Some(kind) = RegionKind::from_bootloader(region.region_type) {
+                sorted.insert(Region {
+                    start: region.range.start_addr(),
+                    end: region.range.end_addr(),
+                    kind,
+                });
+            }
+        }
+        let mut regions = sorted.merged();
+        for (start, end, kind) in reserved {
+            let (start, end) = (start & !(PAGE_SIZE - 1), (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1));
+            regions = regions.carve_out(start, end, kind);
+        }
+        regions
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions[..self.len].iter()
+    }
+
+    pub fn usable(&self) -> impl Iterator<Item = &Region> {
+        self.iter().filter(|region| region.kind == RegionKind::Usable)
+    }
+
+    // How many bytes of memory are `kind`
+    pub fn total(&self, kind: RegionKind) -> u64 {
+        self.iter().filter(|region| region.kind == kind).map(Region::len).sum()
+    }
+
+    // Every region, and then how much there is of what
+    pub fn log_summary(&self) {
+        for region in self.iter() {
+            log::info!("memory: {:#012x}-{:#012x} {:?}", region.start, region.end - 1, region.kind);
+        }
+        let all: u64 = self.iter().map(Region::len).sum();
+        log::info!(
+            "memory: {} usable of {}, kernel {}, boot {}",
+            Size(self.total(RegionKind::Usable)),
+            Size(all),
+            Size(self.total(RegionKind::Kernel)),
+            Size(self.total(RegionKind::Boot)),
+        );
+    }
+
+    fn push(&mut self, region: Region) {
+        if region.is_empty() {
+            return;
+        }
+        if let Some(last) = self.regions[..self.len].last_mut() {
+            if last.kind == region.kind && last.end == region.start {
+                last.end = region.end;
+                return;
+            }
+        }
+        if self.len == MAX_REGIONS {
+            log::warn!("memory: too many regions, ignoring {:#x}-{:#x}", region.start, region.end);
+            return;
+        }
+        self.regions[self.len] = region;
+        self.len += 1;
+    }
+
+    // Keeps the regions in address order, the map doesn't have to be
+    fn insert(&mut self, region: Region) {
+        if self.len == MAX_REGIONS {
+            log::warn!("memory: too many regions, ignoring {:#x}-{:#x}", region.start, region.end);
+            return;
+        }
+        let index = self.regions[..self.len].partition_point(|other| other.start <= region.start);
+        self.regions.copy_within(index..self.len, index + 1);
+        self.regions[index] = region;
+        self.len += 1;
+    }
+
+    fn merged(&self) -> MemoryRegions {
+        let mut merged = MemoryRegions::new();
+        for &region in self.iter() {
+            merged.push(region);
+        }
+        merged
+    }
+
+    fn carve_out(&self, start: u64, end: u64, kind: RegionKind) -> MemoryRegions {
+        let mut carved = MemoryRegions::new();
+        for &region in self.iter() {
+            if region.kind != RegionKind::Usable || region.end <= start || end <= region.start {
+                carved.push(region);
+                continue;
+            }
+            carved.push(Region { end: start.max(region.start), ..region });
+            carved.push(Region {
+                start: start.max(region.start),
+                end: end.min(region.end),
+                kind,
+            });
+            carved.push(Region { start: end.min(region.end), ..region });
+        }
+        carved
+    }
+}
+
+// A number of bytes, as something a person can read: "127 MiB", "640 KiB"
+pub struct Size(pub u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            bytes if bytes >= 1 << 30 => write!(f, "{}.{} GiB", bytes >> 30, (bytes >> 20) % 1024 * 10 / 1024),
+            bytes if bytes >= 1 << 20 => write!(f, "{}.{} MiB", bytes >> 20, (bytes >> 10) % 1024 * 10 / 1024),
+            bytes if bytes >= 1 << 10 => write!(f, "{} KiB", bytes >> 10),
+            bytes => write!(f, "{} bytes", bytes),
+        }
+    }
+}
+
+pub fn init<I>(memory_map: &MemoryMap, reserved: I) -> &'static MemoryRegions
+where
+    I: Iterator<Item = (u64, u64, RegionKind)>,
+{
+    REGIONS.call_once(|| MemoryRegions::from_memory_map(memory_map, reserved))
+}
+
+// The regions `init` found, which is none before it's run
+pub fn regions() -> &'static MemoryRegions {
+    static NONE: MemoryRegions = MemoryRegions::new();
+    REGIONS.r#try().unwrap_or(&NONE)
+}
+
+#[test_case]
+fn test_classify_and_carve_out() {
+    use bootloader::bootinfo::{FrameRange, MemoryRegion};
+
+    let mut map = MemoryMap::new();
+    let mut add = |start, end, region_type| {
+        map.add_region(MemoryRegion {
+            range: FrameRange::new(start, end),
+            region_type,
+        })
+    };
+    // Out of order, and with two usable ranges next to each other
+    add(0x10_0000, 0x20_0000, MemoryRegionType::Kernel);
+    add(0x1000, 0x9_f000, MemoryRegionType::Usable);
+    add(0x20_0000, 0x40_0000, MemoryRegionType::Usable);
+    add(0x40_0000, 0x80_0000, MemoryRegionType::Usable);
+    add(0, 0x1000, MemoryRegionType::FrameZero);
+
+    let reserved = [(0x50_0800, 0x50_1000, RegionKind::CrashDump)];
+    let regions = MemoryRegions::from_memory_map(&map, reserved.iter().copied());
+    let kinds: [(u64, u64, RegionKind); 6] = [
+        (0, 0x1000, RegionKind::Reserved),
+        (0x1000, 0x9_f000, RegionKind::Usable),
+        (0x10_0000, 0x20_0000, RegionKind::Kernel),
+        (0x20_0000, 0x50_0000, RegionKind::Usable),
+        (0x50_0000, 0x50_1000, RegionKind::CrashDump),
+        (0x50_1000, 0x80_0000, RegionKind::Usable),
+    ];
+    assert!(regions.iter().map(|region| (region.start, region.end, region.kind)).eq(kinds));
+    assert_eq!(regions.total(RegionKind::Usable), 0x9_e000 + 0x30_0000 + 0x2f_f000);
+}
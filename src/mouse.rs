@@ -0,0 +1,291 @@
+// The PS/2 mouse, on the controller's second port. Once `ps2::init` has found it
+// there, `init` sets it up and asks
+// whether it's an IntelliMouse with a scroll wheel, by setting the sample rate to
+// 200, 100 and then 80 - the magic knock that makes one say it's ID 3 instead of 0.
+// After that it sends a packet (3 bytes, or 4 with the wheel) whenever it moves or a
+// button changes, each byte raising IRQ 12. The handler puts the packets back
+// together into `MouseEvent`s for `try_next_event`.
+//
+// Packets don't carry a proper start marker, only bit 3 of the first byte is always
+// set. A byte without it can't be the start of a packet, which is enough to get back
+// in step after a lost byte most of the time.
+
+use core::fmt;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::interrupts;
+use crate::pic;
+use crate::ps2::{self, Channel, Device};
+use crate::sync::{self, IrqSafeMutex};
+
+pub const IRQ: u8 = 12;
+
+// Mouse commands
+const SET_DEFAULTS: u8 = 0xf6;
+const ENABLE_REPORTING: u8 = 0xf4;
+const SET_SAMPLE_RATE: u8 = 0xf3;
+const GET_ID: u8 = 0xf2;
+
+const ID_WHEEL: u8 = 3;
+const INTELLIMOUSE_KNOCK: [u8; 3] = [200, 100, 80];
+
+const PACKET_ALWAYS_SET: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+const QUEUE_SIZE: usize = 64;
+
+static MOUSE: IrqSafeMutex<Mouse> = IrqSafeMutex::new(Mouse {
+    decoder: Decoder::new(false),
+    events: [MouseEvent::NONE; QUEUE_SIZE],
+    head: 0,
+    len: 0,
+    dropped: 0,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    Standard,
+    // IntelliMouse, with a scroll wheel
+    Wheel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseError {
+    // `ps2::init` found something else on the second port, or nothing
+    NotFound,
+    // The controller wouldn't take the byte
+    ControllerTimeout,
+    // Nothing answered the command, most likely there's no mouse
+    NoResponse(u8),
+    // The mouse answered the command with something other than ACK
+    Refused(u8, u8),
+}
+
+impl fmt::Display for MouseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MouseError::NotFound => write!(f, "nothing on the second PS/2 port"),
+            MouseError::ControllerTimeout => write!(f, "the PS/2 controller isn't taking commands"),
+            MouseError::NoResponse(command) => write!(f, "no answer to command {:#x}", command),
+            MouseError::Refused(command, answer) => {
+                write!(f, "command {:#x} answered with {:#x}", command, answer)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+// One packet's worth: how far the mouse moved since the last one (up is positive y,
+// and scrolling down is positive wheel), and which buttons are held now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
+
+impl MouseEvent {
+    const NONE: MouseEvent = MouseEvent {
+        dx: 0,
+        dy: 0,
+        wheel: 0,
+        buttons: Buttons {
+            left: false,
+            right: false,
+            middle: false,
+        },
+    };
+}
+
+// Turns bytes from the mouse into events, one packet at a time
+pub struct Decoder {
+    bytes: [u8; 4],
+    len: usize,
+    wheel: bool,
+}
+
+impl Decoder {
+    pub const fn new(wheel: bool) -> Decoder {
+        Decoder {
+            bytes: [0; 4],
+            len: 0,
+            wheel,
+        }
+    }
+
+    // The event `byte` finishes, if it finishes a packet
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        if self.len == 0 && byte & PACKET_ALWAYS_SET == 0 {
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_len() {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y, z] = self.bytes;
+        // 9 bit two's complement, the sign bit is in the first byte. An overflowed
+        // packet has nothing useful in it.
+        let delta = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                value as i16 - 0x100
+            } else {
+                value as i16
+            }
+        };
+        Some(MouseEvent {
+            dx: delta(x, PACKET_X_SIGN, PACKET_X_OVERFLOW),
+            dy: delta(y, PACKET_Y_SIGN, PACKET_Y_OVERFLOW),
+            // The bottom 4 bits are a signed number
+            wheel: if self.wheel { ((z << 4) as i8) >> 4 } else { 0 },
+            buttons: Buttons {
+                left: flags & 1 != 0,
+                right: flags & 2 != 0,
+                middle: flags & 4 != 0,
+            },
+        })
+    }
+
+    fn packet_len(&self) -> usize {
+        if self.wheel {
+            4
+        } else {
+            3
+        }
+    }
+}
+
+struct Mouse {
+    decoder: Decoder,
+    events: [MouseEvent; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+    dropped: usize,
+}
+
+impl Mouse {
+    fn push(&mut self, event: MouseEvent) {
+        if self.len == QUEUE_SIZE {
+            self.dropped += 1;
+            return;
+        }
+        self.events[(self.head + self.len) % QUEUE_SIZE] = event;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<MouseEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head];
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+// Sends `command` to the mouse and waits for it to say it got it
+fn command(command: u8) -> Result<(), MouseError> {
+    if !ps2::write_to(Channel::Second, command) {
+        return Err(MouseError::ControllerTimeout);
+    }
+    match ps2::read_from(Channel::Second) {
+        Some(ps2::ACK) => Ok(()),
+        Some(answer) => Err(MouseError::Refused(command, answer)),
+        None => Err(MouseError::NoResponse(command)),
+    }
+}
+
+fn set_sample_rate(rate: u8) -> Result<(), MouseError> {
+    command(SET_SAMPLE_RATE)?;
+    command(rate)
+}
+
+fn id() -> Result<u8, MouseError> {
+    command(GET_ID)?;
+    ps2::read_from(Channel::Second).ok_or(MouseError::NoResponse(GET_ID))
+}
+
+// Has to come before `keyboard::init`, since anything the keyboard sends meanwhile
+// is thrown away
+pub fn init() -> Result<MouseKind, MouseError> {
+    if ps2::device(Channel::Second) != Some(Device::Mouse) {
+        return Err(MouseError::NotFound);
+    }
+    // Everything's polled, so nothing else should be reading the data port meanwhile
+    let kind = sync::without_interrupts(|| -> Result<MouseKind, MouseError> {
+        command(SET_DEFAULTS)?;
+        for rate in INTELLIMOUSE_KNOCK {
+            set_sample_rate(rate)?;
+        }
+        let kind = if id()? == ID_WHEEL {
+            MouseKind::Wheel
+        } else {
+            MouseKind::Standard
+        };
+        // Back to the usual rate, the knock leaves it at 80
+        set_sample_rate(100)?;
+        command(ENABLE_REPORTING)?;
+        Ok(kind)
+    })?;
+    MOUSE.lock().decoder = Decoder::new(kind == MouseKind::Wheel);
+    // Only now, so none of the answers above went to the interrupt handler
+    if !ps2::enable_irq(Channel::Second) {
+        return Err(MouseError::ControllerTimeout);
+    }
+    interrupts::enable_irq(IRQ);
+    Ok(kind)
+}
+
+// The oldest mouse event nobody has taken yet
+pub fn try_next_event() -> Option<MouseEvent> {
+    MOUSE.lock().pop()
+}
+
+// How many events were lost because nobody took them in time
+pub fn dropped() -> usize {
+    MOUSE.lock().dropped
+}
+
+pub extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    interrupts::record(pic::vector(IRQ));
+    let byte: u8 = unsafe { Port::new(ps2::DATA_PORT).read() };
+    let mut mouse = MOUSE.lock();
+    if let Some(event) = mouse.decoder.add_byte(byte) {
+        mouse.push(event);
+    }
+    drop(mouse);
+    interrupts::end_of_interrupt(IRQ);
+}
+
+#[test_case]
+fn test_decode_packets() {
+    let mut decoder = Decoder::new(false);
+    // Left button, 5 right and 3 down
+    assert_eq!(decoder.add_byte(0x29), None);
+    assert_eq!(decoder.add_byte(5), None);
+    let event = decoder.add_byte(0xfd).unwrap();
+    assert_eq!((event.dx, event.dy, event.buttons.left), (5, -3, true));
+
+    // A stray byte without bit 3 is skipped, and the wheel packet after it still
+    // comes out right
+    let mut decoder = Decoder::new(true);
+    let bytes = [0x00, 0x08, 0, 0, 0x0f];
+    let events = bytes.iter().filter_map(|&byte| decoder.add_byte(byte));
+    assert!(events.map(|event| event.wheel).eq([-1]));
+}
@@ -0,0 +1,206 @@
+// A driver for the two 8259 programmable interrupt controllers, which is how the
+// PC's hardware interrupts get to the CPU. The secondary PIC hangs off the primary's
+// IRQ 2, so between them there are 15 usable lines: IRQ 0-7 on the primary and
+// IRQ 8-15 on the secondary.
+//
+// Out of reset the primary PIC sends its IRQs as vectors 8-15, right on top of the
+// CPU's own exceptions (IRQ 0, the timer, would look like a double fault). `init`
+// moves both PICs to the vectors after the exceptions, 32-47.
+//
+// Every line starts out masked, and a driver unmasks its own once it has a handler
+// for it. Handlers have to say they're done with `end_of_interrupt`, or the PIC never
+// sends anything on that line (or anything less important) again.
+
+use x86_64::instructions::port::Port;
+
+use crate::sync::IrqSafeMutex;
+
+// Where IRQ 0 and 8 end up
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+// The line the secondary PIC is on
+pub const CASCADE_IRQ: u8 = 2;
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_COMMAND: u16 = 0xA0;
+const PIC_2_DATA: u16 = 0xA1;
+
+// ICW1: start initializing, and an ICW4 is coming
+const ICW1_INIT: u8 = 0x11;
+// ICW4: 8086 mode, rather than the 8080 mode nobody has used since
+const ICW4_8086: u8 = 0x01;
+const COMMAND_END_OF_INTERRUPT: u8 = 0x20;
+// Have the next read of the command port return the in-service register
+const COMMAND_READ_ISR: u8 = 0x0B;
+// Writing to this takes long enough to give old PICs the pause they need between
+// commands, and nothing is listening
+const UNUSED_PORT: u16 = 0x80;
+
+pub static PICS: IrqSafeMutex<ChainedPics> = IrqSafeMutex::new(ChainedPics::new());
+
+struct Pic {
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    fn handles(&self, vector: u8) -> bool {
+        (self.offset..self.offset + 8).contains(&vector)
+    }
+}
+
+pub struct ChainedPics {
+    primary: Pic,
+    secondary: Pic,
+}
+
+impl ChainedPics {
+    const fn new() -> ChainedPics {
+        ChainedPics {
+            primary: Pic {
+                offset: PIC_1_OFFSET,
+                command: Port::new(PIC_1_COMMAND),
+                data: Port::new(PIC_1_DATA),
+            },
+            secondary: Pic {
+                offset: PIC_2_OFFSET,
+                command: Port::new(PIC_2_COMMAND),
+                data: Port::new(PIC_2_DATA),
+            },
+        }
+    }
+
+    // Remaps both PICs and masks everything but the cascade. The PICs take the four
+    // initialization words in order, on the data port after the first.
+    unsafe fn initialize(&mut self) {
+        let mut wait_port: Port<u8> = Port::new(UNUSED_PORT);
+        let mut wait = || wait_port.write(0);
+
+        self.primary.command.write(ICW1_INIT);
+        wait();
+        self.secondary.command.write(ICW1_INIT);
+        wait();
+        // ICW2: the vector offset
+        self.primary.data.write(self.primary.offset);
+        wait();
+        self.secondary.data.write(self.secondary.offset);
+        wait();
+        // ICW3: which line the secondary is on - as a bit mask for the primary, and
+        // as a number for the secondary
+        self.primary.data.write(1 << CASCADE_IRQ);
+        wait();
+        self.secondary.data.write(CASCADE_IRQ);
+        wait();
+        self.primary.data.write(ICW4_8086);
+        wait();
+        self.secondary.data.write(ICW4_8086);
+        wait();
+
+        self.primary.data.write(!(1 << CASCADE_IRQ));
+        self.secondary.data.write(0xff);
+    }
+
+    // All 16 lines, IRQ 0 in bit 0. A set bit is a masked line.
+    pub fn masks(&mut self) -> u16 {
+        unsafe { u16::from_le_bytes([self.primary.data.read(), self.secondary.data.read()]) }
+    }
+
+    pub fn set_masked(&mut self, irq: u8, masked: bool) {
+        assert!(irq < 16, "there is no IRQ {}", irq);
+        let (pic, bit) = if irq < 8 {
+            (&mut self.primary, irq)
+        } else {
+            (&mut self.secondary, irq - 8)
+        };
+        unsafe {
+            let mask = pic.data.read();
+            let mask = if masked {
+                mask | (1 << bit)
+            } else {
+                mask & !(1 << bit)
+            };
+            pic.data.write(mask);
+        }
+    }
+
+    // Which IRQs are being handled right now, in the same order as `masks`
+    pub fn in_service(&mut self) -> u16 {
+        unsafe {
+            self.primary.command.write(COMMAND_READ_ISR);
+            self.secondary.command.write(COMMAND_READ_ISR);
+            u16::from_le_bytes([self.primary.command.read(), self.secondary.command.read()])
+        }
+    }
+
+    // Tells the PICs the handler for `vector` is done. An IRQ from the secondary went
+    // through the primary as well, so both need telling.
+    pub fn end_of_interrupt(&mut self, vector: u8) {
+        if !self.primary.handles(vector) && !self.secondary.handles(vector) {
+            return;
+        }
+        unsafe {
+            if self.secondary.handles(vector) {
+                self.secondary.command.write(COMMAND_END_OF_INTERRUPT);
+            }
+            self.primary.command.write(COMMAND_END_OF_INTERRUPT);
+        }
+    }
+}
+
+// The vector IRQ `irq` comes in on
+pub const fn vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
+}
+
+// Remaps the PICs, with every line masked. Interrupts are left for the caller to
+// turn on (see `interrupts::init_controllers`).
+pub fn init() {
+    // Safe since nothing else has touched the PICs, and the vectors don't clash
+    unsafe { PICS.lock().initialize() };
+}
+
+// Masks every line, cascade included, for when the I/O APIC takes over
+pub fn disable() {
+    let mut pics = PICS.lock();
+    for irq in 0..16 {
+        pics.set_masked(irq, true);
+    }
+}
+
+pub fn mask(irq: u8) {
+    PICS.lock().set_masked(irq, true);
+}
+
+pub fn unmask(irq: u8) {
+    PICS.lock().set_masked(irq, false);
+}
+
+// For the end of IRQ handlers, where interrupts are off already
+pub fn end_of_interrupt(vector: u8) {
+    PICS.lock().end_of_interrupt(vector);
+}
+
+#[test_case]
+fn test_lines_masked_after_init() {
+    let masks = PICS.lock().masks();
+    if crate::interrupts::routed_by_io_apic() {
+        assert_eq!(masks, 0xffff);
+    } else {
+        // Only the cascade (and whatever drivers turned on) gets through
+        assert_eq!(masks & (1 << CASCADE_IRQ), 0);
+    }
+}
+
+#[test_case]
+fn test_mask_unmask() {
+    // IRQ 15 is the secondary ATA channel, which has no handler, so it's masked again
+    // before anything can come in on it
+    let mut pics = PICS.lock();
+    pics.set_masked(15, false);
+    assert_eq!(pics.masks() & (1 << 15), 0);
+    pics.set_masked(15, true);
+    assert_ne!(pics.masks() & (1 << 15), 0);
+}
@@ -0,0 +1,68 @@
+// The programmable interval timer (the 8253/8254), which is what keeps time here.
+// Channel 0 counts down from a divisor at 1.193182 MHz and raises IRQ 0 every time
+// it gets to zero, so the divisor picks how often the timer ticks. Every tick moves
+// `time` along and counts towards `ticks`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::config;
+use crate::pic;
+use crate::time;
+
+pub const IRQ: u8 = 0;
+
+const BASE_FREQUENCY: u64 = 1_193_182;
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+// Channel 0, low byte then high byte, mode 3 (square wave), binary
+const COMMAND_CHANNEL_0_SQUARE_WAVE: u8 = 0x36;
+
+const DIVISOR: u64 = BASE_FREQUENCY / config::TIMER_HZ;
+// What the divisor actually comes out as. It's never quite `TIMER_HZ`, so the
+// leftover nanoseconds are carried over to the next tick to keep uptime honest.
+const TICK_NANOS: u64 = DIVISOR * 1_000_000_000 / BASE_FREQUENCY;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// Nanoseconds that didn't add up to a whole microsecond yet
+static LEFTOVER_NANOS: AtomicU64 = AtomicU64::new(0);
+
+// Starts the timer at `config::TIMER_HZ`
+pub fn init() {
+    // The counter is 16 bits, and 0 means 65536
+    const _: () = assert!(DIVISOR > 0 && DIVISOR <= 65536);
+    interrupts::without_interrupts(|| unsafe {
+        Port::<u8>::new(COMMAND).write(COMMAND_CHANNEL_0_SQUARE_WAVE);
+        let mut channel: Port<u8> = Port::new(CHANNEL_0);
+        channel.write(DIVISOR as u8);
+        channel.write((DIVISOR >> 8) as u8);
+    });
+    crate::interrupts::enable_irq(IRQ);
+}
+
+// How many times the timer has gone off since `init`
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::interrupts::record(pic::vector(IRQ));
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    let nanos = LEFTOVER_NANOS.load(Ordering::Relaxed) + TICK_NANOS;
+    LEFTOVER_NANOS.store(nanos % 1000, Ordering::Relaxed);
+    // Tell the interrupt controller first, since this can end up never returning
+    // (the test watchdog)
+    crate::interrupts::end_of_interrupt(IRQ);
+    time::advance(nanos / 1000);
+}
+
+#[test_case]
+fn test_timer_ticks() {
+    let start = ticks();
+    // Interrupts are on, so this can't wait long
+    while ticks() == start {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,352 @@
+// The 8042 PS/2 controller, which the keyboard and the mouse both talk through. It
+// has one byte each way: bytes for a device (or the controller itself) are written to
+// the data port once the controller has taken the last one, and bytes from either
+// device show up in the data port one at a time, with the status saying which port
+// each came from.
+//
+// `init` doesn't take anything for granted: it checks the controller works, whether
+// it has a second port, and what's plugged into each one, and leaves both ports'
+// IRQs off for the drivers to turn on (see `enable_irq`) once they've found their
+// device with `device`. It also turns on translation on the first port, so whatever
+// the keyboard speaks arrives as scancode set 1.
+//
+// Everything here polls with a bound on how long it waits, so a missing device or
+// controller means a `None` (or a `false`) instead of hanging at boot.
+
+use core::fmt;
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+use crate::sync;
+
+pub const DATA_PORT: u16 = 0x60;
+// Reads are the status, writes are commands for the controller
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+// There's a byte waiting in the data port
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+// The controller hasn't taken the last byte written yet
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+// The waiting byte is from the second port
+const STATUS_SECOND_PORT: u8 = 1 << 5;
+
+// Controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND: u8 = 0xa7;
+const ENABLE_SECOND: u8 = 0xa8;
+const TEST_SECOND: u8 = 0xa9;
+const SELF_TEST: u8 = 0xaa;
+const TEST_FIRST: u8 = 0xab;
+const DISABLE_FIRST: u8 = 0xad;
+const ENABLE_FIRST: u8 = 0xae;
+// The next byte written to the data port goes to the second port
+const WRITE_SECOND: u8 = 0xd4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// Config byte bits
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+// Device commands, which both keyboards and mice understand
+const IDENTIFY: u8 = 0xf2;
+const ENABLE_SCANNING: u8 = 0xf4;
+const DISABLE_SCANNING: u8 = 0xf5;
+const RESET: u8 = 0xff;
+const RESET_PASSED: u8 = 0xaa;
+
+// What devices answer commands with
+pub const ACK: u8 = 0xfa;
+pub const RESEND: u8 = 0xfe;
+const MAX_RESENDS: usize = 3;
+
+// How many times to poll the status, a controller with a device on it takes
+// microseconds and each poll is about one
+const ATTEMPTS: usize = 100_000;
+// Devices take a while to test themselves after a reset, up to about half a second
+const RESET_ATTEMPTS: usize = 1_000_000;
+
+static DEVICES: Once<[Option<Device>; 2]> = Once::new();
+
+// The controller's two ports. The keyboard is normally on the first and the mouse on
+// the second, and only the first one can translate scancodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    First,
+    Second,
+}
+
+impl Channel {
+    const ALL: [Channel; 2] = [Channel::First, Channel::Second];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn irq_bit(self) -> u8 {
+        match self {
+            Channel::First => CONFIG_FIRST_IRQ,
+            Channel::Second => CONFIG_SECOND_IRQ,
+        }
+    }
+}
+
+// What answered identify, by the ID it sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    // No ID at all (an old AT keyboard), or 0xab and then which kind
+    Keyboard,
+    // 0x00, or 0x03 and 0x04 for mice with a wheel or more buttons
+    Mouse,
+    Unknown(u8),
+}
+
+impl Device {
+    fn from_id(id: &[u8]) -> Device {
+        match id {
+            [] | [0xab, ..] => Device::Keyboard,
+            [0x00] | [0x03] | [0x04] => Device::Mouse,
+            [first, ..] => Device::Unknown(*first),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    // Nothing answers on the controller's ports, which modern machines without any
+    // PS/2 emulation do
+    NoController,
+    SelfTestFailed(u8),
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ps2Error::NoController => write!(f, "no PS/2 controller"),
+            Ps2Error::SelfTestFailed(answer) => {
+                write!(f, "PS/2 controller failed its self test ({:#x})", answer)
+            }
+        }
+    }
+}
+
+fn status() -> u8 {
+    // Safe since reading the status has no side effects
+    unsafe { Port::new(STATUS_PORT).read() }
+}
+
+fn wait_for_input_empty() -> bool {
+    for _ in 0..ATTEMPTS {
+        if status() & STATUS_INPUT_FULL == 0 {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+// Writes a byte for the first port's device, or whatever a controller command
+// expects next. False if the controller never took it.
+pub fn write_data(byte: u8) -> bool {
+    if !wait_for_input_empty() {
+        return false;
+    }
+    unsafe { Port::new(DATA_PORT).write(byte) };
+    true
+}
+
+fn write_command(command: u8) -> bool {
+    if !wait_for_input_empty() {
+        return false;
+    }
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+    true
+}
+
+// Writes a byte for the device on `channel`
+pub fn write_to(channel: Channel, byte: u8) -> bool {
+    match channel {
+        Channel::First => write_data(byte),
+        Channel::Second => write_command(WRITE_SECOND) && write_data(byte),
+    }
+}
+
+fn read_within(attempts: usize) -> Option<(Channel, u8)> {
+    for _ in 0..attempts {
+        let status = status();
+        if status & STATUS_OUTPUT_FULL != 0 {
+            let byte = unsafe { Port::new(DATA_PORT).read() };
+            let channel = if status & STATUS_SECOND_PORT != 0 {
+                Channel::Second
+            } else {
+                Channel::First
+            };
+            return Some((channel, byte));
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+fn read_from_within(channel: Channel, attempts: usize) -> Option<u8> {
+    loop {
+        let (from, byte) = read_within(attempts)?;
+        if from == channel {
+            return Some(byte);
+        }
+    }
+}
+
+// The next byte from the device on `channel`, dropping anything the other one sends
+// meanwhile. Answers to controller commands count as the first port's. Only for when
+// the port's IRQ is off, otherwise its interrupt handler gets there first.
+pub fn read_from(channel: Channel) -> Option<u8> {
+    read_from_within(channel, ATTEMPTS)
+}
+
+// Throws away anything either device sent that nobody asked for
+fn flush() {
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+fn read_config() -> Option<u8> {
+    if !write_command(READ_CONFIG) {
+        return None;
+    }
+    read_from(Channel::First)
+}
+
+fn write_config(config: u8) -> bool {
+    write_command(WRITE_CONFIG) && write_data(config)
+}
+
+// Sends `command` to the device on `channel`, and says whether it took it
+fn command(channel: Channel, command: u8) -> bool {
+    for _ in 0..=MAX_RESENDS {
+        if !write_to(channel, command) {
+            return false;
+        }
+        match read_from(channel) {
+            Some(ACK) => return true,
+            Some(RESEND) => continue,
+            _ => return false,
+        }
+    }
+    false
+}
+
+// What's plugged into `channel`, if anything is. Leaves it reset, with scanning on.
+fn identify(channel: Channel) -> Option<Device> {
+    if !command(channel, RESET) || read_from_within(channel, RESET_ATTEMPTS) != Some(RESET_PASSED) {
+        return None;
+    }
+    // Mice send their ID after a reset too
+    let _ = read_from(channel);
+    if !command(channel, DISABLE_SCANNING) || !command(channel, IDENTIFY) {
+        return None;
+    }
+    // Up to two bytes, and old keyboards don't send any
+    let mut id = [0; 2];
+    let mut len = 0;
+    while len < id.len() {
+        match read_from(channel) {
+            Some(byte) => id[len] = byte,
+            None => break,
+        }
+        len += 1;
+    }
+    command(channel, ENABLE_SCANNING);
+    Some(Device::from_id(&id[..len]))
+}
+
+pub fn init() -> Result<(), Ps2Error> {
+    // Everything's polled, so nothing else should be reading the data port meanwhile
+    let devices = sync::without_interrupts(|| -> Result<[Option<Device>; 2], Ps2Error> {
+        // Quiet, so nothing the devices send gets in the way of checking the controller.
+        // If there's no controller at all, nothing takes these.
+        if !write_command(DISABLE_FIRST) || !write_command(DISABLE_SECOND) {
+            return Err(Ps2Error::NoController);
+        }
+        flush();
+        let config = read_config().ok_or(Ps2Error::NoController)?;
+        let config = config & !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATION);
+        write_config(config);
+
+        write_command(SELF_TEST);
+        match read_from(Channel::First) {
+            Some(SELF_TEST_PASSED) => {}
+            Some(answer) => return Err(Ps2Error::SelfTestFailed(answer)),
+            None => return Err(Ps2Error::NoController),
+        }
+        // Some controllers reset themselves on a self test
+        write_config(config);
+
+        // Only a controller with a second port clears its clock bit when it's enabled
+        write_command(ENABLE_SECOND);
+        let dual = read_config().is_some_and(|config| config & CONFIG_SECOND_CLOCK_DISABLED == 0);
+        write_command(DISABLE_SECOND);
+
+        let mut devices = [None, None];
+        for channel in Channel::ALL {
+            if channel == Channel::Second && !dual {
+                continue;
+            }
+            let (test, enable) = match channel {
+                Channel::First => (TEST_FIRST, ENABLE_FIRST),
+                Channel::Second => (TEST_SECOND, ENABLE_SECOND),
+            };
+            write_command(test);
+            match read_from(Channel::First) {
+                Some(PORT_TEST_PASSED) => {}
+                answer => {
+                    log::warn!("PS/2 {:?} port failed its test ({:x?})", channel, answer);
+                    continue;
+                }
+            }
+            write_command(enable);
+            devices[channel.index()] = identify(channel);
+        }
+
+        // Translation only now, since it would have translated the IDs too
+        if let Some(config) = read_config() {
+            write_config(config | CONFIG_TRANSLATION);
+        }
+        Ok(devices)
+    })?;
+    for channel in Channel::ALL {
+        match devices[channel.index()] {
+            Some(device) => log::info!("PS/2 {:?} port: {:?}", channel, device),
+            None => log::info!("PS/2 {:?} port: nothing there", channel),
+        }
+    }
+    DEVICES.call_once(|| devices);
+    Ok(())
+}
+
+// What `init` found on `channel`
+pub fn device(channel: Channel) -> Option<Device> {
+    DEVICES.r#try()?[channel.index()]
+}
+
+// Lets the device on `channel` raise its IRQ, for its driver to do once it's set up
+pub fn enable_irq(channel: Channel) -> bool {
+    sync::without_interrupts(|| match read_config() {
+        Some(config) => write_config(config | channel.irq_bit()),
+        None => false,
+    })
+}
+
+#[test_case]
+fn test_device_from_id() {
+    assert_eq!(Device::from_id(&[]), Device::Keyboard);
+    assert_eq!(Device::from_id(&[0xab, 0x83]), Device::Keyboard);
+    assert_eq!(Device::from_id(&[0x03]), Device::Mouse);
+    assert_eq!(Device::from_id(&[0x50]), Device::Unknown(0x50));
+}
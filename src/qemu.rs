@@ -0,0 +1,28 @@
+// Talking to QEMU's isa-debug-exit device, which lets the kernel quit QEMU with an
+// exit status of its own choosing. The device only exists when QEMU is started with
+//     -device isa-debug-exit,iobase=0xf4,iosize=0x04
+// (test runs get that from `package.metadata.bootimage` in Cargo.toml). On anything
+// else the port write does nothing and we carry on.
+
+use x86_64::instructions::port::Port;
+
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+// QEMU exits with `(code << 1) | 1`, so none of these can look like a clean exit of
+// QEMU itself. Success comes out as 33, failure as 35 and a test that hung as 37.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+    // The test watchdog ran out of patience
+    TimedOut = 0x12,
+}
+
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    // Writing to an unused port is harmless
+    unsafe {
+        let mut port = Port::new(ISA_DEBUG_EXIT_PORT);
+        port.write(exit_code as u32);
+    }
+}
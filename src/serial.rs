@@ -0,0 +1,413 @@
+// A driver for the 16550 UART behind the PC's serial ports. QEMU can hook a serial
+// port up to the host terminal (`-serial stdio`), which makes it the easiest way to
+// get output out of the kernel - and it keeps working when the screen doesn't.
+//
+// The UART has eight registers, at consecutive ports starting from the base port.
+// A few of them share a port and are told apart by the divisor latch access bit
+// (DLAB) in the line control register.
+
+use core::fmt::{self, Write};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::config;
+use crate::console::Console;
+use crate::color::Color;
+use crate::pic;
+use crate::sync::IrqSafeMutex;
+
+// The standard base ports of the four PC serial ports. Only COM1 and COM2 have
+// fixed interrupt lines (4 and 3), COM3 and COM4 share them.
+pub const COM1: u16 = 0x3F8;
+pub const COM2: u16 = 0x2F8;
+pub const COM3: u16 = 0x3E8;
+pub const COM4: u16 = 0x2E8;
+
+// The serial console's interrupt line
+pub const IRQ: u8 = match config::SERIAL_CONSOLE_PORT {
+    COM2 | COM4 => 3,
+    _ => 4,
+};
+
+// Register offsets from the base port
+const DATA: u16 = 0; // transmit/receive buffer, or the divisor's low byte with DLAB set
+const INTERRUPT_ENABLE: u16 = 1; // or the divisor's high byte with DLAB set
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_CONTROL_DLAB: u8 = 0x80;
+// 8 data bits and one stop bit, the parity bits go on top
+const LINE_CONTROL_8_DATA_BITS: u8 = 0x03;
+// Enable and clear both FIFOs, the trigger level bits go on top
+const FIFO_ENABLE_AND_CLEAR: u8 = 0x07;
+// Data terminal ready, request to send, and OUT2 (which gates the UART's interrupt line)
+const MODEM_CONTROL_READY: u8 = 0x0B;
+// Set while there's a received byte waiting to be read
+const LINE_STATUS_DATA_READY: u8 = 0x01;
+// Set once the transmit holding register (THR) can take another byte
+const LINE_STATUS_THR_EMPTY: u8 = 0x20;
+// Raise an interrupt whenever a byte has been received
+const INTERRUPT_DATA_AVAILABLE: u8 = 0x01;
+
+// The UART's clock, which the divisor divides down to the baud rate
+const BASE_BAUD: u32 = 115200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    // The parity bit is always 1 (mark) or always 0 (space)
+    Mark,
+    Space,
+}
+
+impl Parity {
+    // Bits 3-5 of the line control register
+    fn line_control_bits(self) -> u8 {
+        match self {
+            Parity::None => 0x00,
+            Parity::Odd => 0x08,
+            Parity::Even => 0x18,
+            Parity::Mark => 0x28,
+            Parity::Space => 0x38,
+        }
+    }
+}
+
+// How many bytes have to be in the receive FIFO before the UART raises an interrupt.
+// Lower means less latency, higher means fewer interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoTrigger {
+    One,
+    Four,
+    Eight,
+    Fourteen,
+}
+
+impl FifoTrigger {
+    // Bits 6-7 of the FIFO control register
+    fn fifo_control_bits(self) -> u8 {
+        match self {
+            FifoTrigger::One => 0x00,
+            FifoTrigger::Four => 0x40,
+            FifoTrigger::Eight => 0x80,
+            FifoTrigger::Fourteen => 0xC0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    // What the base clock is divided by, see `divisor_for`
+    pub baud_divisor: u16,
+    pub parity: Parity,
+    pub fifo_trigger: FifoTrigger,
+}
+
+impl SerialConfig {
+    // The divisor for `baud`, which has to divide 115200 evenly
+    pub const fn divisor_for(baud: u32) -> u16 {
+        assert!(baud > 0 && BASE_BAUD.is_multiple_of(baud), "unsupported baud rate");
+        (BASE_BAUD / baud) as u16
+    }
+}
+
+impl Default for SerialConfig {
+    // 38400 baud 8N1, which is what QEMU and most terminal programs expect
+    fn default() -> SerialConfig {
+        SerialConfig {
+            baud_divisor: SerialConfig::divisor_for(38400),
+            parity: Parity::None,
+            fifo_trigger: FifoTrigger::Fourteen,
+        }
+    }
+}
+
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    // Unsafe since the caller has to make sure there actually is a UART at `base`,
+    // and that nothing else is using it
+    pub const unsafe fn new(base: u16) -> SerialPort {
+        SerialPort { base }
+    }
+
+    pub fn init(&mut self, config: SerialConfig) {
+        // No interrupts until `enable_receive_interrupt`
+        self.write_register(INTERRUPT_ENABLE, 0x00);
+
+        // A divisor of 0 would stop the clock
+        let divisor = config.baud_divisor.max(1);
+        self.write_register(LINE_CONTROL, LINE_CONTROL_DLAB);
+        self.write_register(DATA, (divisor & 0xff) as u8);
+        self.write_register(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+        // Clearing DLAB again gives us the data and interrupt enable registers back
+        let line_control = LINE_CONTROL_8_DATA_BITS | config.parity.line_control_bits();
+        self.write_register(LINE_CONTROL, line_control);
+
+        let fifo_control = FIFO_ENABLE_AND_CLEAR | config.fifo_trigger.fifo_control_bits();
+        self.write_register(FIFO_CONTROL, fifo_control);
+        self.write_register(MODEM_CONTROL, MODEM_CONTROL_READY);
+    }
+
+    // Waits until the UART can take another byte, then sends it
+    pub fn send(&mut self, byte: u8) {
+        while self.read_register(LINE_STATUS) & LINE_STATUS_THR_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write_register(DATA, byte);
+    }
+
+    // The next received byte, if there is one. This doesn't wait.
+    pub fn receive(&mut self) -> Option<u8> {
+        if self.read_register(LINE_STATUS) & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+        Some(self.read_register(DATA))
+    }
+
+    // From now on, the UART raises its interrupt line (IRQ 4 for COM1) every time a byte
+    // comes in. Whatever handles that interrupt should call `handle_interrupt`.
+    pub fn enable_receive_interrupt(&mut self) {
+        self.write_register(INTERRUPT_ENABLE, INTERRUPT_DATA_AVAILABLE);
+    }
+
+    fn read_register(&mut self, offset: u16) -> u8 {
+        let mut port: Port<u8> = Port::new(self.base + offset);
+        // `new` made sure this is a UART we own
+        unsafe { port.read() }
+    }
+
+    fn write_register(&mut self, offset: u16, value: u8) {
+        let mut port: Port<u8> = Port::new(self.base + offset);
+        unsafe { port.write(value) }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // Terminals want a carriage return before moving down a line
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+// So `print!` can be sent here, and mirrored here with the `dual-console` feature.
+// Colors and clearing are done with ANSI escape codes, which about every terminal
+// on the other end understands.
+impl Console for SerialPort {
+    fn write_str(&mut self, s: &str) {
+        let _ = fmt::Write::write_str(self, s);
+    }
+
+    fn clear(&mut self) {
+        let _ = fmt::Write::write_str(self, "\x1b[2J\x1b[H");
+    }
+
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        let _ = write!(self, "\x1b[{};{}m", sgr_color(foreground, 30), sgr_color(background, 40));
+    }
+
+    fn write_colored(&mut self, foreground: Color, s: &str) {
+        let _ = write!(self, "\x1b[{}m{}\x1b[39m", sgr_color(foreground, 30), s);
+    }
+}
+
+// The SGR parameter for `color`, where `base` is 30 for the foreground and 40 for the
+// background. ANSI has red and blue the other way around from VGA, and puts the
+// bright colors in their own range 60 up.
+fn sgr_color(color: Color, base: u8) -> u8 {
+    let vga = color as u8;
+    let ansi = (vga & 0x01) << 2 | (vga & 0x02) | (vga & 0x04) >> 2;
+    let bright = if vga & 0x08 != 0 { 60 } else { 0 };
+    base + bright + ansi
+}
+
+lazy_static! {
+    // The serial console, on whichever port `config` picks. It's set up the first time
+    // it's used, so printing to it never needs an explicit init.
+    pub static ref SERIAL1: IrqSafeMutex<SerialPort> = {
+        // The port comes from the kernel config, and this is the only place it's used
+        let mut serial_port = unsafe { SerialPort::new(config::SERIAL_CONSOLE_PORT) };
+        serial_port.init(config::serial_console());
+        IrqSafeMutex::new(serial_port)
+    };
+}
+
+// Received bytes, put here by the interrupt handler until someone reads them. There
+// is only ever one writer (the interrupt handler) and one reader (`read_byte`, which
+// takes `RECEIVED_READER`), so this doesn't need a lock: each side only moves its own
+// end along.
+struct ReceiveBuffer {
+    bytes: [AtomicU8; RECEIVE_BUFFER_SIZE],
+    // Both only ever count up, the index is the count modulo the size
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+const RECEIVE_BUFFER_SIZE: usize = 256;
+
+static RECEIVED: ReceiveBuffer = ReceiveBuffer {
+    bytes: [const { AtomicU8::new(0) }; RECEIVE_BUFFER_SIZE],
+    head: AtomicUsize::new(0),
+    tail: AtomicUsize::new(0),
+};
+
+// Makes sure there is only one reader at a time
+static RECEIVED_READER: IrqSafeMutex<()> = IrqSafeMutex::new(());
+
+impl ReceiveBuffer {
+    // Only called from the interrupt handler. Bytes that don't fit are dropped.
+    fn push(&self, byte: u8) {
+        let head = self.head.load(Ordering::Relaxed);
+        if head - self.tail.load(Ordering::Acquire) == RECEIVE_BUFFER_SIZE {
+            return;
+        }
+        self.bytes[head % RECEIVE_BUFFER_SIZE].store(byte, Ordering::Relaxed);
+        self.head.store(head + 1, Ordering::Release);
+    }
+
+    fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let byte = self.bytes[tail % RECEIVE_BUFFER_SIZE].load(Ordering::Relaxed);
+        self.tail.store(tail + 1, Ordering::Release);
+        Some(byte)
+    }
+}
+
+// Whoever's waiting on a `SerialStream`
+static WAKER: AtomicWaker = AtomicWaker::new();
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+// Has to come after `interrupts::init_controllers`, which says where IRQs go
+pub fn init() {
+    SERIAL1.lock().enable_receive_interrupt();
+    crate::interrupts::enable_irq(IRQ);
+}
+
+pub extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::interrupts::record(pic::vector(IRQ));
+    handle_interrupt();
+    crate::interrupts::end_of_interrupt(IRQ);
+}
+
+// Moves everything the UART received into the receive buffer. Interrupts are off in
+// the handler and wherever else `SERIAL1` is locked, so the lock is always free here.
+pub fn handle_interrupt() {
+    let mut serial_port = SERIAL1.lock();
+    // With the FIFO on, a single interrupt can mean a whole bunch of bytes
+    let mut received = false;
+    while let Some(byte) = serial_port.receive() {
+        RECEIVED.push(byte);
+        received = true;
+    }
+    if received {
+        WAKER.wake();
+    }
+}
+
+// The oldest received byte that hasn't been read yet. This doesn't wait.
+pub fn read_byte() -> Option<u8> {
+    let _reader = RECEIVED_READER.lock();
+    RECEIVED.pop()
+}
+
+// Received bytes for an async task. `read_byte` still works alongside it, whichever
+// asks first gets the byte.
+//
+// There's only ever one stream, since there's only room for one waker.
+pub struct SerialStream {
+    // Only made by `new`
+    _private: (),
+}
+
+impl SerialStream {
+    // Panics if there's a stream already
+    pub fn new() -> SerialStream {
+        assert!(!STREAM_TAKEN.swap(true, Ordering::AcqRel), "there's a SerialStream already");
+        SerialStream { _private: () }
+    }
+}
+
+impl Default for SerialStream {
+    fn default() -> SerialStream {
+        SerialStream::new()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // Saves taking the waker when there's something there already
+        if let Some(byte) = read_byte() {
+            return Poll::Ready(Some(byte));
+        }
+        WAKER.register(cx.waker());
+        match read_byte() {
+            Some(byte) => Poll::Ready(Some(byte)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for SerialStream {
+    fn drop(&mut self) {
+        STREAM_TAKEN.store(false, Ordering::Release);
+    }
+}
+
+// Like `print!`, but to the serial console instead of the screen
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+// Interrupts stay off while the port is locked, for the same reason as in `console::_print`
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    SERIAL1
+        .lock()
+        .write_fmt(args)
+        .expect("printing to serial failed");
+}
+
+#[test_case]
+fn test_stream_gets_received_bytes() {
+    use core::task::Waker;
+
+    let mut stream = SerialStream::new();
+    let mut cx = Context::from_waker(Waker::noop());
+    // Anything typed into the terminal before this would get in the way
+    while read_byte().is_some() {}
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Pending);
+    // What the interrupt handler does with a byte, with interrupts off so the real
+    // one doesn't run meanwhile
+    crate::sync::without_interrupts(|| RECEIVED.push(b'x'));
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(Some(b'x')));
+}
@@ -0,0 +1,173 @@
+// The kernel's own symbol table, so backtraces can say `crash::report_panic+0x2b`
+// instead of just giving an address. The compiler can't know where anything ends up
+// until it's linked, so the table is added to the linked kernel afterwards by
+// tools/embed-symbols.py, which fills in the `.ksyms` section reserved here (see the
+// README). A kernel that didn't get one just shows addresses.
+//
+// The table is sorted by address and squeezed a bit, since it has to fit in the
+// section:
+//     "KSYM", then the number of symbols as a little endian u32, then where the code
+//     ends (the end of .text) as a little endian u64, then for each symbol the
+//     distance from the previous symbol's address as an unsigned LEB128, how many
+//     bytes of the name are the same as the previous name's (a u8), how many new
+//     bytes follow (a u8), and those bytes.
+
+// Has to match SECTION_SIZE in tools/embed-symbols.py
+const SECTION_SIZE: usize = 256 * 1024;
+const MAGIC: &[u8; 4] = b"KSYM";
+// The tool cuts longer names short
+const MAX_NAME_LEN: usize = 128;
+
+// Zeroes (that is, no table) until the tool overwrites them in the kernel binary
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; SECTION_SIZE] = [0; SECTION_SIZE];
+
+pub struct Symbol {
+    name: [u8; MAX_NAME_LEN],
+    len: usize,
+    // How far into the function the address was
+    pub offset: u64,
+}
+
+impl Symbol {
+    pub fn name(&self) -> &str {
+        // The tool only cuts names at character boundaries
+        core::str::from_utf8(&self.name[..self.len]).unwrap_or("?")
+    }
+}
+
+// The function `address` is in, if there's a symbol table (and `address` is in the
+// kernel's code)
+pub fn lookup(address: u64) -> Option<Symbol> {
+    // As far as the compiler knows this is all zeroes, so it has to be kept from
+    // reading it at compile time
+    let table: &[u8] = unsafe { &*core::hint::black_box(&KSYMS as *const [u8; SECTION_SIZE]) };
+    lookup_in(table, address)
+}
+
+fn lookup_in(table: &[u8], address: u64) -> Option<Symbol> {
+    let mut reader = Reader { table, position: 0 };
+    if reader.bytes(4)? != MAGIC {
+        return None;
+    }
+    let count = u32::from_le_bytes(reader.bytes(4)?.try_into().unwrap());
+    let end = u64::from_le_bytes(reader.bytes(8)?.try_into().unwrap());
+    // Past the last function there's no telling what an address is
+    if address >= end {
+        return None;
+    }
+
+    let mut symbol_address: u64 = 0;
+    let mut current = Symbol {
+        name: [0; MAX_NAME_LEN],
+        len: 0,
+        offset: 0,
+    };
+    let mut found = false;
+    for _ in 0..count {
+        let next_address = symbol_address.checked_add(reader.leb128()?)?;
+        if next_address > address {
+            break;
+        }
+        symbol_address = next_address;
+        let shared = reader.byte()? as usize;
+        let new = reader.byte()? as usize;
+        if shared > current.len || shared + new > MAX_NAME_LEN {
+            return None;
+        }
+        current.name[shared..shared + new].copy_from_slice(reader.bytes(new)?);
+        current.len = shared + new;
+        found = true;
+    }
+    if !found {
+        return None;
+    }
+    current.offset = address - symbol_address;
+    Some(current)
+}
+
+// Reads through the table, giving up (rather than panicking) if it's cut short
+struct Reader<'a> {
+    table: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.table.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.table.get(self.position..self.position + len)?;
+        self.position += len;
+        Some(bytes)
+    }
+
+    fn leb128(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+fn test_table(end: u64) -> ([u8; 64], usize) {
+    // `init` at 0x1000, `init_heap` at 0x1010 and `run` at 0x1200
+    let mut table = [0; 64];
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| {
+        table[len..len + bytes.len()].copy_from_slice(bytes);
+        len += bytes.len();
+    };
+    push(MAGIC);
+    push(&3u32.to_le_bytes());
+    push(&end.to_le_bytes());
+    push(&[0x80, 0x20, 0, 4]);
+    push(b"init");
+    push(&[0x10, 4, 5]);
+    push(b"_heap");
+    push(&[0xf0, 0x03, 0, 3]);
+    push(b"run");
+    (table, len)
+}
+
+#[test_case]
+fn test_lookup() {
+    let (table, len) = test_table(0x1300);
+    let table = &table[..len];
+    let symbol = lookup_in(table, 0x1000).unwrap();
+    assert_eq!((symbol.name(), symbol.offset), ("init", 0));
+    let symbol = lookup_in(table, 0x1014).unwrap();
+    assert_eq!((symbol.name(), symbol.offset), ("init_heap", 4));
+    let symbol = lookup_in(table, 0x12ff).unwrap();
+    assert_eq!((symbol.name(), symbol.offset), ("run", 0xff));
+    // Before the first function and after the end of the code
+    assert!(lookup_in(table, 0xfff).is_none());
+    assert!(lookup_in(table, 0x1300).is_none());
+    // Cut short, or with no table at all
+    assert!(lookup_in(&table[..len - 1], 0x1200).is_none());
+    assert!(lookup_in(&[0; 16], 0x1000).is_none());
+}
+
+#[test_case]
+fn test_lookup_address_overflow() {
+    // `a` at u64::MAX - 1, then something 2 bytes further on, which is past the end
+    // of the address space
+    let mut table = [0; 32];
+    table[..4].copy_from_slice(MAGIC);
+    table[4..8].copy_from_slice(&2u32.to_le_bytes());
+    table[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+    table[16..26].copy_from_slice(&[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+    table[26..29].copy_from_slice(&[0, 1, b'a']);
+    table[29] = 2;
+    assert!(lookup_in(&table, u64::MAX - 1).is_none());
+}
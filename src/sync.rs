@@ -0,0 +1,136 @@
+// Locks that are safe to share with interrupt handlers. A plain spinlock isn't: if
+// an interrupt comes in while the lock is held and its handler wants the same lock,
+// the handler spins forever on a lock that only the code it interrupted can give
+// back. So `IrqSafeMutex` turns interrupts off for as long as it's locked, and puts
+// them back the way they were once it's unlocked.
+//
+// Guards have to be dropped in the reverse order they were taken, which is what
+// Rust does anyway unless something is dropped by hand. Unlocking an outer lock
+// while an inner one is still held would turn interrupts back on too early.
+
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+// Interrupts are off for as long as one of these is around, and back to how they
+// were before once it's dropped, so these nest
+pub struct InterruptGuard {
+    were_enabled: bool,
+}
+
+impl InterruptGuard {
+    pub fn new() -> InterruptGuard {
+        let were_enabled = interrupts::are_enabled();
+        if were_enabled {
+            interrupts::disable();
+        }
+        InterruptGuard { were_enabled }
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> InterruptGuard {
+        InterruptGuard::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.were_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+// Runs `f` with interrupts off, for anything that isn't just one lock
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = InterruptGuard::new();
+    f()
+}
+
+pub struct IrqSafeMutex<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+// The lock, and interrupts off while it's held. The lock has to go first, which is
+// the order fields are dropped in.
+pub struct IrqSafeMutexGuard<'a, T: ?Sized + 'a> {
+    guard: MutexGuard<'a, T>,
+    _interrupts: InterruptGuard,
+}
+
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(value: T) -> IrqSafeMutex<T> {
+        IrqSafeMutex {
+            inner: Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSafeMutex<T> {
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T> {
+        let interrupts = InterruptGuard::new();
+        IrqSafeMutexGuard {
+            guard: self.inner.lock(),
+            _interrupts: interrupts,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<'_, T>> {
+        let interrupts = InterruptGuard::new();
+        // If it's taken, dropping `interrupts` puts them back on before returning
+        let guard = self.inner.try_lock()?;
+        Some(IrqSafeMutexGuard {
+            guard,
+            _interrupts: interrupts,
+        })
+    }
+
+    // Unsafe because whoever held the lock still thinks they do. Only for crash
+    // paths, where they're never getting back to it (see `crash::force_lock`).
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSafeMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+#[test_case]
+fn test_lock_disables_interrupts() {
+    static LOCK: IrqSafeMutex<u32> = IrqSafeMutex::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut value = LOCK.lock();
+        *value += 1;
+        assert!(!interrupts::are_enabled());
+        // Taken, so this fails, and leaves interrupts off for the lock that's held
+        assert!(LOCK.try_lock().is_none());
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*LOCK.lock(), 1);
+}
+
+#[test_case]
+fn test_guards_nest() {
+    let outer = InterruptGuard::new();
+    without_interrupts(|| assert!(!interrupts::are_enabled()));
+    assert!(!interrupts::are_enabled());
+    drop(outer);
+    assert!(interrupts::are_enabled());
+}
@@ -0,0 +1,24 @@
+// Time since boot. We don't read any clock ourselves: whichever timer interrupt is
+// running calls `advance` on every tick, and everyone else asks `uptime_micros`.
+// So the resolution is whatever the timer ticks at, and it stays 0 until one is set up.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+static UPTIME_MICROS: AtomicU64 = AtomicU64::new(0);
+
+// Called from the timer interrupt with how long one tick is
+pub fn advance(micros: u64) {
+    let now = UPTIME_MICROS.fetch_add(micros, Ordering::Relaxed) + micros;
+    crate::keyboard::repeat_tick(now);
+    // Every time another whole second has gone by
+    #[cfg(not(feature = "headless"))]
+    if now / 1_000_000 != (now - micros) / 1_000_000 {
+        crate::vga_buffer::update_status_bar(now / 1_000_000);
+    }
+    // Last, since this can end up never returning
+    crate::watchdog::check(now);
+}
+
+pub fn uptime_micros() -> u64 {
+    UPTIME_MICROS.load(Ordering::Relaxed)
+}
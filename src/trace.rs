@@ -0,0 +1,239 @@
+// Tracepoints, for when printing would change what we're looking at: an interrupt
+// handler that logs is a much slower interrupt handler. A tracepoint just stores a
+// small fixed-size record (the TSC, the event's name and up to four numbers) in a
+// ring buffer, and the rings are printed with `dump` once it's all over.
+//
+// Every subsystem gets its own ring, so a busy one can't push everyone else's events
+// out. A module declares its ring once and then traces into it:
+//     trace_ring!(interrupts);
+//     ...
+//     trace_event!(irq_enter, vector);
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+
+use crate::sync::IrqSafeMutex;
+
+const RING_SIZE: usize = 256;
+const MAX_ARGS: usize = 4;
+
+// Tracing is on from the start, it's cheap enough
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+// All rings that have seen an event, newest first
+static RINGS: AtomicPtr<Ring> = AtomicPtr::new(ptr::null_mut());
+
+#[derive(Clone, Copy)]
+pub struct Event {
+    pub tsc: u64,
+    pub name: &'static str,
+    args: [u64; MAX_ARGS],
+    len: u8,
+}
+
+impl Event {
+    const EMPTY: Event = Event {
+        tsc: 0,
+        name: "",
+        args: [0; MAX_ARGS],
+        len: 0,
+    };
+
+    pub fn args(&self) -> &[u64] {
+        &self.args[..self.len as usize]
+    }
+}
+
+struct Events {
+    events: [Event; RING_SIZE],
+    // How many events there have ever been, the next one goes into `count % RING_SIZE`
+    count: usize,
+}
+
+pub struct Ring {
+    subsystem: &'static str,
+    events: IrqSafeMutex<Events>,
+    // Events that came in while the ring was being written to or dumped
+    dropped: AtomicU64,
+    registered: AtomicBool,
+    next: AtomicPtr<Ring>,
+}
+
+impl Ring {
+    // Nothing traces anything yet
+    pub const fn new(subsystem: &'static str) -> Ring {
+        Ring {
+            subsystem,
+            events: IrqSafeMutex::new(Events {
+                events: [Event::EMPTY; RING_SIZE],
+                count: 0,
+            }),
+            dropped: AtomicU64::new(0),
+            registered: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    // Adds the ring to `RINGS`, so `dump` knows about it
+    fn register(&'static self) {
+        if self.registered.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let ring = self as *const Ring as *mut Ring;
+        let mut head = RINGS.load(Ordering::Acquire);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match RINGS.compare_exchange_weak(head, ring, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    // Calls `f` with the events still in the ring, oldest first
+    pub fn for_each<F: FnMut(&Event)>(&self, mut f: F) {
+        let events = self.events.lock();
+        let oldest = events.count.saturating_sub(RING_SIZE);
+        for index in oldest..events.count {
+            f(&events.events[index % RING_SIZE]);
+        }
+    }
+}
+
+// Declares the ring for the `trace_event!`s in the current module
+#[macro_export]
+macro_rules! trace_ring {
+    ($subsystem:ident) => {
+        static TRACE: $crate::trace::Ring = $crate::trace::Ring::new(stringify!($subsystem));
+    };
+}
+
+// Records an event with up to four numbers (anything that casts to u64), into the
+// ring declared with `trace_ring!` in the same module
+#[macro_export]
+macro_rules! trace_event {
+    ($name:ident $(, $arg:expr)* $(,)?) => {
+        $crate::trace::_record(&TRACE, stringify!($name), &[$($arg as u64),*])
+    };
+}
+
+#[doc(hidden)]
+pub fn _record(ring: &'static Ring, name: &'static str, args: &[u64]) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    ring.register();
+    let mut event = Event {
+        // Safe, the TSC is always there on x86_64
+        tsc: unsafe { core::arch::x86_64::_rdtsc() },
+        name,
+        args: [0; MAX_ARGS],
+        len: args.len().min(MAX_ARGS) as u8,
+    };
+    event.args[..event.len as usize].copy_from_slice(&args[..event.len as usize]);
+
+    // Never wait here: whoever holds the lock could be what we interrupted
+    match ring.events.try_lock() {
+        Some(mut events) => {
+            let slot = events.count % RING_SIZE;
+            events.events[slot] = event;
+            events.count += 1;
+        }
+        None => {
+            ring.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Calls `f` with every ring that's seen an event so far
+pub fn for_each_ring<F: FnMut(&'static Ring)>(mut f: F) {
+    let mut ring = RINGS.load(Ordering::Acquire);
+    // Rings are statics, and only ever added to the list
+    while let Some(current) = unsafe { ring.as_ref() } {
+        f(current);
+        ring = current.next.load(Ordering::Acquire);
+    }
+}
+
+// Prints every event in every ring, e.g.
+//     interrupts: 83472342 irq_enter 32
+pub fn dump() {
+    for_each_ring(|ring| {
+        ring.for_each(|event| {
+            crate::print!("{}: {} {}", ring.subsystem, event.tsc, event.name);
+            for arg in event.args() {
+                crate::print!(" {}", arg);
+            }
+            crate::println!();
+        });
+        let dropped = ring.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            crate::println!("{}: {} events dropped", ring.subsystem, dropped);
+        }
+    });
+}
+
+#[test_case]
+fn test_ring_wraps_oldest_first() {
+    static RING: Ring = Ring::new("test_wrap");
+    for i in 0..RING_SIZE as u64 + 10 {
+        _record(&RING, "event", &[i]);
+    }
+    // The first ten got overwritten, and the rest come out in the order they went in
+    let mut next = 10;
+    RING.for_each(|event| {
+        assert_eq!(event.args(), &[next]);
+        next += 1;
+    });
+    assert_eq!(next, RING_SIZE as u64 + 10);
+}
+
+#[test_case]
+fn test_args_truncated() {
+    static RING: Ring = Ring::new("test_args");
+    _record(&RING, "event", &[1, 2, 3, 4, 5, 6]);
+    let mut events = 0;
+    RING.for_each(|event| {
+        assert_eq!(event.name, "event");
+        assert_eq!(event.args(), &[1, 2, 3, 4]);
+        events += 1;
+    });
+    assert_eq!(events, 1);
+}
+
+#[test_case]
+fn test_dropped_while_locked() {
+    static RING: Ring = Ring::new("test_dropped");
+    {
+        // What a tracepoint in an interrupt handler sees while the ring is being dumped
+        let _events = RING.events.lock();
+        _record(&RING, "event", &[]);
+    }
+    assert_eq!(RING.dropped.load(Ordering::Relaxed), 1);
+    let mut events = 0;
+    RING.for_each(|_| events += 1);
+    assert_eq!(events, 0);
+}
+
+#[test_case]
+fn test_rings_registered_once() {
+    static RING: Ring = Ring::new("test_registered");
+    static UNUSED: Ring = Ring::new("test_unused");
+    _record(&RING, "event", &[]);
+    _record(&RING, "event", &[]);
+    let (mut found, mut found_unused) = (0, 0);
+    for_each_ring(|ring| {
+        if ptr::eq(ring, &RING) {
+            found += 1;
+        }
+        if ptr::eq(ring, &UNUSED) {
+            found_unused += 1;
+        }
+    });
+    assert_eq!(found, 1);
+    assert_eq!(found_unused, 0);
+}
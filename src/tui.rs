@@ -0,0 +1,135 @@
+// A few helpers for drawing simple text UIs (boxes, rules and tables) on top of a
+// `Writer`, using the line drawing characters from the VGA font. They just use
+// Unicode box drawing characters, `Writer` already turns those into CP437 glyphs.
+//
+// Like the rest of the writer API nothing is flushed, so something like
+//     let mut writer = WRITER.lock();
+//     tui::draw_box(&mut writer, Rect::new(2, 10, 40, 8), "Memory");
+//     writer.flush();
+// draws the whole box in one go.
+
+use core::fmt::Write;
+
+use crate::vga_buffer::Writer;
+
+// A rectangle on screen, in rows and columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub top: usize,
+    pub left: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(top: usize, left: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            top,
+            left,
+            width,
+            height,
+        }
+    }
+}
+
+// Draws a single line border along the edges of `rect`, with `title` in the top edge
+// (if it isn't empty). The inside of the box is left alone, and anything past the
+// edge of the screen is cut off.
+pub fn draw_box(writer: &mut Writer, rect: Rect, title: &str) {
+    // Anything smaller doesn't have room for the corners
+    if rect.width < 2 || rect.height < 2 {
+        return;
+    }
+    let (top, bottom) = (rect.top, rect.top + rect.height - 1);
+    let (left, right) = (rect.left, rect.left + rect.width - 1);
+
+    repeat_at(writer, top, left + 1, '─', rect.width - 2);
+    repeat_at(writer, bottom, left + 1, '─', rect.width - 2);
+    for row in top + 1..bottom {
+        draw_char(writer, row, left, '│');
+        draw_char(writer, row, right, '│');
+    }
+    draw_char(writer, top, left, '┌');
+    draw_char(writer, top, right, '┐');
+    draw_char(writer, bottom, left, '└');
+    draw_char(writer, bottom, right, '┘');
+
+    // `┌─ title ───┐`, as long as there is room for at least part of the title
+    if !title.is_empty() && rect.width > 6 {
+        let room = rect.width - 6;
+        let end = title.char_indices().nth(room).map_or(title.len(), |(index, _)| index);
+        draw_char(writer, top, left + 2, ' ');
+        writer.write_at(top, left + 3, &title[..end]);
+        draw_char(writer, top, left + 3 + title[..end].chars().count(), ' ');
+    }
+}
+
+// Writes a line all the way across the screen at the current position, going on to
+// the next line afterwards like `println!` does
+pub fn horizontal_rule(writer: &mut Writer) {
+    // Start on a fresh line, so the rule doesn't get split across two
+    if writer.position().1 != 0 {
+        writer.write_string("\n");
+    }
+    for _ in 0..writer.width() - 1 {
+        writer.write_char('─');
+    }
+    writer.write_string("─\n");
+}
+
+// The most columns a table can have, so the column widths fit on the stack
+const MAX_COLUMNS: usize = 8;
+
+// Prints `rows` as a table at the current position, with every column padded to its
+// widest cell and `headers` separated from the rest by a line:
+//     Region │ Start    │ Size
+//     ───────┼──────────┼─────
+//     kernel │ 0x200000 │ 64K
+// Cells past `MAX_COLUMNS` are dropped, and rows that don't fit on the screen
+// wrap like any other output.
+pub fn print_table(writer: &mut Writer, headers: &[&str], rows: &[&[&str]]) {
+    let columns = headers.len().min(MAX_COLUMNS);
+    let mut widths = [0; MAX_COLUMNS];
+    for row in core::iter::once(headers).chain(rows.iter().copied()) {
+        for (width, cell) in widths.iter_mut().zip(row.iter().take(columns)) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    print_row(writer, headers, &widths[..columns]);
+    for (column, &width) in widths[..columns].iter().enumerate() {
+        if column > 0 {
+            writer.write_string("─┼─");
+        }
+        for _ in 0..width {
+            writer.write_char('─');
+        }
+    }
+    writer.write_string("\n");
+    for row in rows {
+        print_row(writer, row, &widths[..columns]);
+    }
+}
+
+fn print_row(writer: &mut Writer, cells: &[&str], widths: &[usize]) {
+    for (column, &width) in widths.iter().enumerate() {
+        if column > 0 {
+            writer.write_string(" │ ");
+        }
+        // Missing cells are left blank
+        let cell = cells.get(column).copied().unwrap_or("");
+        let _ = write!(writer, "{:<width$}", cell, width = width);
+    }
+    writer.write_string("\n");
+}
+
+fn draw_char(writer: &mut Writer, row: usize, col: usize, c: char) {
+    let mut bytes = [0; 4];
+    writer.write_at(row, col, c.encode_utf8(&mut bytes));
+}
+
+fn repeat_at(writer: &mut Writer, row: usize, col: usize, c: char, count: usize) {
+    for offset in 0..count {
+        draw_char(writer, row, col + offset, c);
+    }
+}
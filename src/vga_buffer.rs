@@ -1,36 +1,31 @@
 use core::fmt;
+use core::ptr::{addr_of_mut, write_volatile};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use volatile::Volatile;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use x86_64::instructions::port::Port;
 
+use crate::crash;
+use crate::sync::{IrqSafeMutex, IrqSafeMutexGuard};
 
-// We use a C-like enum to specify the number for each color
-// repr(u8) ensures that each variant is stored as a u8
-// 4 bits would be sufficient, but Rust lacks a `u4` type
-// By deriving the `Copy`, `Clone`, `Debug`, `PartialEq`, and `Eq` traits
-// we enable copy semantics for the type and make it printable, and comparable
+mod ansi;
+mod cp437;
+mod mode;
+mod progress_bar;
+mod status_bar;
+mod theme;
+mod window;
+
+use ansi::{Action, CsiSequence};
+pub use mode::TextMode;
+pub use progress_bar::ProgressBar;
+pub use status_bar::{StatusBar, StatusBarPosition};
+pub(crate) use status_bar::update as update_status_bar;
+pub use theme::{PANIC_BACKGROUND, PANIC_FOREGROUND};
+pub use window::Window;
+
+pub use crate::color::Color;
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum Color {
-    Black = 0,
-    Blue = 1,
-    Green = 2,
-    Cyan = 3,
-    Red = 4,
-    Magenta = 5,
-    Brown = 6,
-    LightGray = 7,
-    DarkGray = 8,
-    LightBlue = 9,
-    LightGreen = 10,
-    LightCyan = 11,
-    LightRed = 12,
-    Pink = 13,
-    Yellow = 14,
-    White = 15,
-}
 
 // The `ColorCode` struct contains the full color byte, containing foreground
 // and background color. We use repr(transparent) to ensure it has the same layout in
@@ -39,12 +34,62 @@ pub enum Color {
 #[repr(transparent)]
 struct ColorCode(u8);
 
+// The top bit of the attribute byte either makes the character blink, or picks the
+// bright half of the background colors - which one is up to the attribute
+// controller (see `set_blinking_enabled`). We turn blinking off at boot, so by
+// default all 16 background colors work.
+const BLINK: u8 = 0x80;
+
 impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
-        ColorCode((background as u8) << 4 | (foreground as u8))
+    // With `blink` the background is limited to the dark colors, since blinking
+    // takes over the bit that would make it bright. It only actually blinks
+    // while blinking is enabled.
+    const fn new(foreground: Color, background: Color, blink: bool) -> ColorCode {
+        if blink {
+            ColorCode(BLINK | ((background as u8) & 0x07) << 4 | (foreground as u8))
+        } else {
+            ColorCode((background as u8) << 4 | (foreground as u8))
+        }
+    }
+
+    // Swaps out the low nibble while keeping the current background
+    fn with_foreground(self, foreground: Color) -> ColorCode {
+        ColorCode((self.0 & 0xf0) | (foreground as u8))
+    }
+
+    fn with_background(self, background: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (self.0 & 0x0f))
     }
 }
 
+const DEFAULT_FOREGROUND: Color = theme::FOREGROUND;
+const DEFAULT_BACKGROUND: Color = theme::BACKGROUND;
+const DEFAULT_COLOR_CODE: ColorCode = ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND, false);
+
+// ANSI numbers its colors differently from VGA (red is 1 rather than 4), so SGR
+// color codes are translated through these tables. The bright variants are
+// used for 90-97/100-107, and for 30-37 while bold is on.
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::Yellow,
+    Color::LightBlue,
+    Color::Pink,
+    Color::LightCyan,
+    Color::White,
+];
+
 // Since the field ordering in default structs is undefined in Rust
 // we use repr(C) to guarantee that the fields are layed out exactly
 // like a C struct and thus guarantees the correct field ordering. 
@@ -55,95 +100,908 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+impl ScreenChar {
+    // The cell as it sits in VGA memory, character in the low byte
+    fn to_bits(self) -> u64 {
+        self.ascii_character as u64 | (self.color_code.0 as u64) << 8
+    }
+}
+
+// All the buffers are sized for the biggest text mode we support (80x50). How much
+// of that is actually on screen depends on the current `TextMode`.
+const MAX_BUFFER_HEIGHT: usize = 50;
+const MAX_BUFFER_WIDTH: usize = 80;
+
+// How many lines that have scrolled off the top of the screen we hold on to
+const SCROLLBACK_LINES: usize = 256;
+
+// Each console has its own screen contents and scrollback, only one of them is
+// shown at a time
+pub const NUM_CONSOLES: usize = 4;
+
+const BACKSPACE: u8 = 0x08;
+
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+// What happens to a line that's too long to fit on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineMode {
+    // Carry on at the start of the next line
+    Wrap,
+    // Drop everything up to the next newline, and show a marker in the last column
+    Truncate,
+    // Carry on at the next line, but indented by this many columns
+    WrapWithIndent(usize),
+}
+
+// '»', so it's obvious the line went on
+const TRUNCATION_MARKER: u8 = 0xaf;
+
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: DEFAULT_COLOR_CODE,
+};
+
+// The hardware cursor is controlled through the CRT controller. Its registers
+// are not mapped in memory; instead we write the register index to the address
+// port, and then read/write the register's value through the data port.
+const CRTC_ADDR_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+
+// CRTC register indices used for the cursor
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0F;
+
+// Bit 5 of the cursor start register turns the cursor off
+const CURSOR_DISABLE: u8 = 0x20;
+
+// The attribute controller is the odd one out: index and data are both written to
+// the same port, and a flip-flop decides which of the two the next write is.
+// Reading the input status register resets the flip-flop to "index".
+const AC_PORT: u16 = 0x3C0;
+const AC_READ_PORT: u16 = 0x3C1;
+const INPUT_STATUS_PORT: u16 = 0x3DA;
+
+const AC_MODE_CONTROL: u8 = 0x10;
+// Bit 3 of the mode control register switches the top attribute bit to blinking
+const AC_BLINK_ENABLE: u8 = 0x08;
+// Writing an index without bit 5 set blanks the screen until it's set again
+const AC_PALETTE_ADDRESS_SOURCE: u8 = 0x20;
 
 // We use `repr(transparent)` here again to ensure that the struct
 // has the same memory layout as its singular field.
-// We use volatile here, as we never read from the `Buffer` after writing to it
+// We use volatile here, as we never read from the VGA memory after writing to it
 // the compiler knows nothing about the side effect that the characters appear on screen;
 // and therefore, it may optimize the write away - so we use volatile to tell
 // the compiler that the write has side effects, and shouldn't be optimized away
 #[repr(transparent)]
+struct TextMemory {
+    chars: [[Volatile<ScreenChar>; MAX_BUFFER_WIDTH]; MAX_BUFFER_HEIGHT],
+}
+
+// The VGA buffer, along with the text mode the hardware is in - which decides
+// how many of the rows in `memory` are actually shown
 struct Buffer {
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    memory: &'static mut TextMemory,
+    mode: TextMode,
+    // Where the bootloader mapped all of physical memory, which is how we reach
+    // the rest of the VGA registers' memory (like the font) too
+    physical_memory_offset: u64,
+    // Rows that might have something other than blanks on them. Most of the screen
+    // is usually empty (especially early on, while the log is short), and there's no
+    // point rewriting blank rows with more blanks every time the screen scrolls.
+    occupied: [bool; MAX_BUFFER_HEIGHT],
 }
 
-// The writer will always write to the last line and shift lines up when a line is full
-// (or on `\n`) - we specify a static lifetime on the reference to the VGA buffer
-// as the buffer will need to live for the entire program run time
+impl Buffer {
+    fn new(memory: &'static mut TextMemory, mode: TextMode, physical_memory_offset: u64) -> Buffer {
+        Buffer {
+            memory,
+            mode,
+            physical_memory_offset,
+            // We have no idea what's on screen yet
+            occupied: [true; MAX_BUFFER_HEIGHT],
+        }
+    }
+
+    // Shows `characters` on `row`, unless it's blank and already was
+    fn write_row(&mut self, row: usize, characters: &[ScreenChar]) {
+        let blank = characters.iter().all(|&character| character == BLANK);
+        if blank && !self.occupied[row] {
+            return;
+        }
+        self.occupied[row] = !blank;
+
+        // Every access to VGA memory takes about as long no matter how big it is,
+        // so we write four cells at a time instead of one
+        let cells = self.memory.chars[row].as_mut_ptr();
+        for (index, group) in characters.chunks(4).enumerate() {
+            if let [a, b, c, d] = group {
+                let value = a.to_bits() | b.to_bits() << 16 | c.to_bits() << 32 | d.to_bits() << 48;
+                // Rows are 160 bytes, so every group of four starts 8 byte aligned
+                unsafe { write_volatile(cells.add(index * 4) as *mut u64, value) };
+            } else {
+                // The rest of a row that isn't a multiple of four wide
+                for (offset, &character) in group.iter().enumerate() {
+                    self.memory.chars[row][index * 4 + offset].write(character);
+                }
+            }
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.mode.width()
+    }
+
+    fn height(&self) -> usize {
+        self.mode.height()
+    }
+}
+
+type Line = [ScreenChar; MAX_BUFFER_WIDTH];
+
+// A plain RAM copy of a console's screen. Every console keeps its text here,
+// whether or not it's the one currently on display.
+type Screen = [Line; MAX_BUFFER_HEIGHT];
+
+// A ring of the lines that scrolled off the top of the screen, oldest first.
+// Once it's full, each new line overwrites the oldest one.
+struct Scrollback {
+    lines: [Line; SCROLLBACK_LINES],
+    // Where the next line will be stored
+    next: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    const fn new() -> Scrollback {
+        Scrollback {
+            lines: [[BLANK; MAX_BUFFER_WIDTH]; SCROLLBACK_LINES],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, line: Line) {
+        self.lines[self.next] = line;
+        self.next = (self.next + 1) % SCROLLBACK_LINES;
+        self.len = (self.len + 1).min(SCROLLBACK_LINES);
+    }
+
+    // Takes back the most recently pushed line
+    fn pop(&mut self) -> Option<Line> {
+        if self.len == 0 {
+            return None;
+        }
+        self.next = (self.next + SCROLLBACK_LINES - 1) % SCROLLBACK_LINES;
+        self.len -= 1;
+        Some(self.lines[self.next])
+    }
+
+    // Index 0 is the oldest line we still have
+    fn line(&self, index: usize) -> &Line {
+        let oldest = (self.next + SCROLLBACK_LINES - self.len) % SCROLLBACK_LINES;
+        &self.lines[(oldest + index) % SCROLLBACK_LINES]
+    }
+}
+
+// At 40KiB per console, the scrollback is far too big to build on the stack inside
+// `lazy_static!`, so the storage lives in its own statics and each writer just holds
+// references to its share, the same way it does with the VGA buffer
+static mut SCREENS: [Screen; NUM_CONSOLES] = [[[BLANK; MAX_BUFFER_WIDTH]; MAX_BUFFER_HEIGHT]; NUM_CONSOLES];
+static mut SCROLLBACKS: [Scrollback; NUM_CONSOLES] = [const { Scrollback::new() }; NUM_CONSOLES];
+
+// The writer starts out on the last line and shifts lines up when a line is full
+// (or on `\n`) - we specify a static lifetime on the references to the buffers
+// as they will need to live for the entire program run time.
+// ANSI cursor movement can put `row_position` somewhere above the last line, in
+// which case newlines move down a row until they reach the bottom again.
+//
+// All output goes to `screen`, a plain RAM copy, and the rows that changed are
+// marked dirty. Nothing reaches VGA memory until `flush` copies the dirty rows
+// over in one go - volatile accesses are slow, so this keeps heavy logging
+// (and especially scrolling) cheap. The print macros flush for you, but anyone
+// using a `Writer` directly has to call `flush` once they're done.
+//
+// Only the active console holds the reference to the VGA buffer - switching consoles
+// moves the reference over, so there is never more than one `&mut` to VGA memory.
 pub struct Writer {
     column_position: usize,
+    row_position: usize,
     color_code: ColorCode,
-    buffer: &'static mut Buffer,
+    buffer: Option<Buffer>,
+    screen: &'static mut Screen,
+    // The size of the screen this console is laid out for, which follows the text mode
+    width: usize,
+    height: usize,
+    ansi: ansi::Parser,
+    // SGR 1 (bold) is shown as the bright variant of the foreground color
+    bold: bool,
+    scrollback: &'static mut Scrollback,
+    // How many lines back from the live screen we are currently showing
+    view_offset: usize,
+    // Remembered per console, so switching consoles can restore it
+    cursor_visible: bool,
+    // Tab stops are every `tab_width` columns
+    tab_width: usize,
+    line_mode: LineMode,
+    // Rows of the view that differ from what's in VGA memory
+    dirty: [bool; MAX_BUFFER_HEIGHT],
+    // Rows at the top and bottom of the screen that are left out of scrolling,
+    // e.g. for a status bar. Everything else is the scroll region.
+    reserved_top: usize,
+    reserved_bottom: usize,
 }
 
 // This is implemented to write from the bottom of the screen, and 
 // push written lines upward with each newline
 impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
+        self.return_to_live_view();
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
+            b'\r' => self.column_position = 0,
+            BACKSPACE => self.backspace(),
+            b'\t' => self.tab(),
+            byte => self.write_glyph(byte),
+        }
+    }
+
+    // Draws the font glyph `byte` at the current position, without treating any
+    // byte as a control character - glyphs 0x01-0x1f have pictures too
+    fn write_glyph(&mut self, byte: u8) {
+        if self.column_position >= self.width && !self.wrap() {
+            return;
+        }
+
+        let row = self.row_position;
+        let col = self.column_position;
+
+        let color_code = self.color_code;
 
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
-
-                let color_code = self.color_code;
-                
-                // We use `.write()` instead of `=` to ensure we perform a volatile write
-                // guarenteeing that the compiler wont optimize it away
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
+        self.write_cell(row, col, ScreenChar {
+            ascii_character: byte,
+            color_code,
+        });
+        self.column_position += 1;
+    }
+
+    // Writes a single character. ASCII goes through the same escape code and control
+    // character handling as `write_string`, anything else is translated to the
+    // matching code page 437 glyph, or `0xfe` if there is none.
+    pub fn write_char(&mut self, c: char) {
+        if c.is_ascii() {
+            self.write_ascii(c as u8);
+        } else {
+            self.return_to_live_view();
+            self.write_glyph(cp437::from_char(c).unwrap_or(0xfe));
+        }
+    }
+
+    fn write_ascii(&mut self, byte: u8) {
+        // Escape sequences are picked out of the stream first. The parser keeps
+        // its state between calls, since `write!` may split a sequence up.
+        match self.ansi.advance(byte) {
+            Action::None => {}
+            Action::Csi(sequence) => self.handle_csi(sequence),
+            Action::Print(byte) => match byte {
+                // printable ASCII byte, or one of the control characters we handle
+                0x20..=0x7e | b'\n' | b'\r' | b'\t' | BACKSPACE => self.write_byte(byte),
+                // any other control character
+                _ => self.write_byte(0xfe),
+            },
+        }
+    }
+
+    // Pads with blanks up to the next tab stop. A tab never wraps on its own,
+    // past the last stop it just fills up to the end of the line.
+    fn tab(&mut self) {
+        if self.column_position >= self.width && !self.wrap() {
+            return;
+        }
+        let next_stop = (self.column_position / self.tab_width + 1) * self.tab_width;
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in self.column_position..next_stop.min(self.width) {
+            self.write_cell(self.row_position, col, blank);
+        }
+        self.column_position = next_stop.min(self.width);
+    }
+
+    // Deals with running off the end of the line according to the line mode.
+    // Returns false if the rest of the line should be dropped instead.
+    fn wrap(&mut self) -> bool {
+        match self.line_mode {
+            LineMode::Wrap => {
+                self.new_line();
+                true
+            }
+            LineMode::Truncate => {
+                let marker = ScreenChar {
+                    ascii_character: TRUNCATION_MARKER,
+                    color_code: self.color_code,
+                };
+                self.write_cell(self.row_position, self.width - 1, marker);
+                false
             }
+            LineMode::WrapWithIndent(indent) => {
+                self.new_line();
+                // Always leave room for at least one character
+                self.column_position = indent.min(self.width - 1);
+                true
+            }
+        }
+    }
+
+    pub fn set_line_mode(&mut self, mode: LineMode) {
+        self.line_mode = mode;
+    }
+
+    pub fn set_tab_width(&mut self, width: usize) {
+        // A width of 0 would never reach the next stop
+        self.tab_width = width.max(1);
+    }
+
+    // Erases the previous cell and moves back onto it. Like most terminals, this
+    // stops at the start of the line rather than going back up a line.
+    fn backspace(&mut self) {
+        if self.column_position == 0 {
+            return;
         }
+        self.column_position -= 1;
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        self.write_cell(self.row_position, self.column_position, blank);
+    }
+
+    fn write_cell(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.screen[row][col] = character;
+        self.dirty[row] = true;
+    }
+
+    fn mark_all_dirty(&mut self) {
+        self.dirty = [true; MAX_BUFFER_HEIGHT];
+    }
+
+    // The first row of the scroll region
+    fn scroll_top(&self) -> usize {
+        self.reserved_top
+    }
+
+    // One past the last row of the scroll region
+    fn scroll_bottom(&self) -> usize {
+        self.height - self.reserved_bottom
     }
 
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
+        let (top, bottom) = (self.scroll_top(), self.scroll_bottom());
+        if self.row_position < bottom - 1 {
+            self.row_position += 1;
+            self.column_position = 0;
+            return;
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
+        self.scrollback.push(self.screen[top]);
+
+        // Scrolling is just a memory copy in RAM. Every row now holds different text,
+        // but since flushing is deferred, scrolling many lines in one go still only
+        // costs a single redraw.
+        self.screen.copy_within(top + 1..bottom, top);
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        self.screen[bottom - 1] = [blank; MAX_BUFFER_WIDTH];
+        self.dirty[top..bottom].fill(true);
         self.column_position = 0;
     }
 
     fn clear_row(&mut self, row: usize) {
+        self.clear_cells(row, 0, self.width);
+    }
+
+    // Blanks the columns `start..end` of `row`
+    fn clear_cells(&mut self, row: usize, start: usize, end: usize) {
         let blank = ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+        for col in start..end {
+            self.write_cell(row, col, blank);
+        }
+    }
+
+    // Blanks every row of the scroll region (in the current color) and starts writing
+    // from the beginning of the line. Reserved rows are left alone.
+    pub fn clear_screen(&mut self) {
+        self.return_to_live_view();
+        for row in self.scroll_top()..self.scroll_bottom() {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+    }
+
+    // Moves where the next character will be written. Out of range positions
+    // are clamped to the edge of the scroll region.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.row_position = row.clamp(self.scroll_top(), self.scroll_bottom() - 1);
+        self.column_position = col.min(self.width - 1);
+    }
+
+    // Takes `top` rows at the top of the screen and `bottom` rows at the bottom out
+    // of the scroll region. Output, scrolling and clearing then all stay between them,
+    // so the reserved rows are only ever changed through `write_at`.
+    pub fn set_reserved_rows(&mut self, top: usize, bottom: usize) {
+        assert!(top + bottom < self.height, "no rows left to scroll");
+        self.reserved_top = top;
+        self.reserved_bottom = bottom;
+        self.row_position = self.row_position.clamp(self.scroll_top(), self.scroll_bottom() - 1);
+        self.mark_all_dirty();
+    }
+
+    // How many rows are reserved at the top and at the bottom
+    pub fn reserved_rows(&self) -> (usize, usize) {
+        (self.reserved_top, self.reserved_bottom)
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    // The size of the screen in the current text mode
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Draws `s` at a fixed spot without moving the writer's position, so it can be
+    // used for things like counters while the log keeps scrolling as normal.
+    // Everything is drawn on the one row - text past the right edge is cut off,
+    // and nothing wraps or scrolls. Escape codes and newlines aren't interpreted.
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str) {
+        self.return_to_live_view();
+        if row >= self.height {
+            return;
         }
+        for (col, c) in (col..self.width).zip(s.chars()) {
+            let ascii_character = match c {
+                ' '..='~' => c as u8,
+                _ => cp437::from_char(c).unwrap_or(0xfe),
+            };
+            self.write_cell(row, col, ScreenChar {
+                ascii_character,
+                color_code: self.color_code,
+            });
+        }
+    }
+
+    // Like `write_at`, but with `text` centered on the row. Text wider than the
+    // screen starts at the left edge and is cut off as usual.
+    pub fn print_centered(&mut self, row: usize, text: &str) {
+        let len = text.chars().count();
+        let col = self.width.saturating_sub(len) / 2;
+        self.write_at(row, col, text);
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background, false);
+    }
+
+    // Same as `set_color`, but the text blinks (once blinking is enabled)
+    pub fn set_blinking_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background, true);
+    }
+
+    // Runs `f` with the colors temporarily switched, then puts the old ones back
+    pub fn with_color<F, R>(&mut self, foreground: Color, background: Color, f: F) -> R
+    where
+        F: FnOnce(&mut Writer) -> R,
+    {
+        let previous = self.color_code;
+        self.set_color(foreground, background);
+        let result = f(self);
+        self.color_code = previous;
+        result
+    }
+
+    // Writes `s` in a different foreground color, keeping the background
+    pub fn write_colored(&mut self, foreground: Color, s: &str) {
+        let previous = self.color_code;
+        self.color_code = previous.with_foreground(foreground);
+        self.write_string(s);
+        self.color_code = previous;
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
+        self.return_to_live_view();
+        for c in s.chars() {
+            self.write_char(c);
+        }
+    }
+
+    // Looks `lines` further back into the scrollback, stopping at the oldest line we have
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.view_offset = (self.view_offset + lines).min(self.scrollback.len);
+        self.mark_all_dirty();
+    }
+
+    // Moves the view back towards the live screen
+    pub fn scroll_down(&mut self, lines: usize) {
+        if self.view_offset == 0 {
+            return;
+        }
+        self.view_offset = self.view_offset.saturating_sub(lines);
+        self.mark_all_dirty();
+    }
+
+    // Any new output jumps back to the live screen, like a real terminal
+    fn return_to_live_view(&mut self) {
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.mark_all_dirty();
+        }
+    }
+
+    // Copies the dirty rows to VGA memory and moves the hardware cursor. Does nothing
+    // for consoles that aren't on display, they get fully redrawn when switched to.
+    //
+    // Think of the scrollback followed by the scroll region as one long list of lines;
+    // we fill the scroll region with the lines that end `view_offset` lines before the
+    // end. Reserved rows always show what's on the live screen.
+    pub fn flush(&mut self) {
+        let buffer = match self.buffer.as_mut() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let top = self.reserved_top;
+        let bottom = self.height - self.reserved_bottom;
+        let first = self.scrollback.len - self.view_offset;
+        for row in 0..self.height {
+            if !self.dirty[row] {
+                continue;
+            }
+            self.dirty[row] = false;
+            let characters = if row < top || row >= bottom {
+                &self.screen[row]
+            } else {
+                let line = first + (row - top);
+                if line < self.scrollback.len {
+                    self.scrollback.line(line)
+                } else {
+                    &self.screen[top + line - self.scrollback.len]
+                }
+            };
+            // The writes are volatile, guarenteeing that the compiler wont optimize them away
+            buffer.write_row(row, &characters[..self.width]);
+        }
+        // Moving the cursor costs four port writes, so it's also only done here
+        self.update_cursor();
+    }
+
+    // The glyph at `row`/`col`, as it is on display right now - so output only shows up
+    // here once it's been flushed. This reads VGA memory back for the console on display,
+    // and the RAM copy for any other console since that's all they have.
+    // Off screen positions read as blanks.
+    pub fn char_at(&self, row: usize, col: usize) -> u8 {
+        if row >= self.height || col >= self.width {
+            return b' ';
+        }
+        match &self.buffer {
+            Some(buffer) => buffer.memory.chars[row][col].read().ascii_character,
+            None => self.screen[row][col].ascii_character,
+        }
+    }
+
+    // A whole row of glyphs, read back the same way as `char_at`
+    pub fn row_text(&self, row: usize) -> [u8; MAX_BUFFER_WIDTH] {
+        let mut text = [b' '; MAX_BUFFER_WIDTH];
+        for (col, byte) in text.iter_mut().enumerate() {
+            *byte = self.char_at(row, col);
+        }
+        text
+    }
+
+    // Moves the blinking hardware cursor to where the next character will be written.
+    // `flush` already does this.
+    pub fn update_cursor(&mut self) {
+        // The hardware cursor belongs to whichever console is on display
+        if self.buffer.is_none() {
+            return;
+        }
+        let row = self.row_position;
+        // A full line only wraps on the next write, so keep the cursor on the last cell
+        let col = self.column_position.min(self.width - 1);
+        let position = (row * self.width + col) as u16;
+
+        write_crtc(CRTC_CURSOR_LOCATION_LOW, (position & 0xff) as u8);
+        write_crtc(CRTC_CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+    }
+
+    pub fn show_cursor(&mut self) {
+        self.cursor_visible = true;
+        self.apply_cursor_visibility();
+        self.update_cursor();
+    }
+
+    pub fn hide_cursor(&mut self) {
+        self.cursor_visible = false;
+        self.apply_cursor_visibility();
+    }
+
+    fn apply_cursor_visibility(&mut self) {
+        let mode = match &self.buffer {
+            Some(buffer) => buffer.mode,
+            None => return,
+        };
+        if self.cursor_visible {
+            // Only the low bits hold the scanline, so we preserve the reserved upper bits
+            let (first_scanline, last_scanline) = mode.cursor_scanlines();
+            let start = read_crtc(CRTC_CURSOR_START) & 0xc0;
+            write_crtc(CRTC_CURSOR_START, start | first_scanline);
+            let end = read_crtc(CRTC_CURSOR_END) & 0xe0;
+            write_crtc(CRTC_CURSOR_END, end | last_scanline);
+        } else {
+            write_crtc(CRTC_CURSOR_START, CURSOR_DISABLE);
+        }
+    }
+
+    // Lays the console out for a new screen size, keeping the most recent lines
+    // anchored to the bottom of the scroll region: shrinking pushes its top rows into
+    // the scrollback, and growing pulls lines back out of it to fill the new rows at
+    // the top. Reserved rows at the bottom move down/up with the edge of the screen.
+    fn resize(&mut self, width: usize, height: usize) {
+        let top = self.scroll_top();
+        let old_bottom = self.scroll_bottom();
+        let new_bottom = height - self.reserved_bottom;
+        if new_bottom < old_bottom {
+            let removed = old_bottom - new_bottom;
+            for row in top..top + removed {
+                self.scrollback.push(self.screen[row]);
+            }
+            self.screen.copy_within(top + removed..old_bottom, top);
+            self.screen.copy_within(old_bottom..self.height, new_bottom);
+            self.row_position = self.row_position.saturating_sub(removed);
+        } else if new_bottom > old_bottom {
+            let added = new_bottom - old_bottom;
+            // The reserved rows go first, so they're out of the way of the scroll region
+            self.screen.copy_within(old_bottom..self.height, new_bottom);
+            self.screen.copy_within(top..old_bottom, top + added);
+            for row in (top..top + added).rev() {
+                self.screen[row] = self.scrollback.pop().unwrap_or([BLANK; MAX_BUFFER_WIDTH]);
+            }
+            self.row_position += added;
+        }
+        self.width = width;
+        self.height = height;
+        self.row_position = self.row_position.clamp(top, new_bottom - 1);
+        self.column_position = self.column_position.min(width);
+        self.view_offset = 0;
+        self.mark_all_dirty();
+    }
+
+    // Supported sequences are SGR (`m`) colors, cursor movement (`A`-`D`, `G`, `H`/`f`),
+    // erase in display/line (`J`/`K`), and showing/hiding the cursor (`?25h`/`?25l`).
+    // Anything else is silently dropped.
+    fn handle_csi(&mut self, sequence: CsiSequence) {
+        if sequence.private {
+            if sequence.params() == [25] {
+                match sequence.final_byte {
+                    b'h' => self.show_cursor(),
+                    b'l' => self.hide_cursor(),
+                    _ => {}
+                }
+            }
+            return;
+        }
+
+        // A pending wrap counts as being on the last column for cursor movement
+        let col = self.column_position.min(self.width - 1);
+        let n = sequence.param_or(0, 1) as usize;
+        match sequence.final_byte {
+            b'm' => self.select_graphic_rendition(sequence.params()),
+            b'A' => self.row_position = self.row_position.saturating_sub(n).max(self.scroll_top()),
+            b'B' => self.row_position = (self.row_position + n).min(self.scroll_bottom() - 1),
+            b'C' => self.column_position = (col + n).min(self.width - 1),
+            b'D' => self.column_position = col.saturating_sub(n),
+            b'G' => self.column_position = (n - 1).min(self.width - 1),
+            // Positions are 1-based, with the row first
+            b'H' | b'f' => {
+                let row = sequence.param_or(0, 1) as usize;
+                let col = sequence.param_or(1, 1) as usize;
+                self.set_position(row - 1, col - 1);
+            }
+            b'J' => self.erase_in_display(sequence.param_or(0, 0)),
+            b'K' => self.erase_in_line(sequence.param_or(0, 0)),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        // `ESC[m` is the same as `ESC[0m`
+        if params.is_empty() {
+            self.reset_graphic_rendition();
+        }
+        for &param in params {
+            match param {
+                0 => self.reset_graphic_rendition(),
+                1 => {
+                    self.bold = true;
+                    self.color_code = ColorCode(self.color_code.0 | 0x08);
+                }
+                // Blinking (slow or fast), which only shows while it's enabled
+                5 | 6 => self.color_code = ColorCode(self.color_code.0 | BLINK),
+                22 => {
+                    self.bold = false;
+                    self.color_code = ColorCode(self.color_code.0 & !0x08);
+                }
+                25 => self.color_code = ColorCode(self.color_code.0 & !BLINK),
+                30..=37 => {
+                    let table = if self.bold { &ANSI_BRIGHT_COLORS } else { &ANSI_COLORS };
+                    let color = table[(param - 30) as usize];
+                    self.color_code = self.color_code.with_foreground(color);
+                }
+                39 => self.color_code = self.color_code.with_foreground(DEFAULT_FOREGROUND),
+                40..=47 => {
+                    let color = ANSI_COLORS[(param - 40) as usize];
+                    self.color_code = self.color_code.with_background(color);
+                }
+                49 => self.color_code = self.color_code.with_background(DEFAULT_BACKGROUND),
+                90..=97 => {
+                    let color = ANSI_BRIGHT_COLORS[(param - 90) as usize];
+                    self.color_code = self.color_code.with_foreground(color);
+                }
+                100..=107 => {
+                    let color = ANSI_BRIGHT_COLORS[(param - 100) as usize];
+                    self.color_code = self.color_code.with_background(color);
+                }
+                // Underline, italics, 256 colors, etc. have no VGA equivalent
+                _ => {}
+            }
+        }
+    }
+
+    fn reset_graphic_rendition(&mut self) {
+        self.bold = false;
+        self.color_code = DEFAULT_COLOR_CODE;
+    }
+
+    // 0 erases from the cursor to the end of the screen, 1 from the start of the
+    // screen up to and including the cursor, and 2 (or 3) the whole screen.
+    // Like a real terminal, the cursor stays where it is. The "screen" here is
+    // the scroll region, reserved rows are never erased.
+    fn erase_in_display(&mut self, mode: u16) {
+        let row = self.row_position;
+        let (top, bottom) = (self.scroll_top(), self.scroll_bottom());
+        match mode {
+            0 => {
+                self.erase_in_line(0);
+                for row in row + 1..bottom {
+                    self.clear_row(row);
+                }
             }
+            1 => {
+                for row in top..row {
+                    self.clear_row(row);
+                }
+                self.erase_in_line(1);
+            }
+            2 | 3 => {
+                for row in top..bottom {
+                    self.clear_row(row);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Same as above, but limited to the current line
+    fn erase_in_line(&mut self, mode: u16) {
+        let row = self.row_position;
+        let col = self.column_position.min(self.width - 1);
+        match mode {
+            0 => self.clear_cells(row, col, self.width),
+            1 => self.clear_cells(row, 0, col + 1),
+            2 => self.clear_row(row),
+            _ => {}
         }
     }
 }
 
+// The bootloader leaves us in the standard 80x25 mode
+const BOOT_MODE: TextMode = TextMode::Text80x25;
+
+// The physical address of the text buffer. We never use it directly, since once
+// paging gets rearranged there's no promise it is still identity mapped.
+const TEXT_BUFFER_ADDRESS: u64 = 0xb8000;
+
+// Builds the writer for console `index`. This hands out the `&'static mut`
+// references to that console's storage, so it must only be called once per index.
+fn new_console(index: usize) -> Writer {
+    Writer {
+        column_position: 0,
+        row_position: BOOT_MODE.height() - 1,
+        color_code: DEFAULT_COLOR_CODE,
+        // Console 0 is the one on display at boot, it's given the buffer by `init`
+        buffer: None,
+        screen: unsafe { &mut (*addr_of_mut!(SCREENS))[index] },
+        width: BOOT_MODE.width(),
+        height: BOOT_MODE.height(),
+        ansi: ansi::Parser::new(),
+        bold: false,
+        scrollback: unsafe { &mut (*addr_of_mut!(SCROLLBACKS))[index] },
+        view_offset: 0,
+        cursor_visible: true,
+        tab_width: DEFAULT_TAB_WIDTH,
+        line_mode: LineMode::Wrap,
+        dirty: [false; MAX_BUFFER_HEIGHT],
+        reserved_top: 0,
+        reserved_bottom: 0,
+    }
+}
+
+fn read_crtc(index: u8) -> u8 {
+    let mut addr: Port<u8> = Port::new(CRTC_ADDR_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    // Port I/O is unsafe since the compiler can't know what a port does;
+    // the CRTC ports only affect the display, so this is fine
+    unsafe {
+        addr.write(index);
+        data.read()
+    }
+}
+
+fn write_crtc(index: u8, value: u8) {
+    let mut addr: Port<u8> = Port::new(CRTC_ADDR_PORT);
+    let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+    unsafe {
+        addr.write(index);
+        data.write(value);
+    }
+}
+
+// Chooses what the top bit of the attribute byte does: with blinking enabled,
+// characters with the blink flag blink (and only dark backgrounds are left),
+// otherwise it selects the bright background colors.
+pub fn set_blinking_enabled(enabled: bool) {
+    let mut status: Port<u8> = Port::new(INPUT_STATUS_PORT);
+    let mut ac: Port<u8> = Port::new(AC_PORT);
+    let mut ac_read: Port<u8> = Port::new(AC_READ_PORT);
+    // Like the CRTC, these ports only affect the display
+    unsafe {
+        status.read();
+        ac.write(AC_MODE_CONTROL | AC_PALETTE_ADDRESS_SOURCE);
+        let mode_control = ac_read.read();
+        let mode_control = if enabled {
+            mode_control | AC_BLINK_ENABLE
+        } else {
+            mode_control & !AC_BLINK_ENABLE
+        };
+        // Reading the data doesn't advance the flip-flop, so reset it before writing
+        status.read();
+        ac.write(AC_MODE_CONTROL | AC_PALETTE_ADDRESS_SOURCE);
+        ac.write(mode_control);
+    }
+}
+
 // Allows us to use the `write!` and `writeln!` macros
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.write_string(s);
         Ok(())
     }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        Writer::write_char(self, c);
+        Ok(())
+    }
 }
 
 // We use `lazy_static` because statics in Rust are initialized at compile time
@@ -155,29 +1013,260 @@ impl fmt::Write for Writer {
 lazy_static! {
     // Since we need mutability, as all the write methods take `&mut self`
     // We use a spinlock, as it is a basic Mutex, with no required OS features
-    // that still provides us with interior mutability
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    // that still provides us with interior mutability. It keeps interrupts off while
+    // it's held, so a handler that prints can't deadlock on it (see `sync`).
+    pub static ref CONSOLES: [IrqSafeMutex<Writer>; NUM_CONSOLES] =
+        core::array::from_fn(|index| IrqSafeMutex::new(new_console(index)));
+
+    // The kernel log always goes to the first console (tty0), whichever one is on display
+    pub static ref WRITER: &'static IrqSafeMutex<Writer> = &CONSOLES[0];
+}
+
+// Which console currently owns the VGA buffer. This also serializes switching,
+// which would otherwise race on who holds the buffer.
+static ACTIVE_CONSOLE: IrqSafeMutex<usize> = IrqSafeMutex::new(0);
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+// Where `init` found the text buffer, or 0 before that. Only `write_raw` uses this,
+// everyone else goes through a writer.
+static TEXT_BUFFER: AtomicU64 = AtomicU64::new(0);
+
+// Hands the VGA buffer to the console on display, reaching it through the bootloader's
+// mapping of physical memory at `physical_memory_offset`. Until this is called,
+// output is only kept in RAM - it all shows up at once when the buffer arrives.
+pub fn init(physical_memory_offset: u64) {
+    // A second call would make a second `&mut` to VGA memory
+    assert!(!INITIALIZED.swap(true, Ordering::SeqCst), "vga_buffer::init called twice");
+
+    let active = ACTIVE_CONSOLE.lock();
+    let mut writer = CONSOLES[*active].lock();
+    let address = physical_memory_offset + TEXT_BUFFER_ADDRESS;
+    TEXT_BUFFER.store(address, Ordering::SeqCst);
+    // The bootloader maps all of physical memory at the offset it gave us
+    let memory = unsafe { &mut *(address as *mut TextMemory) };
+    writer.buffer = Some(Buffer::new(memory, BOOT_MODE, physical_memory_offset));
+    writer.mark_all_dirty();
+    writer.apply_cursor_visibility();
+    writer.flush();
+
+    // Bright backgrounds are a lot more useful than blinking text
+    set_blinking_enabled(false);
+}
+
+// Writes `text` straight into the top row of the text buffer, in white on red,
+// without taking any locks or touching any writer. This is the last resort for when
+// the panic handler itself crashes, and does nothing until `init` has been called.
+//
+// Unsafe because it scribbles over whatever owns the buffer, which is fine only
+// because nobody is ever going to draw anything again.
+pub unsafe fn write_raw(text: &[u8]) {
+    let address = TEXT_BUFFER.load(Ordering::SeqCst);
+    if address == 0 {
+        return;
+    }
+    let cells = address as *mut u16;
+    let attribute = (ColorCode::new(Color::White, Color::Red, false).0 as u16) << 8;
+    for col in 0..MAX_BUFFER_WIDTH {
+        let byte = text.get(col).copied().unwrap_or(b' ');
+        cells.add(col).write_volatile(attribute | byte as u16);
+    }
+}
+
+// The console on display, locked no matter who's holding it or the buffer. This is
+// for the panic handler, and unsafe for the same reasons as `crash::force_lock`.
+pub unsafe fn force_lock_active_console() -> IrqSafeMutexGuard<'static, Writer> {
+    let active = *crash::force_lock(&ACTIVE_CONSOLE);
+    crash::force_lock(&CONSOLES[active])
+}
+
+pub fn console(index: usize) -> &'static IrqSafeMutex<Writer> {
+    &CONSOLES[index]
 }
 
-// Here we just yeet the std implementation and replace with our own print function
+pub fn active_console() -> usize {
+    *ACTIVE_CONSOLE.lock()
+}
+
+// Puts console `index` on display, redrawing its contents into the VGA buffer.
+// This locks both the old and the new console, so it must not be called while
+// holding a lock on either of them.
+pub fn switch_console(index: usize) {
+    assert!(index < NUM_CONSOLES, "there is no console {}", index);
+    let mut active = ACTIVE_CONSOLE.lock();
+    if *active == index {
+        return;
+    }
+
+    // Consoles are always locked lowest index first, so two switches can't
+    // end up waiting on each other
+    let (mut from, mut to) = if *active < index {
+        let from = CONSOLES[*active].lock();
+        (from, CONSOLES[index].lock())
+    } else {
+        let to = CONSOLES[index].lock();
+        (CONSOLES[*active].lock(), to)
+    };
+    to.buffer = from.buffer.take();
+    to.mark_all_dirty();
+    to.apply_cursor_visibility();
+    to.flush();
+    *active = index;
+}
+
+// Switches the hardware to `mode` and lays every console out for the new size.
+// Like `switch_console`, this locks the consoles, so don't call it while holding one.
+pub fn set_text_mode(mode: TextMode) {
+    let active = ACTIVE_CONSOLE.lock();
+    let mut consoles: [_; NUM_CONSOLES] = core::array::from_fn(|index| CONSOLES[index].lock());
+
+    let buffer = consoles[*active]
+        .buffer
+        .as_mut()
+        .expect("vga_buffer::init hasn't been called yet");
+    if buffer.mode == mode {
+        return;
+    }
+    mode::program(mode, buffer.physical_memory_offset);
+    buffer.mode = mode;
+    // Rows that just came into view could hold anything
+    buffer.occupied = [true; MAX_BUFFER_HEIGHT];
+    let (width, height) = (buffer.width(), buffer.height());
+
+    for console in consoles.iter_mut() {
+        console.resize(width, height);
+    }
+    let console = &mut consoles[*active];
+    console.apply_cursor_visibility();
+    console.flush();
+}
+
+// Convenience wrappers so callers don't have to lock `WRITER` themselves
+pub fn set_color(foreground: Color, background: Color) {
+    WRITER.lock().set_color(foreground, background);
+}
+
+pub fn set_position(row: usize, col: usize) {
+    let mut writer = WRITER.lock();
+    writer.set_position(row, col);
+    writer.update_cursor();
+}
+
+pub fn write_at(row: usize, col: usize, s: &str) {
+    let mut writer = WRITER.lock();
+    writer.write_at(row, col, s);
+    writer.flush();
+}
+
+// Note that the lock is held while `f` runs, so `f` must write through the
+// `&mut Writer` it is given rather than calling `print!` (which would deadlock)
+pub fn with_color<F, R>(foreground: Color, background: Color, f: F) -> R
+where
+    F: FnOnce(&mut Writer) -> R,
+{
+    let mut writer = WRITER.lock();
+    let result = writer.with_color(foreground, background, f);
+    writer.flush();
+    result
+}
+
+// Prints the boot banner: the OS name and version, and when it was built, each
+// line centered. Extra lines can be passed in to go below those.
+#[macro_export]
+macro_rules! banner {
+    ($($line:expr),* $(,)?) => ($crate::vga_buffer::_banner(&[
+        concat!("BoredOS v", env!("CARGO_PKG_VERSION")),
+        concat!("built ", env!("BOREDOS_BUILD_DATE")),
+        $($line),*
+    ]));
+}
+
+// Same as `print!`/`println!`, but the message is written in the given color and the
+// previous color is restored afterwards. These always go to the VGA console. Takes
+// either just a foreground color (keeping the current background) or a
+// foreground/background pair:
+//     println_color!(Color::Red, "boot failed: {}", reason);
+//     println_color!(Color::White, Color::Red, "PANIC");
+// The format string has to be a literal so the two forms can be told apart.
 #[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+macro_rules! print_color {
+    ($fg:expr, $fmt:literal $($arg:tt)*) => (
+        $crate::vga_buffer::_print_color($fg, None, format_args!($fmt $($arg)*))
+    );
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_color($fg, Some($bg), format_args!($($arg)*))
+    );
 }
 
 #[macro_export]
-macro_rules! println {
-    () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+macro_rules! println_color {
+    ($fg:expr, $fmt:literal $($arg:tt)*) => (
+        $crate::vga_buffer::_print_color($fg, None, format_args!("{}\n", format_args!($fmt $($arg)*)))
+    );
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_color($fg, Some($bg), format_args!("{}\n", format_args!($($arg)*)))
+    );
+}
+
+// The banner goes into the log like any other output, one centered line after the
+// other followed by an empty line, so it scrolls away once the log gets going
+#[doc(hidden)]
+pub fn _banner(lines: &[&str]) {
+    let mut writer = WRITER.lock();
+    if writer.column_position != 0 {
+        writer.new_line();
+    }
+    for line in lines {
+        // `new_line` only blanks the row when scrolling, so make sure nothing
+        // is left over on the rows on either side of the text
+        let row = writer.row_position;
+        writer.clear_row(row);
+        writer.print_centered(row, line);
+        writer.new_line();
+    }
+    writer.new_line();
+    writer.flush();
 }
-// end yeet
 
+// The whole message is written under a single lock, so the color change
+// can't leak into output from anyone else. Like `console::_print`, interrupts are
+// off while the lock is held.
 #[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
+pub fn _print_color(foreground: Color, background: Option<Color>, args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mut writer = WRITER.lock();
+    let previous = writer.color_code;
+    writer.color_code = match background {
+        Some(background) => ColorCode::new(foreground, background, false),
+        None => previous.with_foreground(foreground),
+    };
+    writer.write_fmt(args).unwrap();
+    writer.color_code = previous;
+    writer.flush();
+}
+
+#[test_case]
+fn test_println_simple() {
+    crate::println!("test_println_simple output");
+}
+
+#[test_case]
+fn test_println_many() {
+    // Enough to scroll the whole screen a few times over
+    for _ in 0..200 {
+        crate::println!("test_println_many output");
+    }
+}
+
+#[test_case]
+fn test_println_output() {
     use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    let s = "Some test string that fits on a single line";
+    let mut writer = WRITER.lock();
+    writeln!(writer, "\n{}", s).expect("writeln failed");
+    writer.flush();
+    // What's on display, so it has to have made it all the way to VGA memory
+    let (row, _) = writer.position();
+    let text = writer.row_text(row - 1);
+    assert_eq!(&text[..s.len()], s.as_bytes());
 }
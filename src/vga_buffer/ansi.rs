@@ -0,0 +1,211 @@
+// A small state machine for the subset of ANSI escape codes we care about.
+// It only tokenizes the byte stream - deciding what a sequence actually does
+// to the screen is left to the `Writer`.
+//
+// Every escape sequence we handle is a "Control Sequence Introducer" (CSI)
+// sequence, which looks like `ESC [ <params> <final byte>`, e.g. `\x1b[1;31m`.
+// The params are decimal numbers separated by `;`, and the final byte
+// (anything in 0x40..=0x7e) selects the command.
+
+const ESC: u8 = 0x1b;
+
+// ESC[38;5;208m style sequences are the longest we're likely to see in practice,
+// anything past this is simply dropped
+const MAX_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+// A fully parsed CSI sequence, copied out of the parser so the writer can act on
+// it while still owning the parser
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsiSequence {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    // Set for DEC private sequences such as `ESC[?25l`
+    pub private: bool,
+    pub final_byte: u8,
+}
+
+impl CsiSequence {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    // Most commands treat a missing or zero parameter as some default (usually 1)
+    pub fn param_or(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&0) | None => default,
+            Some(&value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // Nothing to do yet, we're in the middle of a sequence
+    None,
+    // A regular byte that should be handled as if there was no escape code
+    Print(u8),
+    Csi(CsiSequence),
+}
+
+pub struct Parser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    private: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: false,
+        }
+    }
+
+    pub fn advance(&mut self, byte: u8) -> Action {
+        match self.state {
+            State::Ground => {
+                if byte == ESC {
+                    self.state = State::Escape;
+                    Action::None
+                } else {
+                    Action::Print(byte)
+                }
+            }
+            State::Escape => {
+                if byte == b'[' {
+                    self.start_csi();
+                } else {
+                    // Not a CSI sequence, we don't support any other kind so just drop it
+                    self.state = State::Ground;
+                }
+                Action::None
+            }
+            State::Csi => self.advance_csi(byte),
+        }
+    }
+
+    fn start_csi(&mut self) {
+        self.state = State::Csi;
+        self.params = [0; MAX_PARAMS];
+        self.len = 0;
+        self.private = false;
+    }
+
+    fn advance_csi(&mut self, byte: u8) -> Action {
+        match byte {
+            b'0'..=b'9' => {
+                // The first digit is what actually creates the first param
+                if self.len == 0 {
+                    self.len = 1;
+                }
+                if self.len <= MAX_PARAMS {
+                    let param = &mut self.params[self.len - 1];
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                }
+                Action::None
+            }
+            b';' => {
+                // `ESC[;5H` means an empty (default) first param, so we count it too
+                if self.len == 0 {
+                    self.len = 1;
+                }
+                self.len += 1;
+                Action::None
+            }
+            b'?' => {
+                self.private = true;
+                Action::None
+            }
+            // Intermediate bytes, none of the commands we support use them
+            0x20..=0x2f => Action::None,
+            0x40..=0x7e => {
+                self.state = State::Ground;
+                Action::Csi(CsiSequence {
+                    params: self.params,
+                    len: self.len.min(MAX_PARAMS),
+                    private: self.private,
+                    final_byte: byte,
+                })
+            }
+            // Anything else is malformed, give up on the sequence
+            _ => {
+                self.state = State::Ground;
+                Action::None
+            }
+        }
+    }
+}
+
+// Feeds `bytes` through a fresh parser and returns what the last one did
+#[cfg(test)]
+fn parse(bytes: &[u8]) -> Action {
+    let mut parser = Parser::new();
+    let mut action = Action::None;
+    for &byte in bytes {
+        action = parser.advance(byte);
+    }
+    action
+}
+
+#[cfg(test)]
+fn parse_csi(bytes: &[u8]) -> CsiSequence {
+    match parse(bytes) {
+        Action::Csi(sequence) => sequence,
+        action => panic!("expected a CSI sequence, got {:?}", action),
+    }
+}
+
+#[test_case]
+fn test_sgr_params() {
+    let sequence = parse_csi(b"\x1b[1;31m");
+    assert_eq!(sequence.params(), &[1, 31]);
+    assert!(!sequence.private);
+    assert_eq!(sequence.final_byte, b'm');
+}
+
+#[test_case]
+fn test_empty_first_param() {
+    let sequence = parse_csi(b"\x1b[;5H");
+    assert_eq!(sequence.params(), &[0, 5]);
+    // The empty one falls back to the default
+    assert_eq!(sequence.param_or(0, 1), 1);
+    assert_eq!(sequence.param_or(1, 1), 5);
+    assert_eq!(sequence.final_byte, b'H');
+}
+
+#[test_case]
+fn test_private_sequence() {
+    let sequence = parse_csi(b"\x1b[?25l");
+    assert!(sequence.private);
+    assert_eq!(sequence.params(), &[25]);
+    assert_eq!(sequence.final_byte, b'l');
+}
+
+#[test_case]
+fn test_too_many_params() {
+    let sequence = parse_csi(b"\x1b[1;2;3;4;5;6;7;8;9;10m");
+    assert_eq!(sequence.params(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(sequence.final_byte, b'm');
+}
+
+#[test_case]
+fn test_malformed_sequence() {
+    let mut parser = Parser::new();
+    for &byte in b"\x1b[1" {
+        assert_eq!(parser.advance(byte), Action::None);
+    }
+    // A control character in the middle gives up on the sequence, so what would have
+    // been its final byte is just printed
+    assert_eq!(parser.advance(0x07), Action::None);
+    assert_eq!(parser.advance(b'm'), Action::Print(b'm'));
+}
@@ -0,0 +1,95 @@
+// The VGA font is code page 437, the original IBM PC character set. Its lower half
+// matches ASCII, but the rest is box drawing, accented Latin, Greek and maths
+// symbols, none of which line up with their Unicode code points - so we look
+// each character up by hand.
+
+// Glyphs 0x80-0xff, in order
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// Glyphs 0x01-0x1f. These share their byte values with the ASCII control characters,
+// so they can only be drawn by bypassing control character handling.
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+// The house glyph that sits in place of DEL
+const HOUSE: char = '⌂';
+
+// Returns the CP437 glyph for a non-ASCII `c`, or the closest thing to it.
+// `None` means we have nothing sensible to show.
+pub fn from_char(c: char) -> Option<u8> {
+    if let Some(index) = HIGH.iter().position(|&glyph| glyph == c) {
+        return Some(0x80 + index as u8);
+    }
+    if let Some(index) = LOW.iter().position(|&glyph| glyph == c) {
+        return Some(0x01 + index as u8);
+    }
+    if c == HOUSE {
+        return Some(0x7f);
+    }
+    transliterate(c)
+}
+
+// Characters CP437 doesn't have, but that look close enough to one it does
+fn transliterate(c: char) -> Option<u8> {
+    let byte = match c {
+        // Symbols that share a glyph with something above
+        'β' => 0xe1,
+        'μ' => 0xe6,
+        // The ohm sign, which looks just like omega
+        '\u{2126}' => 0xea,
+        '∈' => 0xee,
+        '⋅' => 0xf9,
+        // Typographic punctuation
+        '‘' | '’' | '‚' | '′' => b'\'',
+        '“' | '”' | '„' | '″' => b'"',
+        '‐' | '‑' | '‒' | '–' | '—' | '−' => b'-',
+        '×' => b'x',
+        // Accented letters without their own glyph lose the accent
+        'À' | 'Á' | 'Â' | 'Ã' => b'A',
+        'ã' => b'a',
+        'È' | 'Ê' | 'Ë' => b'E',
+        'Ì' | 'Í' | 'Î' | 'Ï' => b'I',
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ø' => b'O',
+        'õ' | 'ø' => b'o',
+        'Ù' | 'Ú' | 'Û' => b'U',
+        'Ý' | 'Ÿ' => b'Y',
+        'ý' => b'y',
+        // Rounded and heavy box drawing corners/lines fall back to the light ones
+        '╭' => 0xda,
+        '╮' => 0xbf,
+        '╯' => 0xd9,
+        '╰' => 0xc0,
+        '━' => 0xc4,
+        '┃' => 0xb3,
+        _ => return None,
+    };
+    Some(byte)
+}
+
+#[test_case]
+fn test_from_char_glyphs() {
+    assert_eq!(from_char('é'), Some(0x82));
+    assert_eq!(from_char('░'), Some(0xb0));
+    assert_eq!(from_char('☺'), Some(0x01));
+    assert_eq!(from_char('⌂'), Some(0x7f));
+}
+
+#[test_case]
+fn test_from_char_transliterated() {
+    assert_eq!(from_char('—'), Some(b'-'));
+    assert_eq!(from_char('“'), Some(b'"'));
+    assert_eq!(from_char('Ã'), Some(b'A'));
+    // Nothing that looks anything like it
+    assert_eq!(from_char('€'), None);
+}
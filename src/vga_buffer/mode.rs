@@ -0,0 +1,152 @@
+// Switching between 80x25 and 80x50 comes down to the font: with 8 scanlines per
+// character instead of 16, the same 400 scanlines fit 50 rows instead of 25. The
+// CRT timings stay exactly the same, we only have to tell the CRTC how tall a
+// character is and load glyphs of that height into font memory.
+//
+// There is no BIOS to hand us an 8x8 font, so we make one by squashing the 8x16
+// font that's already loaded: each row of the 8x8 glyph is two rows of the
+// original OR'd together, which keeps thin strokes from disappearing.
+
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+use x86_64::instructions::port::Port;
+
+use super::{read_crtc, write_crtc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextMode {
+    Text80x25,
+    Text80x50,
+}
+
+impl TextMode {
+    pub fn width(self) -> usize {
+        80
+    }
+
+    pub fn height(self) -> usize {
+        match self {
+            TextMode::Text80x25 => 25,
+            TextMode::Text80x50 => 50,
+        }
+    }
+
+    // Scanlines per character
+    fn char_height(self) -> u8 {
+        match self {
+            TextMode::Text80x25 => 16,
+            TextMode::Text80x50 => 8,
+        }
+    }
+
+    // The usual underline cursor, on the last two scanlines of the cell
+    pub(super) fn cursor_scanlines(self) -> (u8, u8) {
+        let height = self.char_height();
+        (height - 2, height - 1)
+    }
+}
+
+// The sequencer and graphics controller use the same index/data port scheme as the CRTC
+const SEQ_ADDR_PORT: u16 = 0x3C4;
+const SEQ_DATA_PORT: u16 = 0x3C5;
+const GC_ADDR_PORT: u16 = 0x3CE;
+const GC_DATA_PORT: u16 = 0x3CF;
+
+const SEQ_RESET: u8 = 0x00;
+const SEQ_MAP_MASK: u8 = 0x02;
+const SEQ_MEMORY_MODE: u8 = 0x04;
+const GC_READ_MAP_SELECT: u8 = 0x04;
+const GC_GRAPHICS_MODE: u8 = 0x05;
+const GC_MISC: u8 = 0x06;
+const CRTC_MAX_SCAN_LINE: u8 = 0x09;
+
+// While font memory is mapped in (see `with_font_memory`), plane 2 shows up at this
+// physical address. Like the text buffer, we reach it through the physical memory mapping.
+const FONT_MEMORY_ADDRESS: u64 = 0xa0000;
+// Every glyph gets a 32 byte slot in font memory, however tall the font is
+const GLYPH_SLOT: usize = 32;
+const GLYPHS: usize = 256;
+
+// The original 8x16 font, saved when we squash it so we can go back to 80x25 later.
+// Only `program` touches this, and `set_text_mode` holds every console lock while
+// calling it, so there's never more than one user.
+static mut SAVED_FONT: [[u8; 16]; GLYPHS] = [[0; 16]; GLYPHS];
+
+// Reprograms the hardware for `mode`. Callers make sure we are actually changing
+// modes, since the saved font is only valid after switching away from 80x25.
+pub(super) fn program(mode: TextMode, physical_memory_offset: u64) {
+    let saved_font = unsafe { &mut *addr_of_mut!(SAVED_FONT) };
+    let font_memory = (physical_memory_offset + FONT_MEMORY_ADDRESS) as *mut u8;
+    with_font_memory(|| {
+        for (glyph, saved) in saved_font.iter_mut().enumerate() {
+            // Font memory only exists while it's mapped in, and the 8KiB we touch
+            // are well inside the 128KiB VGA window
+            let slot = unsafe { font_memory.add(glyph * GLYPH_SLOT) };
+            match mode {
+                TextMode::Text80x50 => {
+                    for (row, byte) in saved.iter_mut().enumerate() {
+                        *byte = unsafe { read_volatile(slot.add(row)) };
+                    }
+                    for row in 0..8 {
+                        let squashed = saved[row * 2] | saved[row * 2 + 1];
+                        unsafe { write_volatile(slot.add(row), squashed) };
+                    }
+                }
+                TextMode::Text80x25 => {
+                    for (row, &byte) in saved.iter().enumerate() {
+                        unsafe { write_volatile(slot.add(row), byte) };
+                    }
+                }
+            }
+        }
+    });
+
+    // The low 5 bits are the character height minus one, the top bits belong
+    // to unrelated timing settings
+    let max_scan_line = read_crtc(CRTC_MAX_SCAN_LINE) & 0xe0;
+    write_crtc(CRTC_MAX_SCAN_LINE, max_scan_line | (mode.char_height() - 1));
+}
+
+// In text mode the CPU only sees planes 0 and 1 (characters and attributes,
+// interleaved through odd/even addressing), the font lives in plane 2. To get at it
+// we temporarily switch to plain sequential access of plane 2 at 0xa0000, and
+// afterwards put back the standard text mode settings.
+//
+// This is the same register dance Linux's vgacon does to load fonts.
+fn with_font_memory<F: FnOnce()>(f: F) {
+    // Sequencer changes are made while it's held in synchronous reset
+    write_seq(SEQ_RESET, 0x01);
+    write_seq(SEQ_MAP_MASK, 0x04); // CPU writes only go to plane 2
+    write_seq(SEQ_MEMORY_MODE, 0x07); // sequential addressing
+    write_seq(SEQ_RESET, 0x03);
+    write_gc(GC_READ_MAP_SELECT, 0x02); // CPU reads come from plane 2
+    write_gc(GC_GRAPHICS_MODE, 0x00); // no odd/even addressing
+    write_gc(GC_MISC, 0x00); // map memory at 0xa0000
+
+    f();
+
+    write_seq(SEQ_RESET, 0x01);
+    write_seq(SEQ_MAP_MASK, 0x03); // planes 0 and 1 again
+    write_seq(SEQ_MEMORY_MODE, 0x03); // odd/even addressing
+    write_seq(SEQ_RESET, 0x03);
+    write_gc(GC_READ_MAP_SELECT, 0x00);
+    write_gc(GC_GRAPHICS_MODE, 0x10); // odd/even addressing
+    write_gc(GC_MISC, 0x0e); // text mode, mapped at 0xb8000
+}
+
+fn write_seq(index: u8, value: u8) {
+    write_indexed(SEQ_ADDR_PORT, SEQ_DATA_PORT, index, value);
+}
+
+fn write_gc(index: u8, value: u8) {
+    write_indexed(GC_ADDR_PORT, GC_DATA_PORT, index, value);
+}
+
+fn write_indexed(addr_port: u16, data_port: u16, index: u8, value: u8) {
+    let mut addr: Port<u8> = Port::new(addr_port);
+    let mut data: Port<u8> = Port::new(data_port);
+    // These ports only affect the display
+    unsafe {
+        addr.write(index);
+        data.write(value);
+    }
+}
@@ -0,0 +1,84 @@
+// A progress bar for the boot log, e.g. `[#####     ]  47%`. It sits on a row of its
+// own at the top of the kernel console, taken out of the scroll region, so it stays
+// put while `println!` output keeps scrolling underneath it.
+
+use super::{MAX_BUFFER_WIDTH, WRITER};
+
+// The brackets, the space and the percentage take up 7 columns
+const DECORATION_WIDTH: usize = 7;
+
+pub struct ProgressBar {
+    row: usize,
+    // How many cells the bar itself has, not counting the brackets
+    width: usize,
+    percent: usize,
+}
+
+impl ProgressBar {
+    // Reserves the next row down from the top of the screen (below a status bar or
+    // other progress bars) and draws an empty bar `width` cells wide there
+    pub fn new(width: usize) -> ProgressBar {
+        let mut writer = WRITER.lock();
+        let (top, bottom) = writer.reserved_rows();
+        writer.set_reserved_rows(top + 1, bottom);
+        let width = width.clamp(1, writer.width - DECORATION_WIDTH);
+        drop(writer);
+
+        let bar = ProgressBar {
+            row: top,
+            width,
+            percent: 0,
+        };
+        bar.draw();
+        bar
+    }
+
+    // Sets the progress to `done` out of `total` steps
+    pub fn set(&mut self, done: usize, total: usize) {
+        // Nothing to do counts as done
+        let percent = (done.min(total) * 100).checked_div(total).unwrap_or(100);
+        self.set_percent(percent);
+    }
+
+    pub fn set_percent(&mut self, percent: usize) {
+        self.percent = percent.min(100);
+        self.draw();
+    }
+
+    // Gives the row back to the scroll region. The bar stays where it is, as part of
+    // the log, and scrolls away like any other line. This only works for the bar
+    // closest to the scroll region - any other one just stays on screen.
+    pub fn finish(self) {
+        let mut writer = WRITER.lock();
+        let (top, bottom) = writer.reserved_rows();
+        if top == self.row + 1 {
+            writer.set_reserved_rows(top - 1, bottom);
+            writer.flush();
+        }
+    }
+
+    fn draw(&self) {
+        let mut line = [b' '; MAX_BUFFER_WIDTH];
+        let filled = self.width * self.percent / 100;
+        line[0] = b'[';
+        line[1..1 + filled].fill(b'#');
+        line[1 + self.width] = b']';
+        // Right aligned in 3 digits, so the bar doesn't jump around
+        let percent = &mut line[self.width + 3..self.width + DECORATION_WIDTH];
+        if self.percent == 100 {
+            percent[0] = b'1';
+        }
+        if self.percent >= 10 {
+            percent[1] = b'0' + (self.percent / 10 % 10) as u8;
+        }
+        percent[2] = b'0' + (self.percent % 10) as u8;
+        percent[3] = b'%';
+
+        let len = self.width + DECORATION_WIDTH;
+        // Only ASCII went into the line
+        let text = core::str::from_utf8(&line[..len]).unwrap_or("");
+        let mut writer = WRITER.lock();
+        writer.write_at(self.row, 0, text);
+        writer.flush();
+    }
+}
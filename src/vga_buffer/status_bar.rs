@@ -0,0 +1,156 @@
+// A status line that sits on its own reserved row, outside of the scroll region,
+// on every console. Since each console draws its own number into the bar, the bar
+// always names whichever console is on display, even right after switching.
+//
+// A bar handed over with `keep_updated` gets the uptime and heap usage put in and
+// redrawn once a second, from the timer interrupt (see `time::advance`).
+
+use core::fmt::{self, Write};
+
+use super::{Color, ColorCode, CONSOLES, MAX_BUFFER_WIDTH};
+use crate::allocator;
+use crate::config;
+use crate::sync::IrqSafeMutex;
+
+// The bar `keep_updated` was called on
+static UPDATED: IrqSafeMutex<Option<StatusBar>> = IrqSafeMutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusBarPosition {
+    Top,
+    Bottom,
+}
+
+// The bar doesn't know where any of its numbers come from - whoever owns it
+// feeds it new values and calls `draw` again
+pub struct StatusBar {
+    position: StatusBarPosition,
+    color_code: ColorCode,
+    uptime_seconds: Option<u64>,
+    // Used and total, in bytes
+    heap_usage: Option<(usize, usize)>,
+}
+
+impl StatusBar {
+    // Reserves the row on every console and draws the (still mostly empty) bar. The bar
+    // goes on the very first or last row, so it has to be created before anything
+    // else reserves rows on that side.
+    pub fn new(position: StatusBarPosition) -> StatusBar {
+        for console in CONSOLES.iter() {
+            let mut console = console.lock();
+            let (top, bottom) = console.reserved_rows();
+            match position {
+                StatusBarPosition::Top => console.set_reserved_rows(top + 1, bottom),
+                StatusBarPosition::Bottom => console.set_reserved_rows(top, bottom + 1),
+            }
+        }
+        let bar = StatusBar {
+            position,
+            // Inverted colors set it apart from the log
+            color_code: ColorCode::new(Color::Black, Color::LightGray, false),
+            uptime_seconds: None,
+            heap_usage: None,
+        };
+        bar.draw();
+        bar
+    }
+
+    pub fn set_uptime(&mut self, seconds: u64) {
+        self.uptime_seconds = Some(seconds);
+    }
+
+    pub fn set_heap_usage(&mut self, used: usize, total: usize) {
+        self.heap_usage = Some((used, total));
+    }
+
+    // Hands the bar over to `update`, replacing any it had before
+    pub fn keep_updated(self) {
+        *UPDATED.lock() = Some(self);
+    }
+
+    // Redraws the bar on every console. Each console is locked in turn, so this
+    // must not be called while holding a lock on any of them.
+    pub fn draw(&self) {
+        for (index, console) in CONSOLES.iter().enumerate() {
+            let mut line = LineBuffer::new();
+            // `LineBuffer` just cuts off whatever doesn't fit, it never fails
+            let _ = self.format(index, &mut line);
+
+            let mut console = console.lock();
+            let row = match self.position {
+                StatusBarPosition::Top => 0,
+                StatusBarPosition::Bottom => console.height - 1,
+            };
+            let previous = console.color_code;
+            console.color_code = self.color_code;
+            // Pad to the full width, so the bar's background runs edge to edge
+            let width = console.width;
+            console.write_at(row, 0, line.padded(width));
+            console.color_code = previous;
+            console.flush();
+        }
+    }
+
+    fn format(&self, console: usize, f: &mut LineBuffer) -> fmt::Result {
+        write!(f, " BoredOS | tty{}", console)?;
+        if let Some(seconds) = self.uptime_seconds {
+            let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+            write!(f, " | up {:02}:{:02}:{:02}", hours, minutes, seconds)?;
+        }
+        if let Some((used, total)) = self.heap_usage {
+            write!(f, " | heap {}K/{}K", used / 1024, total / 1024)?;
+        }
+        Ok(())
+    }
+}
+
+// Called from the timer interrupt once a second. If the bar's taken right now, it
+// just waits for the next second.
+pub fn update(uptime_seconds: u64) {
+    let Some(mut bar) = UPDATED.try_lock() else {
+        return;
+    };
+    let Some(bar) = bar.as_mut() else {
+        return;
+    };
+    bar.set_uptime(uptime_seconds);
+    bar.set_heap_usage(config::HEAP_SIZE - allocator::free(), config::HEAP_SIZE);
+    bar.draw();
+}
+
+// Formats into a fixed-size line on the stack, so drawing never needs the heap (and
+// can happen in the timer interrupt)
+struct LineBuffer {
+    bytes: [u8; MAX_BUFFER_WIDTH],
+    len: usize,
+}
+
+impl LineBuffer {
+    fn new() -> LineBuffer {
+        LineBuffer {
+            bytes: [b' '; MAX_BUFFER_WIDTH],
+            len: 0,
+        }
+    }
+
+    // The text followed by spaces, out to `width` columns
+    fn padded(&self, width: usize) -> &str {
+        let len = width.clamp(self.len, MAX_BUFFER_WIDTH);
+        // Only whole characters are ever copied in, and the padding is ASCII
+        core::str::from_utf8(&self.bytes[..len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for LineBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.len + len > self.bytes.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.bytes[self.len..self.len + len]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+// The console colors are picked at compile time, with at most one of the `theme-*`
+// Cargo features. Without any of them we get the original yellow on black.
+
+use super::Color;
+
+#[cfg(any(
+    all(feature = "theme-light", feature = "theme-amber"),
+    all(feature = "theme-light", feature = "theme-matrix"),
+    all(feature = "theme-amber", feature = "theme-matrix"),
+))]
+compile_error!("only one `theme-*` feature can be enabled at a time");
+
+#[cfg(not(any(feature = "theme-light", feature = "theme-amber", feature = "theme-matrix")))]
+mod colors {
+    use super::Color;
+    pub const FOREGROUND: Color = Color::Yellow;
+    pub const BACKGROUND: Color = Color::Black;
+    pub const PANIC_FOREGROUND: Color = Color::White;
+    pub const PANIC_BACKGROUND: Color = Color::Red;
+}
+
+// Bright backgrounds only work since we turn blinking off, see `set_blinking_enabled`
+#[cfg(feature = "theme-light")]
+mod colors {
+    use super::Color;
+    pub const FOREGROUND: Color = Color::Black;
+    pub const BACKGROUND: Color = Color::White;
+    pub const PANIC_FOREGROUND: Color = Color::White;
+    pub const PANIC_BACKGROUND: Color = Color::Red;
+}
+
+// VGA's "brown" is really a dark orange, the closest we get to an amber monitor
+#[cfg(feature = "theme-amber")]
+mod colors {
+    use super::Color;
+    pub const FOREGROUND: Color = Color::Brown;
+    pub const BACKGROUND: Color = Color::Black;
+    pub const PANIC_FOREGROUND: Color = Color::Black;
+    pub const PANIC_BACKGROUND: Color = Color::Brown;
+}
+
+#[cfg(feature = "theme-matrix")]
+mod colors {
+    use super::Color;
+    pub const FOREGROUND: Color = Color::LightGreen;
+    pub const BACKGROUND: Color = Color::Black;
+    pub const PANIC_FOREGROUND: Color = Color::Black;
+    pub const PANIC_BACKGROUND: Color = Color::LightGreen;
+}
+
+pub use colors::*;
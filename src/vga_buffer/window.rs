@@ -0,0 +1,168 @@
+// A rectangular part of a console's screen that acts like a small terminal of its
+// own: text wraps at its right edge and scrolls inside its bounds, and nothing
+// outside of the rectangle is ever touched. That's enough to put e.g. a log on the
+// left and some stats on the right, without every caller doing the coordinate math.
+//
+// Windows draw straight into the console's screen, so they share it with the
+// console's own output - if that scrolls across a window, the window's text scrolls
+// along with it. Windows are best put on reserved rows, or on a console that
+// nothing else prints to.
+
+use core::fmt;
+
+use super::{cp437, Color, ColorCode, ScreenChar, Writer, DEFAULT_COLOR_CODE};
+use crate::sync::IrqSafeMutex;
+
+pub struct Window {
+    console: &'static IrqSafeMutex<Writer>,
+    // Position and size on the console's screen
+    top: usize,
+    left: usize,
+    width: usize,
+    height: usize,
+    // Where the next character goes, relative to the window
+    row: usize,
+    col: usize,
+    color_code: ColorCode,
+}
+
+impl Window {
+    // Windows may hang off the edge of the screen (say, after switching back to
+    // 80x25), the part that doesn't fit is simply cut off.
+    pub fn new(
+        console: &'static IrqSafeMutex<Writer>,
+        top: usize,
+        left: usize,
+        width: usize,
+        height: usize,
+    ) -> Window {
+        assert!(width > 0 && height > 0, "window has no room for text");
+        Window {
+            console,
+            top,
+            left,
+            width,
+            height,
+            row: 0,
+            col: 0,
+            color_code: DEFAULT_COLOR_CODE,
+        }
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background, false);
+    }
+
+    // Moves where the next character will be written, relative to the window's
+    // top left corner. Out of range positions are clamped to its edges.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.height - 1);
+        self.col = col.min(self.width - 1);
+    }
+
+    // Blanks the whole window (in its current color) and starts writing from the top
+    pub fn clear(&mut self) {
+        let mut writer = self.console.lock();
+        for row in 0..self.height {
+            self.clear_row(&mut writer, row);
+        }
+        writer.flush();
+        self.row = 0;
+        self.col = 0;
+    }
+
+    // Writes `s` into the window. Newlines, carriage returns, tabs and backspace work
+    // like they do on a console, but escape codes aren't interpreted.
+    pub fn write_string(&mut self, s: &str) {
+        let mut writer = self.console.lock();
+        writer.return_to_live_view();
+        for c in s.chars() {
+            match c {
+                '\n' => self.new_line(&mut writer),
+                '\r' => self.col = 0,
+                '\t' => {
+                    let next_stop = (self.col / writer.tab_width + 1) * writer.tab_width;
+                    while self.col < next_stop.min(self.width) {
+                        self.write_glyph(&mut writer, b' ');
+                    }
+                }
+                '\u{8}' => self.backspace(&mut writer),
+                ' '..='~' => self.write_glyph(&mut writer, c as u8),
+                // Any other control character
+                c if c.is_ascii() => self.write_glyph(&mut writer, 0xfe),
+                c => self.write_glyph(&mut writer, cp437::from_char(c).unwrap_or(0xfe)),
+            }
+        }
+        writer.flush();
+    }
+
+    fn write_glyph(&mut self, writer: &mut Writer, byte: u8) {
+        if self.col >= self.width {
+            self.new_line(writer);
+        }
+        self.put(writer, self.row, self.col, byte);
+        self.col += 1;
+    }
+
+    // Like on a console, this stops at the left edge rather than going back up a line
+    fn backspace(&mut self, writer: &mut Writer) {
+        if self.col == 0 {
+            return;
+        }
+        self.col -= 1;
+        self.put(writer, self.row, self.col, b' ');
+    }
+
+    fn new_line(&mut self, writer: &mut Writer) {
+        self.col = 0;
+        if self.row < self.height - 1 {
+            self.row += 1;
+            return;
+        }
+        // Shift the window's rows up by one, copying only the columns inside the window
+        let (left, right) = self.visible_columns(writer);
+        for row in 0..self.height - 1 {
+            let (to, from) = (self.top + row, self.top + row + 1);
+            if from >= writer.height {
+                break;
+            }
+            for col in left..right {
+                let character = writer.screen[from][col];
+                writer.write_cell(to, col, character);
+            }
+        }
+        self.clear_row(writer, self.height - 1);
+    }
+
+    fn clear_row(&self, writer: &mut Writer, row: usize) {
+        for col in 0..self.width {
+            self.put(writer, row, col, b' ');
+        }
+    }
+
+    // Draws `byte` at a position inside the window, as long as it's on screen
+    fn put(&self, writer: &mut Writer, row: usize, col: usize, byte: u8) {
+        let (row, col) = (self.top + row, self.left + col);
+        if row >= writer.height || col >= writer.width {
+            return;
+        }
+        writer.write_cell(row, col, ScreenChar {
+            ascii_character: byte,
+            color_code: self.color_code,
+        });
+    }
+
+    // The screen columns the window covers, cut off at the edge of the screen
+    fn visible_columns(&self, writer: &Writer) -> (usize, usize) {
+        let left = self.left.min(writer.width);
+        let right = (self.left + self.width).min(writer.width);
+        (left, right)
+    }
+}
+
+impl fmt::Write for Window {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+// Catches tests that hang. The test runner gives every test a deadline before it
+// starts, and the timer interrupt (through `time::advance`) checks it on every tick.
+// A test that's still running by then - deadlocked on WRITER, say - fails the whole
+// run with `QemuExitCode::TimedOut`, since there's no getting back out of it to go on
+// with the next one.
+//
+// Nothing here works without a timer interrupt, the deadline just never comes.
+// `test-timeout` in Cargo.toml is the last resort for that, and for hangs with
+// interrupts off.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::crash;
+use crate::qemu::{self, QemuExitCode};
+use crate::serial::SERIAL1;
+use crate::time;
+
+// In microseconds of uptime, 0 when nothing is being watched
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+pub fn arm(timeout_micros: u64) {
+    // Never 0, even if the timer isn't running yet
+    let deadline = time::uptime_micros().saturating_add(timeout_micros).max(1);
+    DEADLINE.store(deadline, Ordering::SeqCst);
+}
+
+pub fn disarm() {
+    DEADLINE.store(0, Ordering::SeqCst);
+}
+
+// Called from the timer interrupt with the new uptime
+pub fn check(now: u64) {
+    let deadline = DEADLINE.load(Ordering::SeqCst);
+    if deadline == 0 || now < deadline {
+        return;
+    }
+    disarm();
+    // Whatever hung may well be holding the serial port, and it's not getting it back
+    let mut serial = unsafe { crash::force_lock(&SERIAL1) };
+    // Finishes the line `Testable::run` started
+    let _ = writeln!(serial, "[timed out]");
+    drop(serial);
+    qemu::exit_qemu(QemuExitCode::TimedOut);
+    // Without the exit device there's nowhere to go, so stay here rather than go back
+    // to the test that hung. Interrupts are off in here, so this is for good.
+    crate::hlt_loop()
+}
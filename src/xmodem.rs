@@ -0,0 +1,270 @@
+// An XMODEM receiver on the serial console, for uploading test programs into memory
+// at runtime instead of rebuilding the kernel image for every change. Any terminal
+// program with XMODEM support can send one, e.g. `sx` from lrzsz:
+//     sx payload.bin < /dev/ttyS0 > /dev/ttyS0
+//
+// Both the original 128 byte blocks and XMODEM-1K's 1024 byte blocks are accepted,
+// with CRC-16 checks (falling back to plain checksums for senders without CRC support).
+// Payloads are flat binaries only, there's no ELF loader: `receive_payload` puts one
+// in the payload area as is, and `execute` runs it from its first byte. With
+// `config::XMODEM_AT_BOOT` the kernel waits for one once it's up.
+//
+// Bytes come in through the serial interrupt (see `serial::read_byte`), and timeouts
+// go by `time::uptime_micros`, so interrupts have to be on. Nothing should print to
+// the serial console during a transfer, it would end up in the middle of the stream.
+
+use core::arch::global_asm;
+use core::ptr::addr_of_mut;
+
+use crate::config;
+use crate::serial::{self, SERIAL1};
+use crate::time;
+
+const SOH: u8 = 0x01; // start of a 128 byte block
+const STX: u8 = 0x02; // start of a 1024 byte block
+const EOT: u8 = 0x04; // end of transmission
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18; // cancel
+// Sent instead of NAK to ask for CRCs rather than checksums
+const CRC_REQUEST: u8 = b'C';
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+// How long we wait for the sender to get going, and for each byte after that
+const START_TIMEOUT_SECONDS: u64 = 3;
+const BYTE_TIMEOUT_SECONDS: u64 = 1;
+// The sender may need a while to get the next block ready
+const BLOCK_TIMEOUT_SECONDS: u64 = 10;
+// Asking for CRCs this many times without an answer means the sender only does checksums
+const CRC_TRIES: usize = 4;
+// Asking this many times without an answer means nobody is sending
+const START_TRIES: usize = 20;
+const MAX_ERRORS: usize = 10;
+
+// Where payloads go: `config::PAYLOAD_SIZE` bytes in a section of their own. Everything
+// else the kernel writes to is mapped non-executable, but the bootloader maps
+// sections the way they're marked, and this one is marked writable and executable.
+global_asm!(
+    ".pushsection .payload, \"awx\", @nobits",
+    ".balign 4096",
+    ".global boredos_payload",
+    "boredos_payload:",
+    ".skip {size}",
+    ".popsection",
+    size = const config::PAYLOAD_SIZE,
+);
+
+extern "C" {
+    static mut boredos_payload: [u8; config::PAYLOAD_SIZE];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // Nobody started sending, or the sender went quiet
+    Timeout,
+    // The sender gave up
+    Cancelled,
+    // The payload doesn't fit into the buffer
+    TooLarge,
+    // Too many corrupted blocks in a row
+    TooManyErrors,
+    // The sender skipped a block, so we can't put the payload back together
+    OutOfSequence,
+}
+
+// Receives a payload into `buffer` and returns how long it is. XMODEM sends whole
+// blocks, so the length is rounded up to the block size - senders usually pad the
+// last block with 0x1a bytes.
+pub fn receive(buffer: &mut [u8]) -> Result<usize, Error> {
+    let result = Receiver::new(buffer).run();
+    if result.is_err() {
+        // Two CANs make sure the sender stops too
+        send(CAN);
+        send(CAN);
+    }
+    result
+}
+
+// Receives a payload into the payload area, over whatever was there before, and
+// returns how long it is
+pub fn receive_payload() -> Result<usize, Error> {
+    // Nothing else touches the area, and the payload that was in it isn't running
+    // anymore - it never returns
+    let buffer = unsafe { &mut *addr_of_mut!(boredos_payload) };
+    receive(buffer)
+}
+
+// Where `receive_payload` puts the payload, and so where it starts running
+pub fn payload_start() -> *const u8 {
+    addr_of_mut!(boredos_payload) as *const u8
+}
+
+// Jumps to `entry`, typically `payload_start` after `receive_payload`.
+//
+// Unsafe since it's whatever the payload does. The memory also has to be mapped
+// executable, which outside of the payload area the kernel's own data and stack
+// aren't.
+pub unsafe fn execute(entry: *const u8) -> ! {
+    let entry: extern "C" fn() -> ! = core::mem::transmute(entry);
+    entry()
+}
+
+// One byte at a time, so the console isn't locked (with interrupts off) for long
+fn send(byte: u8) {
+    SERIAL1.lock().send(byte);
+}
+
+enum BlockError {
+    // Worth asking for the block again
+    Corrupted,
+    // Not worth carrying on with the transfer
+    Failed(Error),
+}
+
+struct Receiver<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    crc: bool,
+    // Block numbers are one byte and wrap around, the first block is 1
+    expected_block: u8,
+}
+
+impl<'a> Receiver<'a> {
+    fn new(buffer: &'a mut [u8]) -> Receiver<'a> {
+        Receiver {
+            buffer,
+            len: 0,
+            crc: true,
+            expected_block: 1,
+        }
+    }
+
+    fn run(&mut self) -> Result<usize, Error> {
+        let mut header = self.start()?;
+        let mut errors = 0;
+        loop {
+            match header {
+                SOH | STX => {
+                    let size = if header == SOH { 128 } else { 1024 };
+                    match self.block(size) {
+                        Ok(()) => {
+                            errors = 0;
+                            send(ACK);
+                        }
+                        Err(BlockError::Corrupted) => {
+                            errors += 1;
+                            if errors == MAX_ERRORS {
+                                return Err(Error::TooManyErrors);
+                            }
+                            self.purge();
+                            send(NAK);
+                        }
+                        Err(BlockError::Failed(error)) => return Err(error),
+                    }
+                }
+                EOT => {
+                    send(ACK);
+                    return Ok(self.len);
+                }
+                CAN => return Err(Error::Cancelled),
+                // Line noise between blocks
+                _ => {}
+            }
+            header = self.read_byte(BLOCK_TIMEOUT_SECONDS).ok_or(Error::Timeout)?;
+        }
+    }
+
+    // Keeps asking the sender to start until it sends the first header byte
+    fn start(&mut self) -> Result<u8, Error> {
+        for attempt in 0..START_TRIES {
+            self.crc = attempt < CRC_TRIES;
+            send(if self.crc { CRC_REQUEST } else { NAK });
+            if let Some(header) = self.read_byte(START_TIMEOUT_SECONDS) {
+                return Ok(header);
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    // Reads the rest of a block after its header
+    fn block(&mut self, size: usize) -> Result<(), BlockError> {
+        let block = self.block_byte()?;
+        let inverse = self.block_byte()?;
+
+        let mut data = [0; 1024];
+        for byte in data[..size].iter_mut() {
+            *byte = self.block_byte()?;
+        }
+        let valid = if self.crc {
+            let high = self.block_byte()?;
+            let low = self.block_byte()?;
+            crc16(&data[..size]) == u16::from_be_bytes([high, low])
+        } else {
+            let checksum = self.block_byte()?;
+            data[..size].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == checksum
+        };
+        if !valid || block != !inverse {
+            return Err(BlockError::Corrupted);
+        }
+
+        // Our ACK got lost and the sender is trying again, we already have this one
+        if block == self.expected_block.wrapping_sub(1) {
+            return Ok(());
+        }
+        if block != self.expected_block {
+            return Err(BlockError::Failed(Error::OutOfSequence));
+        }
+        let destination = self
+            .buffer
+            .get_mut(self.len..self.len + size)
+            .ok_or(BlockError::Failed(Error::TooLarge))?;
+        destination.copy_from_slice(&data[..size]);
+        self.len += size;
+        self.expected_block = self.expected_block.wrapping_add(1);
+        Ok(())
+    }
+
+    // A block that stops halfway is as good as a corrupted one, we just ask for it again
+    fn block_byte(&mut self) -> Result<u8, BlockError> {
+        self.read_byte(BYTE_TIMEOUT_SECONDS).ok_or(BlockError::Corrupted)
+    }
+
+    // Throws away whatever is left of a bad block, so we start clean on the resend
+    fn purge(&mut self) {
+        while self.read_byte(BYTE_TIMEOUT_SECONDS).is_some() {}
+    }
+
+    fn read_byte(&mut self, timeout_seconds: u64) -> Option<u8> {
+        let deadline = time::uptime_micros() + timeout_seconds * MICROS_PER_SECOND;
+        while time::uptime_micros() < deadline {
+            if let Some(byte) = serial::read_byte() {
+                return Some(byte);
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+}
+
+// CRC-16/XMODEM: polynomial 0x1021, starting from 0
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[test_case]
+fn test_payload_area_is_executable() {
+    // A payload that's just `ret`, so it can be called instead of `execute`d. If the
+    // area weren't executable, this would be a page fault.
+    unsafe {
+        addr_of_mut!(boredos_payload).cast::<u8>().write_volatile(0xc3);
+        let payload: extern "C" fn() = core::mem::transmute(payload_start());
+        payload();
+    }
+}
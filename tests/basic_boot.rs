@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(BoredOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// Straight from `main` into the tests, without `BoredOS::init`, to catch anything
+// that quietly depends on it having run
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use BoredOS::{println, serial_println};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    test_main();
+    BoredOS::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_println() {
+    println!("test_println output");
+}
+
+#[test_case]
+fn test_serial_println() {
+    serial_println!("test_serial_println output");
+}
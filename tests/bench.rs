@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(BoredOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// How long the things everything else leans on take, see `BoredOS::bench`
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use BoredOS::bench::Bench;
+use BoredOS::{dmesg, println, trace_event, trace_ring};
+
+trace_ring!(bench);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    BoredOS::earlylog::replay();
+    test_main();
+    BoredOS::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
+
+#[test_case]
+static NOTHING: Bench = Bench::new("nothing", || {});
+
+#[test_case]
+static PRINTLN: Bench = Bench::new("println", || println!("benchmark"));
+
+#[test_case]
+static DMESG_RECORD: Bench = Bench::new("dmesg_record", || {
+    dmesg::record(format_args!("benchmark {}", 42))
+});
+
+#[test_case]
+static TRACE_EVENT: Bench = Bench::new("trace_event", || trace_event!(benchmark, 1, 2));
+
+#[test_case]
+static BOX_SMALL: Bench = Bench::new("box_small", || drop(core::hint::black_box(Box::new(42u64))));
+
+#[test_case]
+static FORMAT: Bench = Bench::new("format", || drop(core::hint::black_box(format!("benchmark {}", 42))));
@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+// A fault the CPU can't even start handling: with the stack pointer somewhere
+// unmapped, the page fault from the push can't be delivered either, since that would
+// mean pushing onto the same stack. That's a double fault, which has to be reported
+// from a stack of its own.
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use BoredOS::serial_print;
+
+// Well clear of anything the bootloader maps
+const UNMAPPED: u64 = 0xdeadbeef000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    BoredOS::earlylog::replay();
+
+    serial_print!("double_fault::bad_stack...\t");
+    BoredOS::crash::expect_crash("DOUBLE FAULT");
+    unsafe {
+        asm!("mov rsp, {}", "push rax", in(reg) UNMAPPED, options(noreturn));
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
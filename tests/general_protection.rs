@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+// Loading a selector past the end of the GDT has to end on the general protection
+// fault handler's crash screen, with the selector in it
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use BoredOS::serial_print;
+
+// GDT entry 100, which there isn't one of
+const BAD_SELECTOR: u16 = 100 << 3;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    BoredOS::earlylog::replay();
+
+    serial_print!("general_protection::bad_selector...\t");
+    BoredOS::crash::expect_crash("selector GDT index 100");
+    unsafe { asm!("mov ds, {0:x}", in(reg) BAD_SELECTOR) };
+    BoredOS::expected_crash_missing()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
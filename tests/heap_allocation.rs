@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(BoredOS::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// The heap, from the outside: `alloc`'s types working on top of `BoredOS::init`
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use BoredOS::config::HEAP_SIZE;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    test_main();
+    BoredOS::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_simple_allocation() {
+    let a = Box::new(41);
+    let b = Box::new(13);
+    assert_eq!(*a + *b, 54);
+}
+
+#[test_case]
+fn test_large_vec() {
+    let n = 1000u64;
+    let vec: Vec<u64> = (0..n).collect();
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn test_collections() {
+    let mut map = BTreeMap::new();
+    for (index, word) in "the quick brown fox jumps over the lazy dog".split(' ').enumerate() {
+        map.entry(String::from(word)).or_insert_with(Vec::new).push(index);
+    }
+    assert_eq!(map["the"], [0, 6]);
+    assert_eq!(map.len(), 8);
+}
+
+#[test_case]
+fn test_memory_is_reused() {
+    // More than the whole heap all told, so this only works if it's given back
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+// The bump allocator can't do this, nothing's reused while `long_lived` is around
+#[cfg(not(feature = "bump-heap"))]
+#[test_case]
+fn test_long_lived_survive() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+    // And nothing's leaked
+    drop(long_lived);
+    assert!(BoredOS::allocator::free() > HEAP_SIZE - 4096);
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+// `ud2` is there to be an invalid opcode, and has to end on the crash screen like
+// any other exception without a special handler
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use BoredOS::serial_print;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    BoredOS::earlylog::replay();
+
+    serial_print!("invalid_opcode::ud2...\t");
+    BoredOS::crash::expect_crash("EXCEPTION: INVALID OPCODE (vector 6)");
+    unsafe { asm!("ud2") };
+    BoredOS::expected_crash_missing()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
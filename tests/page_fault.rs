@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+// Writing somewhere that isn't mapped has to end on the page fault handler's crash
+// screen, not a double fault or a reboot
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use BoredOS::serial_print;
+
+// Well clear of anything the bootloader maps
+const UNMAPPED: u64 = 0xdeadbeef000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    BoredOS::earlylog::replay();
+
+    serial_print!("page_fault::write_unmapped...\t");
+    BoredOS::crash::expect_crash("PAGE FAULT");
+    unsafe { (UNMAPPED as *mut u64).write_volatile(42) };
+    BoredOS::expected_crash_missing()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+// Panicking is the only way to pass here, so this runs its one test by hand instead
+// of through the test runner (see `harness = false` in Cargo.toml)
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use BoredOS::serial_print;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    BoredOS::earlylog::replay();
+    should_fail();
+    BoredOS::should_panic_failed()
+}
+
+fn should_fail() {
+    serial_print!("should_panic::should_fail...\t");
+    assert_eq!(0, 1);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::should_panic_handler(info)
+}
@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+// Recursing until the kernel stack runs out. The page fault on the guard page can't
+// be delivered on the same stack, so it turns into a double fault, and the double
+// fault handler has to get a stack of its own to report it.
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use volatile::Volatile;
+use BoredOS::serial_print;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    BoredOS::init(boot_info);
+    BoredOS::earlylog::replay();
+
+    serial_print!("stack_overflow::stack_overflow...\t");
+    BoredOS::crash::expect_crash("DOUBLE FAULT");
+    stack_overflow();
+    BoredOS::expected_crash_missing()
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    // Keeps the recursion from being turned into a loop
+    Volatile::new(0).read();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    BoredOS::test_panic_handler(info)
+}
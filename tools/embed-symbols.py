@@ -0,0 +1,122 @@
+#!/usr/bin/env python3
+# Puts the kernel's symbol table into its `.ksyms` section, so backtraces come out
+# with function names (see src/symbols.rs for the format). Run it on the linked
+# kernel before making the boot image:
+#     cargo build
+#     tools/embed-symbols.py target/target-spec/debug/BoredOS
+#     cargo bootimage
+# The section is overwritten in place, so nothing in the kernel moves.
+#
+# Needs `nm` (or set NM, e.g. NM=llvm-nm) to read the symbols.
+
+import os
+import re
+import struct
+import subprocess
+import sys
+
+# Has to match SECTION_SIZE in src/symbols.rs
+SECTION_SIZE = 256 * 1024
+SECTION_NAME = b".ksyms"
+MAX_NAME_LEN = 128
+
+# Legacy Rust mangling leaves a hash on the end of every path
+HASH_SUFFIX = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def section(elf, wanted):
+    # Finds (address, file offset, size) of the section called `wanted` in a 64-bit
+    # little endian ELF
+    if elf[:4] != b"\x7fELF" or elf[4] != 2 or elf[5] != 1:
+        sys.exit("not a 64-bit little endian ELF file")
+    (shoff,) = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+
+    def header(index):
+        # name, type, flags, address, offset, size
+        return struct.unpack_from("<IIQQQQ", elf, shoff + index * shentsize)
+
+    names_offset = header(shstrndx)[4]
+    for index in range(shnum):
+        name, _, _, address, offset, size = header(index)
+        start = names_offset + name
+        if elf[start:elf.index(b"\0", start)] == wanted:
+            return address, offset, size
+    sys.exit(f"no {wanted.decode()} section, is this the kernel?")
+
+
+def symbols(path, crate):
+    nm = os.environ.get("NM", "nm")
+    output = subprocess.run(
+        [nm, "--defined-only", "--numeric-sort", "--demangle", path],
+        check=True,
+        capture_output=True,
+        text=True,
+    ).stdout
+    found = {}
+    for line in output.splitlines():
+        parts = line.split(" ", 2)
+        # Functions only
+        if len(parts) != 3 or parts[1] not in "tTwW":
+            continue
+        name = HASH_SUFFIX.sub("", parts[2])
+        # Our own functions are known well enough without the crate name
+        if name.startswith(crate + "::"):
+            name = name[len(crate) + 2:]
+        found.setdefault(int(parts[0], 16), name)
+    return sorted(found.items())
+
+
+def leb128(value):
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return out
+
+
+def table(symbols, end):
+    out = bytearray(b"KSYM")
+    out += struct.pack("<IQ", len(symbols), end)
+    previous_address, previous_name = 0, b""
+    for address, name in symbols:
+        name = name.encode()[:MAX_NAME_LEN].decode(errors="ignore").encode()
+        shared = 0
+        while shared < min(len(name), len(previous_name), 255) and name[shared] == previous_name[shared]:
+            shared += 1
+        out += leb128(address - previous_address)
+        out += bytes([shared, len(name) - shared])
+        out += name[shared:]
+        previous_address, previous_name = address, name
+    return out
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit("usage: embed-symbols.py <kernel ELF>")
+    path = sys.argv[1]
+    with open(path, "rb") as file:
+        elf = bytearray(file.read())
+    _, offset, size = section(elf, SECTION_NAME)
+    if size != SECTION_SIZE:
+        sys.exit(f".ksyms is {size} bytes, expected {SECTION_SIZE}")
+
+    # The file name is the crate name, which is what our symbols start with
+    found = symbols(path, os.path.basename(path))
+    # Addresses past the last function are only in it if they're still in .text
+    text_address, _, text_size = section(elf, b".text")
+    data = table(found, text_address + text_size)
+    if len(data) > SECTION_SIZE:
+        sys.exit(f"symbol table is {len(data)} bytes, .ksyms only has room for {SECTION_SIZE}")
+    elf[offset:offset + SECTION_SIZE] = data.ljust(SECTION_SIZE, b"\0")
+    with open(path, "wb") as file:
+        file.write(elf)
+    print(f"embedded {len(found)} symbols ({len(data)} bytes) into {path}")
+
+
+if __name__ == "__main__":
+    main()